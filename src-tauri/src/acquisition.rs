@@ -0,0 +1,555 @@
+// src/acquisition.rs
+//
+// Raw traceability for measurements: every acquisition run gets a row in
+// `acquisition_sessions`, and every line received from the board is stored
+// in `acquisition_lines` with a millisecond timestamp.
+
+use chrono::Local;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use tauri::State;
+
+use crate::database::Database;
+use crate::history;
+use crate::measurement::Measurement;
+use crate::session::ReadRequest;
+use crate::types::UsbDevice;
+
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+#[derive(Serialize, Debug)]
+pub struct AcquisitionSession {
+    pub id: i64,
+    pub port: String,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub device_serial: Option<String>,
+    pub admission_no: Option<String>,
+    pub admission_id: Option<i64>,
+    pub baud_rate: u32,
+    pub protocol_profile_id: Option<i64>,
+    pub status: String,
+    pub status_message: Option<String>,
+    pub started_at: String,
+    pub ended_at: Option<String>,
+    pub line_count: i64,
+    pub test_run_id: Option<i64>,
+    /// Calibration the voltages were corrected with.
+    pub calibration_id: Option<i64>,
+    /// Firmware the board reported when the run started.
+    pub firmware_name: Option<String>,
+    pub firmware_version: Option<String>,
+    /// Control-material run; never linked to an admission.
+    pub is_qc: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AcquisitionLine {
+    pub line_no: i64,
+    pub received_at: String,
+    pub raw: String,
+    pub phase: Option<String>,
+    pub voltage: Option<f64>,
+    /// Voltage as the device reported it, before calibration
+    pub raw_voltage: Option<f64>,
+    pub cycle_index: Option<u32>,
+    pub unit: Option<String>,
+}
+
+fn now() -> String {
+    Local::now().format(TIMESTAMP_FORMAT).to_string()
+}
+
+/// Creates a new `running` session and returns its id.
+pub fn open_session(
+    conn: &Connection,
+    port: &str,
+    device: Option<&UsbDevice>,
+    request: &ReadRequest,
+    baud_rate: u32,
+    protocol_profile_id: Option<i64>,
+    calibration_id: Option<i64>,
+) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO acquisition_sessions (
+            port, vid, pid, device_serial, admission_no, baud_rate, protocol_profile_id,
+            calibration_id, firmware_name, firmware_version, is_qc, status, started_at
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, 'running', ?12)",
+        params![
+            port,
+            device.map(|d| d.vid),
+            device.map(|d| d.pid),
+            device.and_then(|d| d.serial_number.as_deref()),
+            request.admission_no,
+            baud_rate,
+            protocol_profile_id,
+            calibration_id,
+            device.and_then(|d| d.firmware_name.as_deref()),
+            device.and_then(|d| d.firmware_version.as_deref()),
+            request.qc,
+            now()
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Stores one line exactly as received, plus its parsed form if any. The
+/// measurement carries the calibrated voltage, `raw_voltage` the reported one.
+pub fn record_line(
+    conn: &Connection,
+    session_id: i64,
+    line_no: i64,
+    raw: &str,
+    measurement: Option<&Measurement>,
+    raw_voltage: Option<f64>,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO acquisition_lines (
+            session_id, line_no, received_at, raw, phase, voltage, cycle_index, unit,
+            raw_voltage
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            session_id,
+            line_no,
+            now(),
+            raw,
+            measurement.map(|m| m.phase.as_str()),
+            measurement.map(|m| m.voltage),
+            measurement.map(|m| m.cycle),
+            measurement.map(|m| m.unit.as_str()),
+            raw_voltage
+        ],
+    )?;
+    Ok(())
+}
+
+/// Marks a session as finished with a terminal status
+/// (`completed`, `timeout`, `error`, `cancelled`, ...).
+pub fn close_session(
+    conn: &Connection,
+    session_id: i64,
+    status: &str,
+    message: Option<&str>,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE acquisition_sessions
+         SET status = ?2, status_message = ?3, ended_at = ?4
+         WHERE id = ?1 AND status = 'running'",
+        params![session_id, status, message, now()],
+    )?;
+    history::record_run_end(conn, session_id)
+}
+
+/// Ids of the sessions still running, on `port` or anywhere.
+fn running_session_ids(conn: &Connection, port: Option<&str>) -> rusqlite::Result<Vec<i64>> {
+    let mut stmt = conn.prepare(
+        "SELECT id FROM acquisition_sessions
+         WHERE status = 'running' AND (?1 IS NULL OR port = ?1)",
+    )?;
+    let ids = stmt
+        .query_map(params![port], |row| row.get(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(ids)
+}
+
+/// Cancels whatever session is still running on `port`.
+pub fn cancel_running_sessions(
+    conn: &Connection,
+    port: &str,
+    reason: &str,
+) -> rusqlite::Result<()> {
+    let ids = running_session_ids(conn, Some(port))?;
+    conn.execute(
+        "UPDATE acquisition_sessions
+         SET status = 'cancelled', status_message = ?2, ended_at = ?3
+         WHERE port = ?1 AND status = 'running'",
+        params![port, reason, now()],
+    )?;
+    for id in ids {
+        history::record_run_end(conn, id)?;
+    }
+    Ok(())
+}
+
+/// Sessions left `running` by a crash or forced exit can never finish.
+pub fn mark_interrupted_sessions(conn: &Connection) -> rusqlite::Result<usize> {
+    let ids = running_session_ids(conn, None)?;
+    let marked = conn.execute(
+        "UPDATE acquisition_sessions
+         SET status = 'interrupted', status_message = 'Application exited during acquisition', ended_at = ?1
+         WHERE status = 'running'",
+        params![now()],
+    )?;
+    for id in ids {
+        history::record_run_end(conn, id)?;
+    }
+    Ok(marked)
+}
+
+/// Attaches the given sessions to a saved admission row.
+pub fn link_sessions_to_admission(
+    conn: &Connection,
+    admission_id: i64,
+    admission_no: &str,
+    session_ids: &[i64],
+) -> rusqlite::Result<usize> {
+    let mut linked = 0;
+    for id in session_ids {
+        linked += conn.execute(
+            "UPDATE acquisition_sessions SET admission_id = ?1, admission_no = ?2 WHERE id = ?3",
+            params![admission_id, admission_no, id],
+        )?;
+    }
+    Ok(linked)
+}
+
+fn map_session(row: &rusqlite::Row<'_>) -> rusqlite::Result<AcquisitionSession> {
+    Ok(AcquisitionSession {
+        id: row.get(0)?,
+        port: row.get(1)?,
+        vid: row.get(2)?,
+        pid: row.get(3)?,
+        device_serial: row.get(4)?,
+        admission_no: row.get(5)?,
+        admission_id: row.get(6)?,
+        baud_rate: row.get(7)?,
+        protocol_profile_id: row.get(8)?,
+        status: row.get(9)?,
+        status_message: row.get(10)?,
+        started_at: row.get(11)?,
+        ended_at: row.get(12)?,
+        line_count: row.get(13)?,
+        test_run_id: row.get(14)?,
+        calibration_id: row.get(15)?,
+        firmware_name: row.get(16)?,
+        firmware_version: row.get(17)?,
+        is_qc: row.get(18)?,
+    })
+}
+
+const SESSION_COLUMNS: &str = "
+    s.id, s.port, s.vid, s.pid, s.device_serial, s.admission_no, s.admission_id,
+    s.baud_rate, s.protocol_profile_id, s.status, s.status_message, s.started_at, s.ended_at,
+    (SELECT COUNT(*) FROM acquisition_lines l WHERE l.session_id = s.id), s.test_run_id,
+    s.calibration_id, s.firmware_name, s.firmware_version, s.is_qc";
+
+/* ----------------------------------------
+   TAURI COMMANDS
+----------------------------------------- */
+
+#[tauri::command]
+pub fn get_acquisition_sessions(
+    db: State<'_, Database>,
+    admission_no: Option<String>,
+) -> Result<Vec<AcquisitionSession>, String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM acquisition_sessions s
+             WHERE ?1 IS NULL OR s.admission_no = ?1
+             ORDER BY s.id DESC",
+            SESSION_COLUMNS
+        ))
+        .map_err(|e| e.to_string())?;
+
+    let rows = stmt
+        .query_map(params![admission_no], map_session)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(rows)
+}
+
+#[tauri::command]
+pub fn get_acquisition_lines(
+    db: State<'_, Database>,
+    session_id: i64,
+) -> Result<Vec<AcquisitionLine>, String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT line_no, received_at, raw, phase, voltage, raw_voltage, cycle_index, unit
+             FROM acquisition_lines
+             WHERE session_id = ?1
+             ORDER BY line_no ASC",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let rows = stmt
+        .query_map(params![session_id], |row| {
+            Ok(AcquisitionLine {
+                line_no: row.get(0)?,
+                received_at: row.get(1)?,
+                raw: row.get(2)?,
+                phase: row.get(3)?,
+                voltage: row.get(4)?,
+                raw_voltage: row.get(5)?,
+                cycle_index: row.get(6)?,
+                unit: row.get(7)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::open_test_database;
+    use crate::measurement::Phase;
+
+    fn board() -> UsbDevice {
+        UsbDevice {
+            port: "/dev/ttyUSB0".to_string(),
+            vid: 0x1A86,
+            pid: 0x7523,
+            serial_number: Some("A1B2C3".to_string()),
+            firmware_name: Some("nexus-cancer".to_string()),
+            firmware_version: Some("1.1.0".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn request() -> ReadRequest {
+        ReadRequest {
+            admission_no: Some("A100".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn off_reading(voltage: f64, cycle: u32) -> Measurement {
+        Measurement {
+            phase: Phase::Off,
+            voltage,
+            cycle,
+            unit: "V".to_string(),
+        }
+    }
+
+    fn session(conn: &Connection, id: i64) -> AcquisitionSession {
+        conn.query_row(
+            &format!(
+                "SELECT {} FROM acquisition_sessions s WHERE s.id = ?1",
+                SESSION_COLUMNS
+            ),
+            params![id],
+            map_session,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn records_every_line_of_a_run() {
+        let conn = open_test_database();
+        let id = open_session(
+            &conn,
+            "/dev/ttyUSB0",
+            Some(&board()),
+            &request(),
+            9600,
+            None,
+            None,
+        )
+        .unwrap();
+
+        record_line(&conn, id, 1, "Cycle 1", None, None).unwrap();
+        record_line(
+            &conn,
+            id,
+            2,
+            "Output Voltage (OFF): 0.4500 V",
+            Some(&off_reading(0.46, 1)),
+            Some(0.45),
+        )
+        .unwrap();
+        close_session(&conn, id, "completed", None).unwrap();
+        // A session only ends once
+        close_session(&conn, id, "error", Some("Device removed")).unwrap();
+
+        let recorded = session(&conn, id);
+        assert_eq!(recorded.status, "completed");
+        assert_eq!(recorded.status_message, None);
+        assert!(recorded.ended_at.is_some());
+        assert_eq!(recorded.line_count, 2);
+        assert_eq!(recorded.device_serial.as_deref(), Some("A1B2C3"));
+        assert_eq!(recorded.admission_no.as_deref(), Some("A100"));
+        assert_eq!(recorded.firmware_version.as_deref(), Some("1.1.0"));
+        assert_eq!(recorded.baud_rate, 9600);
+
+        type Line = (
+            String,
+            Option<String>,
+            Option<f64>,
+            Option<f64>,
+            Option<u32>,
+        );
+        let lines: Vec<Line> = conn
+            .prepare(
+                "SELECT raw, phase, voltage, raw_voltage, cycle_index
+                 FROM acquisition_lines WHERE session_id = ?1 ORDER BY line_no",
+            )
+            .unwrap()
+            .query_map(params![id], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            lines,
+            [
+                ("Cycle 1".to_string(), None, None, None, None),
+                (
+                    "Output Voltage (OFF): 0.4500 V".to_string(),
+                    Some("OFF".to_string()),
+                    Some(0.46),
+                    Some(0.45),
+                    Some(1)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn links_sessions_to_the_saved_admission() {
+        let conn = open_test_database();
+        let first = open_session(
+            &conn,
+            "/dev/ttyUSB0",
+            Some(&board()),
+            &request(),
+            9600,
+            None,
+            None,
+        )
+        .unwrap();
+        let second = open_session(
+            &conn,
+            "/dev/ttyUSB1",
+            None,
+            &ReadRequest::default(),
+            9600,
+            None,
+            None,
+        )
+        .unwrap();
+        conn.execute_batch(
+            "
+            INSERT INTO patients (id, firstname, lastname) VALUES (1, 'Amina', 'Otieno');
+            INSERT INTO encounters (patient_id, admission_no) VALUES (1, 'A200');
+            INSERT INTO admissions (id, admission_no, doctor_in_charge) VALUES (7, 'A200', 'Dr Mwangi');
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            link_sessions_to_admission(&conn, 7, "A200", &[first, second]).unwrap(),
+            2
+        );
+
+        for id in [first, second] {
+            let linked = session(&conn, id);
+            assert_eq!(linked.admission_id, Some(7));
+            assert_eq!(linked.admission_no.as_deref(), Some("A200"));
+        }
+    }
+
+    fn status(conn: &Connection, id: i64) -> (String, Option<String>) {
+        let recorded = session(conn, id);
+        (recorded.status, recorded.status_message)
+    }
+
+    fn run_events(conn: &Connection) -> Vec<(i64, String)> {
+        conn.prepare(
+            "SELECT acquisition_session_id, kind FROM device_events
+             WHERE acquisition_session_id IS NOT NULL ORDER BY acquisition_session_id",
+        )
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap()
+    }
+
+    #[test]
+    fn cancels_only_the_runs_on_the_stopped_port() {
+        let conn = open_test_database();
+        let open = |port: &str| {
+            let device = UsbDevice {
+                port: port.to_string(),
+                ..board()
+            };
+            open_session(&conn, port, Some(&device), &request(), 9600, None, None).unwrap()
+        };
+        let stopped = open("/dev/ttyUSB0");
+        let other = open("/dev/ttyUSB1");
+
+        cancel_running_sessions(&conn, "/dev/ttyUSB0", "Stopped by user").unwrap();
+
+        assert_eq!(
+            status(&conn, stopped),
+            ("cancelled".to_string(), Some("Stopped by user".to_string()))
+        );
+        assert_eq!(status(&conn, other), ("running".to_string(), None));
+        assert_eq!(run_events(&conn), [(stopped, "run".to_string())]);
+    }
+
+    #[test]
+    fn marks_runs_left_running_by_a_crash_as_interrupted() {
+        let conn = open_test_database();
+        let finished = open_session(
+            &conn,
+            "/dev/ttyUSB0",
+            Some(&board()),
+            &request(),
+            9600,
+            None,
+            None,
+        )
+        .unwrap();
+        close_session(&conn, finished, "completed", None).unwrap();
+        let running = open_session(
+            &conn,
+            "/dev/ttyUSB0",
+            Some(&board()),
+            &request(),
+            9600,
+            None,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(mark_interrupted_sessions(&conn).unwrap(), 1);
+        // Nothing is left to clean up on the next start
+        assert_eq!(mark_interrupted_sessions(&conn).unwrap(), 0);
+
+        assert_eq!(status(&conn, finished).0, "completed");
+        assert_eq!(
+            status(&conn, running),
+            (
+                "interrupted".to_string(),
+                Some("Application exited during acquisition".to_string())
+            )
+        );
+        assert!(session(&conn, running).ended_at.is_some());
+        assert_eq!(
+            run_events(&conn),
+            [
+                (finished, "run".to_string()),
+                (running, "error".to_string())
+            ]
+        );
+    }
+}
@@ -4,30 +4,60 @@ use once_cell::sync::Lazy;
 use serde_json::json;
 use serialport::{available_ports, SerialPortType};
 use std::collections::{HashMap, HashSet};
-use std::io::{BufRead, BufReader as StdBufReader};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Mutex;
+use std::sync::{mpsc, Mutex};
+use std::thread;
 use std::time::{Duration, Instant};
-use tauri::async_runtime::JoinHandle;
-use tauri::{AppHandle, Emitter};
+use tauri::{AppHandle, Emitter, Manager};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 
+use crate::acquisition;
+use crate::baud;
+use crate::calibration::{self, Calibration};
+use crate::database::{self, Database};
 use crate::errordefs::AppError;
-use serialport::SerialPort;
+use crate::firmware::{self, FirmwareStatus};
+use crate::history;
+use crate::hotplug;
+use crate::identify::{self, FirmwareIdentity, IDENTIFY_TIMEOUT};
+use crate::measurement::{Measurement, MeasurementParser, ParsedLine};
+use crate::protocol;
+use crate::qc;
+use crate::quality;
+use crate::reader::{run_read_loop, CancelToken, DeviceCommand, LineReader, ReadOutcome};
+use crate::session::{self, ReadRequest, SessionState};
+use crate::simulator;
+use crate::testrun::{self, TestRun};
+use crate::transport::{SerialTransport, Transport, READ_TIMEOUT};
 
 // === GLOBAL STATE ===
 static POLLING_ACTIVE: AtomicBool = AtomicBool::new(false);
+static HOTPLUG_ACTIVE: AtomicBool = AtomicBool::new(false);
 static PREV_DEVICES: Lazy<Mutex<Vec<UsbDevice>>> = Lazy::new(|| Mutex::new(Vec::new()));
-static ACTIVE_READERS: Lazy<Mutex<HashMap<String, JoinHandle<()>>>> =
+// Command queue of each running read loop, tagged with the session generation that owns
+// it; the loop owns the port and does the writing
+type CommandChannel = (u64, mpsc::Sender<DeviceCommand>);
+static COMMAND_CHANNELS: Lazy<Mutex<HashMap<String, CommandChannel>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 
-// User clicked "Acquire" → we remember they want a measurement
-// Removed on: success OR any error/timeout/disconnect → prevents infinite retry
-static DESIRED_READING_PORTS: Lazy<Mutex<HashSet<String>>> =
-    Lazy::new(|| Mutex::new(HashSet::new()));
+// Handshake result per connected port; `None` means the board did not identify itself.
+// Cleared when the port disappears, so a re-plugged board is probed again.
+static IDENTITIES: Lazy<Mutex<HashMap<String, Option<FirmwareIdentity>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+// Ports with a handshake in progress. Probes run without holding IDENTITIES; this
+// keeps two scans, or a scan and a reader, from opening the same port at once.
+static PROBING: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+// Connected boards get their `last_seen` refreshed this often, not on every poll
+const LAST_SEEN_REFRESH: Duration = Duration::from_secs(60);
+static LAST_SEEN_WRITTEN: Lazy<Mutex<Option<Instant>>> = Lazy::new(|| Mutex::new(None));
+// While udev delivers hotplug events, polling is only a safety net (e.g. across suspend)
+const HOTPLUG_FALLBACK_INTERVAL: Duration = Duration::from_secs(30);
 
 // === ARDUINO DETECTION ===
 fn is_arduino_board(device: &UsbDevice) -> bool {
-    if device.vid == 0x2341 || device.vid == 9025 {
+    if device.vid == 0x2341 {
         return true;
     }
     if matches!(device.vid, 0x1A86 | 0x10C4 | 0x0403 | 0x239A) {
@@ -39,7 +69,7 @@ fn is_arduino_board(device: &UsbDevice) -> bool {
 }
 
 fn get_board_name(device: &UsbDevice) -> String {
-    if device.vid == 0x2341 || device.vid == 9025 {
+    if device.vid == 0x2341 {
         return match device.pid {
             0x0042 | 0x0043 | 0x0242 | 0x0243 => "Arduino Uno".to_string(),
             0x0010 | 0x0044 => "Arduino Mega 2560".to_string(),
@@ -60,16 +90,28 @@ fn get_current_devices_blocking() -> Result<Vec<UsbDevice>, AppError> {
 
     for p in ports {
         if let SerialPortType::UsbPort(info) = p.port_type {
+            let (serial_number, identity_source) = match info.serial_number.clone() {
+                Some(serial) if !serial.trim().is_empty() => (serial, "usb"),
+                _ => identify::fallback_serial(&p.port_name),
+            };
             let mut device = UsbDevice {
                 port: p.port_name.clone(),
                 vid: info.vid,
                 pid: info.pid,
-                serial_number: info.serial_number.clone(),
+                serial_number: Some(serial_number),
                 product: info.product.clone(),
                 status: "connected".to_string(),
                 board_name: "".to_string(),
                 device_unit: None,
                 custom_name: None,
+                firmware_name: None,
+                firmware_version: None,
+                role: None,
+                last_seen: None,
+                firmware_status: None,
+                firmware_message: None,
+                identity_source: Some(identity_source.to_string()),
+                device_id: None,
             };
 
             if is_arduino_board(&device) {
@@ -80,55 +122,184 @@ fn get_current_devices_blocking() -> Result<Vec<UsbDevice>, AppError> {
         }
     }
 
+    devices.extend(simulator::simulated_usb_devices());
+
     debug!("Found {} Arduino device(s)", devices.len());
     Ok(devices)
 }
 
-fn restart_stale_readers(app: &AppHandle, devices: &[UsbDevice]) -> Result<(), AppError> {
-    let desired = DESIRED_READING_PORTS.lock()?;
-    let mut active = ACTIVE_READERS.lock()?;
+/// Runs the identify handshake against a board, using its protocol profile,
+/// at the rate remembered for it or the settings default. Scans must stay
+/// quick, so other rates are only tried by an explicit auto-baud run.
+/// `Ok(None)` means the board did not answer; an error means the port could
+/// not be opened at all (e.g. another program holds it), which says nothing
+/// about the board.
+fn identify_blocking(
+    app: &AppHandle,
+    device: &UsbDevice,
+) -> Result<Option<FirmwareIdentity>, AppError> {
+    let profile = with_db(app, |conn| protocol::profile_for_device(conn, device))
+        .flatten()
+        .unwrap_or_default();
+    let pattern = profile.identify_regex().unwrap_or_else(|e| {
+        warn!(
+            "Protocol profile '{}' has an invalid identify pattern ({}); using the built-in one",
+            profile.name, e
+        );
+        protocol::ProtocolProfile::default()
+            .identify_regex()
+            .expect("built-in identify pattern is valid")
+    });
 
-    for device in devices {
-        let port = &device.port;
+    let rate = with_db(app, |conn| baud::stored_baud_rate(conn, device))
+        .flatten()
+        .or_else(|| with_db(app, database::default_baud_rate).flatten())
+        .unwrap_or(baud::FALLBACK_BAUD_RATE);
+    let transport = open_transport(&device.port, rate, profile.dtr_pulse()).map_err(|e| {
+        warn!("Could not open {} to identify it: {}", device.port, e);
+        e
+    })?;
 
-        if desired.contains(port) && !active.contains_key(port) {
-            info!("Auto-restarting reader for {} (user requested)", port);
+    let mut reader = LineReader::new(transport);
+    let Some(id) = identify::probe(
+        &mut reader,
+        &profile.identify_command,
+        IDENTIFY_TIMEOUT,
+        |line| identify::parse_identity(&pattern, line),
+    ) else {
+        warn!(
+            "{} did not identify itself at {} baud; marking it as unknown",
+            device.port, rate
+        );
+        return Ok(None);
+    };
 
-            let app_clone = app.clone();
-            let port_clone = port.clone();
+    info!(
+        "{} identified as {} {} at {} baud (role: {})",
+        device.port,
+        id.name,
+        id.version,
+        rate,
+        id.role.as_deref().unwrap_or("none")
+    );
+    let mut identified = device.clone();
+    apply_identity(&mut identified, Some(&id));
+    with_db(app, |conn| {
+        identify::record_identity(conn, &identified, &id)
+    });
+    Ok(Some(id))
+}
 
-            let handle = tauri::async_runtime::spawn(async move {
-                let _ = manual_read_loop(app_clone.clone(), port_clone.clone(), 9600).await;
-                let _ = ACTIVE_READERS.lock().map(|mut m| m.remove(&port_clone));
-                let _ = app_clone.emit("arduino-reading-stopped", json!({ "port": port_clone }));
-            });
+/// Claims `port` for a handshake; `false` if one is already in progress.
+fn claim_probe(port: &str) -> Result<bool, AppError> {
+    Ok(PROBING.lock()?.insert(port.to_string()))
+}
 
-            active.insert(port.clone(), handle);
-            let _ = app.emit("arduino-reading-started", json!({ "port": port }))?;
-        }
+fn release_probe(port: &str) {
+    if let Ok(mut probing) = PROBING.lock() {
+        probing.remove(port);
+    }
+}
+
+/// Refuses to open a port while it is being identified.
+fn ensure_not_probing(port: &str) -> Result<(), AppError> {
+    if PROBING.lock()?.contains(port) {
+        return Err(AppError::Resource(format!(
+            "{} is still being identified; try again in a moment",
+            port
+        )));
     }
     Ok(())
 }
 
-fn scan_arduino_and_emit_core(
-    app: &AppHandle,
-    current: Vec<UsbDevice>,
-) -> Result<(bool, Vec<UsbDevice>), AppError> {
-    let mut prev_guard = PREV_DEVICES.lock()?;
-    let mut desired_guard = DESIRED_READING_PORTS.lock()?;
-    let mut readers = ACTIVE_READERS.lock()?;
+fn apply_identity(device: &mut UsbDevice, identity: Option<&FirmwareIdentity>) {
+    match identity {
+        Some(id) => {
+            device.firmware_name = Some(id.name.clone());
+            device.firmware_version = Some(id.version.clone());
+            device.role = id.role.clone();
+            let (status, message) = firmware::check(&id.name, &id.version);
+            device.firmware_status = Some(status.as_str().to_string());
+            device.firmware_message = message;
+            // A firmware id outlives moving the board to another USB port
+            if let Some(uid) = id
+                .uid
+                .as_ref()
+                .filter(|_| device.identity_source.as_deref() != Some("usb"))
+            {
+                device.serial_number = Some(format!("uid:{}", uid));
+                device.identity_source = Some("firmware".to_string());
+            }
+        }
+        None => device.status = "unknown".to_string(),
+    }
+}
 
-    let added: Vec<UsbDevice> = current
+/// Lists boards and runs the handshake on ports not probed yet. Ports owned
+/// by a reader are left alone: the reader holds the port open.
+fn scan_devices_blocking(app: &AppHandle) -> Result<Vec<UsbDevice>, AppError> {
+    let mut devices = get_current_devices_blocking()?;
+    let busy = session::active_ports();
+
+    let unprobed: Vec<UsbDevice> = {
+        let identities = IDENTITIES.lock()?;
+        devices
+            .iter()
+            .filter(|d| !identities.contains_key(&d.port) && !busy.contains(&d.port))
+            .cloned()
+            .collect()
+    };
+    // Probed with no lock held; each can take several seconds
+    for device in unprobed {
+        if !claim_probe(&device.port)? {
+            continue;
+        }
+        let result = identify_blocking(app, &device);
+        if let Ok(identity) = result {
+            IDENTITIES.lock()?.insert(device.port.clone(), identity);
+        }
+        release_probe(&device.port);
+    }
+
+    let identities = IDENTITIES.lock()?;
+    for device in devices.iter_mut() {
+        if let Some(identity) = identities.get(&device.port) {
+            apply_identity(device, identity.as_ref());
+        }
+    }
+    drop(identities);
+
+    // The stored role includes manual assignments, which win over the firmware's
+    for device in devices.iter_mut() {
+        if let Some(role) = with_db(app, |conn| identify::device_role(conn, device)).flatten() {
+            device.role = Some(role);
+        }
+    }
+
+    Ok(devices)
+}
+
+/// Boards on ports that appeared and disappeared between two scans.
+fn diff_devices(previous: &[UsbDevice], current: &[UsbDevice]) -> (Vec<UsbDevice>, Vec<UsbDevice>) {
+    let added = current
         .iter()
-        .filter(|d| !prev_guard.iter().any(|p| p.port == d.port))
+        .filter(|d| !previous.iter().any(|p| p.port == d.port))
         .cloned()
         .collect();
-
-    let removed: Vec<UsbDevice> = prev_guard
+    let removed = previous
         .iter()
         .filter(|p| !current.iter().any(|c| c.port == p.port))
         .cloned()
         .collect();
+    (added, removed)
+}
+
+fn scan_arduino_and_emit_core(
+    app: &AppHandle,
+    current: Vec<UsbDevice>,
+) -> Result<(bool, Vec<UsbDevice>), AppError> {
+    let mut prev_guard = PREV_DEVICES.lock()?;
+    let (added, removed) = diff_devices(&prev_guard, &current);
 
     let has_changed = !added.is_empty() || !removed.is_empty();
 
@@ -139,17 +310,22 @@ fn scan_arduino_and_emit_core(
             removed.len()
         );
 
+        for device in &added {
+            with_db(app, |conn| history::record_connected(conn, device));
+        }
+
+        let mut identities = IDENTITIES.lock()?;
         for device in &removed {
-            desired_guard.remove(&device.port);
-            if let Some(handle) = readers.remove(&device.port) {
-                handle.abort();
-                let _ = app.emit(
-                    "arduino-reading-stopped",
-                    json!({
-                        "port": &device.port,
-                        "reason": "Device removed"
-                    }),
-                );
+            identities.remove(&device.port);
+            with_db(app, |conn| history::record_disconnected(conn, device));
+            if let Some(run) =
+                session::stop(app, &device.port, SessionState::Failed, "Device removed")
+            {
+                if let Some(id) = run.acquisition_session_id {
+                    with_db(app, |conn| {
+                        acquisition::close_session(conn, id, "error", Some("Device removed"))
+                    });
+                }
             }
         }
 
@@ -159,96 +335,406 @@ fn scan_arduino_and_emit_core(
         );
     }
 
+    record_seen_devices(app, &current, &added, &removed);
+
     *prev_guard = current.clone();
     Ok((has_changed, current))
 }
 
-pub async fn manual_read_loop(
-    app: AppHandle,
-    port_name: String,
+/// Keeps the devices table in step with the scan: boards that came or went
+/// are written at once, the rest every `LAST_SEEN_REFRESH`.
+fn record_seen_devices(
+    app: &AppHandle,
+    current: &[UsbDevice],
+    added: &[UsbDevice],
+    removed: &[UsbDevice],
+) {
+    let refresh_all = match LAST_SEEN_WRITTEN.lock() {
+        Ok(mut written) => {
+            let due = written.is_none_or(|at| at.elapsed() >= LAST_SEEN_REFRESH);
+            if due {
+                *written = Some(Instant::now());
+            }
+            due
+        }
+        Err(_) => false,
+    };
+    let seen = if refresh_all { current } else { added };
+
+    let to_write: Vec<&UsbDevice> = seen
+        .iter()
+        .chain(removed)
+        .filter(|d| !simulator::is_simulated_port(&d.port))
+        .collect();
+    if to_write.is_empty() {
+        return;
+    }
+    with_db(app, |conn| {
+        to_write
+            .iter()
+            .try_for_each(|device| database::record_seen_device(conn, device))
+    });
+}
+
+/// Boards currently attached, as of the last scan.
+pub fn connected_devices() -> Vec<UsbDevice> {
+    PREV_DEVICES
+        .lock()
+        .map(|devices| devices.clone())
+        .unwrap_or_default()
+}
+
+/// Full rescan as done by the watcher: detect, diff, notify the UI.
+fn refresh_devices_blocking(app: &AppHandle) -> Result<(), AppError> {
+    let current = scan_devices_blocking(app)?;
+    let (_changed, final_devices) = scan_arduino_and_emit_core(app, current)?;
+    app.emit("arduino-scan-complete", final_devices)?;
+    Ok(())
+}
+
+/// Whether the watcher scans on this tick: always while polling is the only
+/// source of changes, otherwise only as a fallback for missed hotplug events.
+fn poll_due(hotplug_active: bool, since_last_scan: Duration) -> bool {
+    !hotplug_active || since_last_scan >= HOTPLUG_FALLBACK_INTERVAL
+}
+
+/// Rescans on udev tty events in a thread of its own, for as long as the
+/// watcher runs. Where that is not possible the watcher simply keeps polling.
+fn start_hotplug_watcher(app: AppHandle) {
+    if HOTPLUG_ACTIVE
+        .compare_exchange(false, true, Ordering::SeqCst, Ordering::Relaxed)
+        .is_err()
+    {
+        return;
+    }
+
+    std::thread::spawn(move || {
+        info!("Watching udev for serial hotplug events");
+        let result = hotplug::watch(&POLLING_ACTIVE, || {
+            debug!("Hotplug event received, rescanning");
+            if let Err(e) = refresh_devices_blocking(&app) {
+                warn!("Rescan after hotplug event failed: {}", e);
+            }
+        });
+        HOTPLUG_ACTIVE.store(false, Ordering::Relaxed);
+
+        match result {
+            Ok(()) => info!("Hotplug watcher stopped"),
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                debug!("No hotplug events on this platform; polling only")
+            }
+            Err(e) => warn!("Hotplug watcher failed ({}); falling back to polling", e),
+        }
+    });
+}
+
+/// Opens the transport for a port: a simulator for `SIM*` ports, otherwise
+/// the real serial port after pulsing DTR to reset the board.
+fn open_transport(
+    port_name: &str,
     baud_rate: u32,
-) -> Result<(), AppError> {
+    dtr_pulse: Duration,
+) -> Result<Box<dyn Transport>, AppError> {
+    let mut transport: Box<dyn Transport> = if simulator::is_simulated_port(port_name) {
+        simulator::open(port_name)?
+    } else {
+        Box::new(SerialTransport::open(port_name, baud_rate)?)
+    };
+
+    // DTR reset
+    if !dtr_pulse.is_zero() {
+        let _ = transport.reset(dtr_pulse);
+    }
+
+    Ok(transport)
+}
+
+/// Runs `f` against the managed database, logging instead of failing:
+/// losing an audit row must never abort a running measurement.
+fn with_db<T>(
+    app: &AppHandle,
+    f: impl FnOnce(&rusqlite::Connection) -> rusqlite::Result<T>,
+) -> Option<T> {
+    let db = app.try_state::<Database>()?;
+    let conn = match db.0.lock() {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("Database lock poisoned: {}", e);
+            return None;
+        }
+    };
+    match f(&conn) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            error!("Acquisition persistence failed: {}", e);
+            None
+        }
+    }
+}
+
+/// Picks the rate for a run: detected if asked for, otherwise the requested
+/// rate, the one remembered for the board or the settings default.
+fn resolve_baud_rate(
+    app: &AppHandle,
+    port_name: &str,
+    device: Option<&UsbDevice>,
+    request: &ReadRequest,
+    profile: &protocol::ProtocolProfile,
+    cancel: &CancelToken,
+) -> Option<u32> {
+    let stored = device
+        .and_then(|d| with_db(app, |conn| baud::stored_baud_rate(conn, d)))
+        .flatten();
+
+    if !request.auto_baud || simulator::is_simulated_port(port_name) {
+        let rate = request
+            .baud_rate
+            .or(stored)
+            .or_else(|| with_db(app, database::default_baud_rate).flatten())
+            .unwrap_or(baud::FALLBACK_BAUD_RATE);
+        return Some(rate);
+    }
+
+    info!("Auto-detecting baud rate on {}", port_name);
+    let rates = baud::candidates(&[stored, request.baud_rate]);
+    let detected = baud::detect(
+        |rate| {
+            if cancel.is_cancelled() {
+                return Err(AppError::Resource("Stopped".into()));
+            }
+            open_transport(port_name, rate, profile.dtr_pulse())
+        },
+        &rates,
+        profile,
+    )?;
+
+    if let Some(device) = device {
+        with_db(app, |conn| baud::record_baud_rate(conn, device, detected));
+    }
+    // The next scan asks the board again, now at the rate that works
+    if let Ok(mut identities) = IDENTITIES.lock() {
+        identities.remove(port_name);
+    }
+    Some(detected)
+}
+
+/// What a reader thread reports back to the task supervising its run.
+enum ReaderEvent {
+    /// Rate settled on; the port is about to be opened and the board reset.
+    Resolved {
+        baud_rate: u32,
+        device: Option<Box<UsbDevice>>,
+        profile_id: Option<i64>,
+    },
+    /// Board reset; protocol lines follow.
+    Opened,
+    Line {
+        raw: String,
+        parsed: ParsedLine,
+    },
+    /// The thread has let go of the port.
+    Finished(ReaderExit),
+}
+
+/// Why a reader thread let go of its port.
+enum ReaderExit {
+    NoBaudRate,
+    OpenFailed(String),
+    Read {
+        outcome: ReadOutcome,
+        no_data_timeout: Duration,
+    },
+}
+
+/// Starts a reader thread for a new run on `port`, plus the task that turns
+/// its reports into session state, acquisition rows and UI events.
+fn spawn_reader(app: &AppHandle, port: &str, request: ReadRequest) -> Result<(), AppError> {
+    if let Some(device) = attached_device(port).filter(|d| {
+        d.firmware_status
+            .as_deref()
+            .and_then(FirmwareStatus::parse)
+            .is_some_and(|status| !status.may_acquire())
+    }) {
+        return Err(AppError::Resource(device.firmware_message.unwrap_or_else(
+            || format!("The firmware on {} is not allowed to acquire", port),
+        )));
+    }
+
+    let (events_tx, events_rx) = tokio::sync::mpsc::unbounded_channel();
+
+    let generation = session::start(app, port, request.clone(), |run| {
+        let app = app.clone();
+        let port = port.to_string();
+        let request = request.clone();
+        thread::Builder::new()
+            .name(format!("reader {}", port))
+            .spawn(move || {
+                let exit = read_port(&app, &port, run, &request, &events_tx);
+                let _ = events_tx.send(ReaderEvent::Finished(exit));
+            })
+    })?;
+
+    tauri::async_runtime::spawn(supervise_run(
+        app.clone(),
+        port.to_string(),
+        generation,
+        request,
+        events_rx,
+    ));
+
+    Ok(())
+}
+
+/// Body of a reader thread: owns the port for one run. Everything here may
+/// block; the thread is dedicated to this port.
+fn read_port(
+    app: &AppHandle,
+    port_name: &str,
+    mut run: session::Run,
+    request: &ReadRequest,
+    events: &UnboundedSender<ReaderEvent>,
+) -> ReaderExit {
+    // The previous run's thread may still be closing the port
+    run.wait_for_previous();
+
+    let device = PREV_DEVICES
+        .lock()
+        .ok()
+        .and_then(|devices| devices.iter().find(|d| d.port == port_name).cloned());
+    let profile = device
+        .as_ref()
+        .and_then(|d| with_db(app, |conn| protocol::profile_for_device(conn, d)))
+        .flatten()
+        .unwrap_or_default();
+    info!("Using protocol profile '{}' on {}", profile.name, port_name);
+
+    let Some(baud_rate) = resolve_baud_rate(
+        app,
+        port_name,
+        device.as_ref(),
+        request,
+        &profile,
+        &run.cancel,
+    ) else {
+        return ReaderExit::NoBaudRate;
+    };
+
     info!(
         "Opening and resetting Arduino on {} @ {} baud",
         port_name, baud_rate
     );
+    let _ = events.send(ReaderEvent::Resolved {
+        baud_rate,
+        device: device.map(Box::new),
+        profile_id: profile.id,
+    });
 
-    let mut serial_port = match serialport::new(&port_name, baud_rate)
-        .timeout(Duration::from_millis(1000))
-        .open_native()
-    {
-        Ok(p) => p,
-        Err(e) => {
-            let msg = e.to_string();
-            error!("Failed to open {}: {}", port_name, msg);
-            let _ = app.emit(
-                "arduino-disconnected",
-                json!({
-                    "port": &port_name,
-                    "error": msg
-                }),
-            );
-            let _ = DESIRED_READING_PORTS
-                .lock()
-                .map(|mut s| s.remove(&port_name));
-            let _ = app.emit("arduino-reading-stopped", json!({ "port": &port_name }));
-            return Err(e.into());
-        }
+    let transport = match open_transport(port_name, baud_rate, profile.dtr_pulse()) {
+        Ok(transport) => transport,
+        Err(e) => return ReaderExit::OpenFailed(e.to_string()),
     };
-
-    // DTR reset
-    let _ = serial_port.write_data_terminal_ready(true);
-    std::thread::sleep(Duration::from_millis(250));
-    let _ = serial_port.write_data_terminal_ready(false);
+    let _ = events.send(ReaderEvent::Opened);
 
     info!(
         "Arduino reset complete on {}. Starting read loop...",
         port_name
     );
 
-    let mut reader = StdBufReader::new(serial_port);
-    let mut line = String::new();
-    let timeout_duration = Duration::from_secs(10);
-    let mut last_data_time = Instant::now();
-
-    macro_rules! fail_and_forget {
-        ($error_msg:expr, $toast_key:expr) => {{
-            error!("{} on {}", $error_msg, port_name);
-            let _ = app.emit("arduino-disconnected", json!({
-                "port": &port_name,
-                "error": $error_msg
-            }));
-            // Dedicated timeout event for nice toast
-            let _ = app.emit($toast_key, json!({
-                "port": &port_name,
-                "message": $error_msg
-            }));
-            let _ = DESIRED_READING_PORTS.lock().map(|mut s| s.remove(&port_name));
-            let _ = app.emit("arduino-reading-stopped", json!({ "port": &port_name }));
-            break;
-        }};
-    }
-
-    loop {
-        line.clear();
-
-        // 30-second timeout — user-friendly message
-        if last_data_time.elapsed() > timeout_duration {
-            fail_and_forget!(
-                "No data received for 10 seconds. Measurement stopped.",
-                "arduino-timeout"
-            );
+    let mut reader = LineReader::new(transport);
+    let mut parser = profile.parser().unwrap_or_else(|e| {
+        warn!(
+            "Protocol profile '{}' has invalid patterns ({}); using built-in ones",
+            profile.name, e
+        );
+        MeasurementParser::new()
+    });
+    let config = profile.read_loop_config();
+
+    let (command_tx, command_rx) = mpsc::channel();
+    let _ = COMMAND_CHANNELS
+        .lock()
+        .map(|mut c| c.insert(port_name.to_string(), (run.generation, command_tx)));
+
+    let outcome = run_read_loop(
+        &mut reader,
+        &mut parser,
+        &config,
+        Some(&command_rx),
+        Some(&run.cancel),
+        |raw, parsed| {
+            let _ = events.send(ReaderEvent::Line {
+                raw: raw.to_string(),
+                parsed: parsed.clone(),
+            });
+        },
+    );
+
+    // A restarted run may already have registered its own channel
+    let _ = COMMAND_CHANNELS.lock().map(|mut c| {
+        if c.get(port_name)
+            .is_some_and(|(owner, _)| *owner == run.generation)
+        {
+            c.remove(port_name);
         }
+    });
+
+    ReaderExit::Read {
+        outcome,
+        no_data_timeout: config.no_data_timeout,
+    }
+}
 
-        match reader.read_line(&mut line) {
-            Ok(0) => fail_and_forget!("Connection lost unexpectedly", "arduino-error"),
-            Ok(_) => {
-                let data = line.trim().to_string();
-                if data.is_empty() {
+/// Async side of a run. Reports from a run that has since been stopped or
+/// superseded are dropped, apart from closing its acquisition row.
+async fn supervise_run(
+    app: AppHandle,
+    port_name: String,
+    generation: u64,
+    request: ReadRequest,
+    mut events: UnboundedReceiver<ReaderEvent>,
+) {
+    let mut session_id: Option<i64> = None;
+    let mut calibration: Option<Calibration> = None;
+    let mut line_no: i64 = 0;
+
+    while let Some(event) = events.recv().await {
+        match event {
+            ReaderEvent::Resolved {
+                baud_rate,
+                device,
+                profile_id,
+            } => {
+                if !session::owns(&port_name, generation) {
                     continue;
                 }
-
-                last_data_time = Instant::now();
+                calibration = device
+                    .as_deref()
+                    .and_then(|d| with_db(&app, |conn| calibration::active_calibration(conn, d)))
+                    .flatten();
+                if let Some(c) = &calibration {
+                    info!("Applying calibration {} on {}", c.id, port_name);
+                }
+                session_id = with_db(&app, |conn| {
+                    acquisition::open_session(
+                        conn,
+                        &port_name,
+                        device.as_deref(),
+                        &request,
+                        baud_rate,
+                        profile_id,
+                        calibration.as_ref().map(|c| c.id),
+                    )
+                });
+                session::set_run_details(&port_name, generation, session_id, baud_rate);
+            }
+            ReaderEvent::Opened => {
+                session::transition(&app, &port_name, generation, SessionState::Acquiring, None);
+            }
+            ReaderEvent::Line { raw, parsed } => {
+                if !session::owns(&port_name, generation) {
+                    continue;
+                }
+                let data = raw.trim();
                 debug!("DATA: {}", data);
 
                 let _ = app.emit(
@@ -259,30 +745,174 @@ pub async fn manual_read_loop(
                     }),
                 );
 
-                if data.contains("cycles completed") {
-                    info!(
-                        "Measurement successfully finished on {} (3 cycles)",
-                        port_name
+                let reported = match &parsed {
+                    ParsedLine::Measurement(m) => Some(m),
+                    _ => None,
+                };
+                let measurement = reported.map(|m| match &calibration {
+                    Some(c) => Measurement {
+                        voltage: c.apply(m.voltage),
+                        ..m.clone()
+                    },
+                    None => m.clone(),
+                });
+                let raw_voltage = reported.map(|m| m.voltage);
+
+                if let Some(id) = session_id {
+                    line_no += 1;
+                    with_db(&app, |conn| {
+                        acquisition::record_line(
+                            conn,
+                            id,
+                            line_no,
+                            &raw,
+                            measurement.as_ref(),
+                            raw_voltage,
+                        )
+                    });
+                }
+
+                if let Some(measurement) = measurement {
+                    let _ = app.emit(
+                        "arduino-measurement",
+                        json!({
+                            "port": &port_name,
+                            "raw": data,
+                            "measurement": measurement,
+                            "raw_voltage": raw_voltage,
+                            "calibration_id": calibration.as_ref().map(|c| c.id)
+                        }),
                     );
-                    let _ = DESIRED_READING_PORTS
-                        .lock()
-                        .map(|mut s| s.remove(&port_name));
-                    let _ = app.emit("arduino-cycle-complete", json!({ "port": &port_name }));
-                    let _ = app.emit("arduino-reading-stopped", json!({ "port": &port_name }));
-                    break;
                 }
             }
-            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => continue,
-            Err(e) => {
-                let msg = e.to_string();
-                fail_and_forget!(msg, "arduino-error");
+            ReaderEvent::Finished(exit) => {
+                finish_run(&app, &port_name, generation, session_id, exit);
+                return;
             }
         }
+    }
 
-        std::thread::sleep(Duration::from_millis(10));
+    // The thread went away without reporting, i.e. it panicked
+    if session::transition(
+        &app,
+        &port_name,
+        generation,
+        SessionState::Failed,
+        Some("Reader stopped unexpectedly"),
+    ) {
+        if let Some(id) = session_id {
+            with_db(&app, |conn| {
+                acquisition::close_session(conn, id, "error", Some("Reader stopped unexpectedly"))
+            });
+        }
+        let still_attached = PREV_DEVICES
+            .lock()
+            .is_ok_and(|devices| devices.iter().any(|d| d.port == port_name));
+        if still_attached {
+            info!("Auto-restarting reader for {} (user requested)", port_name);
+            let _ = spawn_reader(&app, &port_name, request);
+        }
     }
+}
 
-    Ok(())
+/// Settles the session and the acquisition row once the reader is done, and
+/// emits the events the UI has always listened to.
+fn finish_run(
+    app: &AppHandle,
+    port_name: &str,
+    generation: u64,
+    session_id: Option<i64>,
+    exit: ReaderExit,
+) {
+    // Only the run that still owns the port may report how it ended. A run
+    // stopped from outside has been accounted for, bar an acquisition row it
+    // opened after the stop.
+    let end_run = |state: SessionState, status: &str, message: Option<&str>| {
+        let owned = session::transition(app, port_name, generation, state, message);
+        match session_id {
+            Some(id) => {
+                with_db(app, |conn| {
+                    if owned {
+                        acquisition::close_session(conn, id, status, message)
+                    } else {
+                        acquisition::close_session(conn, id, "cancelled", Some("Stopped"))
+                    }
+                });
+            }
+            // Without an acquisition row the device history is the only trace
+            None if owned && state == SessionState::Failed => {
+                if let Some(device) = attached_device(port_name) {
+                    with_db(app, |conn| {
+                        history::record_error(conn, &device, message.unwrap_or(status))
+                    });
+                }
+            }
+            None => {}
+        }
+        owned
+    };
+
+    // The UI follows the run through `session-state-changed`, which carries
+    // the failure message; there are no per-outcome events.
+    let report_failure = |error_msg: &str, status: &str| {
+        if end_run(SessionState::Failed, status, Some(error_msg)) {
+            error!("{} on {}", error_msg, port_name);
+        }
+    };
+
+    let (outcome, no_data_timeout) = match exit {
+        ReaderExit::NoBaudRate => {
+            report_failure(
+                "Could not detect the baud rate: no protocol lines at any common rate",
+                "error",
+            );
+            return;
+        }
+        ReaderExit::OpenFailed(msg) => {
+            report_failure(&format!("Failed to open the port: {}", msg), "error");
+            return;
+        }
+        ReaderExit::Read {
+            outcome,
+            no_data_timeout,
+        } => (outcome, no_data_timeout),
+    };
+
+    match outcome {
+        ReadOutcome::Completed { cycles } => {
+            if !end_run(SessionState::Completed, "completed", None) {
+                return;
+            }
+            info!(
+                "Measurement successfully finished on {} ({} cycles)",
+                port_name, cycles
+            );
+            if let Some(report) =
+                session_id.and_then(|id| with_db(app, |conn| quality::assess_session(conn, id)))
+            {
+                info!("Quality check on {}: {:?}", port_name, report.verdict);
+            }
+        }
+        ReadOutcome::Incomplete { cycles, expected } => report_failure(
+            &format!(
+                "Measurement ended after {} of {} expected cycles.",
+                cycles, expected
+            ),
+            "incomplete",
+        ),
+        ReadOutcome::NoData => report_failure(
+            &format!(
+                "No data received for {} seconds. Measurement stopped.",
+                no_data_timeout.as_secs()
+            ),
+            "timeout",
+        ),
+        ReadOutcome::Disconnected => report_failure("Connection lost unexpectedly", "error"),
+        ReadOutcome::Failed(msg) => report_failure(&msg, "error"),
+        ReadOutcome::Cancelled => {
+            end_run(SessionState::Cancelled, "cancelled", Some("Stopped"));
+        }
+    }
 }
 
 // ==============================================================================================
@@ -302,26 +932,38 @@ pub async fn start_arduino_watcher(app: AppHandle) -> Result<(), AppError> {
     let app_clone = app.clone();
     tauri::async_runtime::spawn(async move {
         // Initial scan
-        if let Ok(Ok(current)) = tokio::task::spawn_blocking(get_current_devices_blocking).await {
+        let app_scan = app_clone.clone();
+        if let Ok(Ok(current)) =
+            tokio::task::spawn_blocking(move || scan_devices_blocking(&app_scan)).await
+        {
             let _ = scan_arduino_and_emit_core(&app_clone, current.clone());
             let _ = app_clone.emit("arduino-scan-complete", current);
         }
+        let mut last_scan = Instant::now();
+
+        start_hotplug_watcher(app_clone.clone());
 
         // Main polling loop
         while POLLING_ACTIVE.load(Ordering::Relaxed) {
-            if let Ok(Ok(current)) = tokio::task::spawn_blocking(get_current_devices_blocking).await
+            if !poll_due(HOTPLUG_ACTIVE.load(Ordering::Relaxed), last_scan.elapsed()) {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue;
+            }
+            last_scan = Instant::now();
+
+            let app_scan = app_clone.clone();
+            if let Ok(Ok(current)) =
+                tokio::task::spawn_blocking(move || scan_devices_blocking(&app_scan)).await
             {
                 let (_changed, final_devices) =
                     scan_arduino_and_emit_core(&app_clone, current.clone())
                         .unwrap_or((false, current.clone()));
 
-                let _ = restart_stale_readers(&app_clone, &final_devices);
-
                 // ALWAYS emit — fixes sleep/wake UI freeze
                 let _ = app_clone.emit("arduino-scan-complete", final_devices);
             }
 
-            tokio::time::sleep(Duration::from_secs(3)).await;
+            tokio::time::sleep(POLL_INTERVAL).await;
         }
 
         POLLING_ACTIVE.store(false, Ordering::Relaxed);
@@ -342,7 +984,8 @@ pub fn stop_arduino_watcher() -> Result<(), AppError> {
 
 #[tauri::command]
 pub async fn scan_arduino_now(app: AppHandle) -> Result<(), AppError> {
-    let current = tokio::task::spawn_blocking(get_current_devices_blocking)
+    let app_scan = app.clone();
+    let current = tokio::task::spawn_blocking(move || scan_devices_blocking(&app_scan))
         .await
         .map_err(AppError::from)??;
 
@@ -355,29 +998,87 @@ pub async fn scan_arduino_now(app: AppHandle) -> Result<(), AppError> {
 pub async fn start_reading_from_port(
     app: AppHandle,
     port_name: String,
-    baud_rate: u32,
+    baud_rate: Option<u32>,
+    admission_no: Option<String>,
+    auto_baud: Option<bool>,
+    qc: Option<bool>,
 ) -> Result<(), AppError> {
-    DESIRED_READING_PORTS.lock()?.insert(port_name.clone());
+    let qc = qc.unwrap_or(false);
+    if qc && admission_no.is_some() {
+        return Err(AppError::Resource(
+            "A QC run measures a control material and cannot belong to an admission".into(),
+        ));
+    }
+    ensure_not_probing(&port_name)?;
+    let auto_baud = auto_baud.unwrap_or(false);
+    // Scans only ask at one rate; auto-baud searches the others itself
+    if !auto_baud && matches!(IDENTITIES.lock()?.get(&port_name), Some(None)) {
+        return Err(not_identified(&port_name));
+    }
+    // Control samples are measured to clear a failed QC, so only patients wait on it
+    if !qc {
+        ensure_qc_passed(&app, &port_name)?;
+    }
 
-    let mut readers = ACTIVE_READERS.lock()?;
-    if readers.contains_key(&port_name) {
-        return Err(AppError::Resource(format!(
-            "Already reading from {}",
+    let request = ReadRequest {
+        admission_no,
+        baud_rate,
+        auto_baud,
+        test_run_id: None,
+        qc,
+    };
+    spawn_reader(&app, &port_name, request)
+}
+
+fn not_identified(port_name: &str) -> AppError {
+    AppError::Resource(format!(
+        "The device on {} did not identify itself as a Nexus board at its baud rate; \
+         read with auto-baud to try the other rates",
+        port_name
+    ))
+}
+
+/// The board last seen on a port.
+fn attached_device(port_name: &str) -> Option<UsbDevice> {
+    PREV_DEVICES
+        .lock()
+        .ok()?
+        .iter()
+        .find(|d| d.port == port_name)
+        .cloned()
+}
+
+/// Refuses patient measurements on a board whose latest QC was rejected, or
+/// whose QC status cannot be read.
+fn ensure_qc_passed(app: &AppHandle, port_name: &str) -> Result<(), AppError> {
+    let Some(device) = attached_device(port_name) else {
+        return Ok(());
+    };
+    match with_db(app, |conn| qc::device_qc_status(conn, &device)) {
+        Some(status) if status.blocks_patients() => Err(AppError::Resource(format!(
+            "QC failed on {}: {}. Run QC again before measuring patients",
+            port_name,
+            status.messages.join("; ")
+        ))),
+        Some(_) => Ok(()),
+        None => Err(AppError::Internal(format!(
+            "Could not read the QC status of the device on {}",
             port_name
-        )));
+        ))),
     }
+}
 
-    let app_clone = app.clone();
-    let port_clone = port_name.clone();
-
-    let handle = tauri::async_runtime::spawn(async move {
-        let _ = manual_read_loop(app_clone.clone(), port_clone.clone(), baud_rate).await;
-        let _ = ACTIVE_READERS.lock().map(|mut m| m.remove(&port_clone));
+/// Stops the run on a port and tells the UI. Returns `false` if nothing was
+/// reading from it.
+fn stop_reader(app: &AppHandle, port_name: &str, reason: &str) -> Result<bool, AppError> {
+    if session::stop(app, port_name, SessionState::Cancelled, reason).is_none() {
+        return Ok(false);
+    }
+    COMMAND_CHANNELS.lock()?.remove(port_name);
+    with_db(app, |conn| {
+        acquisition::cancel_running_sessions(conn, port_name, reason)
     });
-
-    readers.insert(port_name.clone(), handle);
-    app.emit("arduino-reading-started", json!({ "port": &port_name }))?;
-    Ok(())
+    Ok(true)
 }
 
 #[tauri::command]
@@ -385,14 +1086,327 @@ pub async fn stop_reading_from_port(
     app: tauri::AppHandle,
     port_name: String,
 ) -> Result<(), AppError> {
-    DESIRED_READING_PORTS.lock()?.remove(&port_name);
-
-    let mut readers = ACTIVE_READERS.lock()?;
-    if let Some(handle) = readers.remove(&port_name) {
-        handle.abort();
-        app.emit("arduino-reading-stopped", json!({ "port": port_name }))?;
+    if stop_reader(&app, &port_name, "Stopped by user")? {
         Ok(())
     } else {
         Err(AppError::Resource("Not reading from this port".into()))
     }
 }
+
+/// Starts reading from several boards at once as one test run for an
+/// admission. Progress and the overall outcome arrive as `test-run-changed`,
+/// `test-run-complete` and `test-run-failed` events.
+#[tauri::command]
+pub async fn start_test_run(
+    app: AppHandle,
+    admission_no: String,
+    ports: Vec<String>,
+    auto_baud: Option<bool>,
+) -> Result<TestRun, AppError> {
+    let mut unique: Vec<String> = Vec::new();
+    for port in ports {
+        if !unique.contains(&port) {
+            unique.push(port);
+        }
+    }
+    if unique.is_empty() {
+        return Err(AppError::Resource(
+            "A test run needs at least one device".into(),
+        ));
+    }
+
+    let auto_baud = auto_baud.unwrap_or(false);
+    // Each snapshot is copied out on its own: the scan takes PREV_DEVICES
+    // before IDENTITIES, so holding both here could deadlock with it
+    let unidentified: Vec<String> = {
+        let identities = IDENTITIES.lock()?;
+        unique
+            .iter()
+            .filter(|port| !auto_baud && matches!(identities.get(*port), Some(None)))
+            .cloned()
+            .collect()
+    };
+    let known = connected_devices();
+    let mut devices: Vec<(String, Option<String>)> = Vec::new();
+    for port in &unique {
+        if session::is_active(port) {
+            return Err(AppError::Resource(format!("Already reading from {}", port)));
+        }
+        ensure_not_probing(port)?;
+        if unidentified.contains(port) {
+            return Err(not_identified(port));
+        }
+        let role = known
+            .iter()
+            .find(|d| &d.port == port)
+            .and_then(|d| d.role.clone());
+        devices.push((port.clone(), role));
+    }
+    for port in &unique {
+        ensure_qc_passed(&app, port)?;
+    }
+
+    let run_id = with_db(&app, |conn| {
+        testrun::create(conn, Some(&admission_no), &devices)
+    })
+    .ok_or_else(|| AppError::Internal("Could not create the test run".into()))?;
+    info!(
+        "Starting test run {} for {} on {}",
+        run_id,
+        admission_no,
+        unique.join(", ")
+    );
+
+    let mut failure = None;
+    for port in &unique {
+        let request = ReadRequest {
+            admission_no: Some(admission_no.clone()),
+            baud_rate: None,
+            auto_baud,
+            test_run_id: Some(run_id),
+            qc: false,
+        };
+        if let Err(e) = spawn_reader(&app, port, request) {
+            testrun::device_not_started(&app, run_id, port, &e.to_string());
+            failure.get_or_insert(e);
+        }
+    }
+
+    // A run is measured together or not at all
+    if let Some(e) = failure {
+        stop_test_run_readers(&app, run_id, &unique, "Test run could not start")?;
+        return Err(e);
+    }
+
+    with_db(&app, |conn| testrun::load(conn, run_id))
+        .ok_or_else(|| AppError::Internal("Could not load the test run".into()))
+}
+
+fn stop_test_run_readers(
+    app: &AppHandle,
+    run_id: i64,
+    ports: &[String],
+    reason: &str,
+) -> Result<(), AppError> {
+    for port in ports {
+        if session::snapshot(port).request.test_run_id == Some(run_id) {
+            stop_reader(app, port, reason)?;
+        }
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn stop_test_run(app: AppHandle, run_id: i64) -> Result<(), AppError> {
+    let run = with_db(&app, |conn| testrun::load(conn, run_id))
+        .ok_or_else(|| AppError::Resource(format!("Unknown test run {}", run_id)))?;
+    let ports: Vec<String> = run
+        .devices
+        .into_iter()
+        .filter(|d| d.status == "running")
+        .map(|d| d.port)
+        .collect();
+    stop_test_run_readers(&app, run_id, &ports, "Test run stopped by user")
+}
+
+/// Sends one line to the firmware on a port that is currently being read.
+///
+/// With `reply_prefix` set, waits up to `timeout_ms` (default 3000) for the
+/// first line starting with that prefix and returns it; an empty prefix
+/// accepts the next line. Without it, returns once the line has been written.
+#[tauri::command]
+pub async fn send_device_command(
+    port_name: String,
+    command: String,
+    reply_prefix: Option<String>,
+    timeout_ms: Option<u64>,
+) -> Result<Option<String>, AppError> {
+    if command.trim().is_empty() {
+        return Err(AppError::Resource("Command cannot be empty".into()));
+    }
+
+    let sender = COMMAND_CHANNELS
+        .lock()?
+        .get(&port_name)
+        .map(|(_, sender)| sender.clone())
+        .ok_or_else(|| {
+            AppError::Resource(format!(
+                "No active reader on {}; start reading first",
+                port_name
+            ))
+        })?;
+
+    let timeout = Duration::from_millis(timeout_ms.unwrap_or(3000));
+    let (reply_tx, reply_rx) = mpsc::channel();
+    sender
+        .send(DeviceCommand {
+            text: command.clone(),
+            reply_prefix,
+            timeout,
+            reply: reply_tx,
+        })
+        .map_err(|_| AppError::Resource(format!("Reader on {} has stopped", port_name)))?;
+
+    // The loop only picks up commands between reads, so allow one read timeout on top.
+    let wait = timeout + READ_TIMEOUT;
+    let reply = tauri::async_runtime::spawn_blocking(move || reply_rx.recv_timeout(wait)).await?;
+
+    match reply {
+        Ok(Ok(line)) => {
+            info!("Command '{}' sent to {}", command.trim(), port_name);
+            Ok(line)
+        }
+        Ok(Err(e)) => Err(AppError::Serial(e)),
+        Err(_) => Err(AppError::Resource(format!(
+            "No reply to '{}' from {} within {} ms",
+            command.trim(),
+            port_name,
+            timeout.as_millis()
+        ))),
+    }
+}
+
+/// Re-runs the identify handshake on a connected board, e.g. after flashing
+/// new firmware onto a board previously reported as unknown.
+#[tauri::command]
+pub async fn identify_device(
+    app: AppHandle,
+    port_name: String,
+) -> Result<Option<FirmwareIdentity>, AppError> {
+    if session::is_active(&port_name) {
+        return Err(AppError::Resource(format!(
+            "{} is busy reading; stop it before identifying",
+            port_name
+        )));
+    }
+
+    let device = PREV_DEVICES
+        .lock()?
+        .iter()
+        .find(|d| d.port == port_name)
+        .cloned()
+        .ok_or_else(|| AppError::Resource(format!("No device on {}", port_name)))?;
+
+    if !claim_probe(&port_name)? {
+        return Err(AppError::Resource(format!(
+            "{} is already being identified",
+            port_name
+        )));
+    }
+    let app_probe = app.clone();
+    let identity = tokio::task::spawn_blocking(move || {
+        let result = identify_blocking(&app_probe, &device);
+        if let Ok(identity) = &result {
+            IDENTITIES
+                .lock()?
+                .insert(device.port.clone(), identity.clone());
+        }
+        release_probe(&device.port);
+        result
+    })
+    .await??;
+
+    scan_arduino_now(app).await?;
+    Ok(identity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(serial_number: &str, identity_source: &str) -> UsbDevice {
+        UsbDevice {
+            port: "/dev/ttyUSB0".to_string(),
+            vid: 0x1A86,
+            pid: 0x7523,
+            serial_number: Some(serial_number.to_string()),
+            product: Some("USB Serial".to_string()),
+            status: "connected".to_string(),
+            custom_name: None,
+            device_unit: None,
+            board_name: "USB Serial".to_string(),
+            firmware_name: None,
+            firmware_version: None,
+            role: None,
+            last_seen: None,
+            firmware_status: None,
+            firmware_message: None,
+            identity_source: Some(identity_source.to_string()),
+            device_id: None,
+        }
+    }
+
+    fn identity(uid: Option<&str>) -> FirmwareIdentity {
+        FirmwareIdentity {
+            name: "nexus-cancer".to_string(),
+            version: "1.1.0".to_string(),
+            role: Some("cancer".to_string()),
+            uid: uid.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn a_firmware_uid_replaces_a_stand_in_serial() {
+        let mut device = board("loc:1-1.2", "location");
+        apply_identity(&mut device, Some(&identity(Some("5A3F09"))));
+
+        assert_eq!(device.serial_number.as_deref(), Some("uid:5A3F09"));
+        assert_eq!(device.identity_source.as_deref(), Some("firmware"));
+        assert_eq!(device.role.as_deref(), Some("cancer"));
+        assert_eq!(device.firmware_status.as_deref(), Some("outdated"));
+        assert!(device.firmware_message.is_some());
+    }
+
+    #[test]
+    fn a_usb_serial_is_kept() {
+        let mut device = board("A50285BI", "usb");
+        apply_identity(&mut device, Some(&identity(Some("5A3F09"))));
+
+        assert_eq!(device.serial_number.as_deref(), Some("A50285BI"));
+        assert_eq!(device.identity_source.as_deref(), Some("usb"));
+
+        let mut device = board("port:/dev/ttyUSB0", "port");
+        apply_identity(&mut device, Some(&identity(None)));
+        assert_eq!(device.serial_number.as_deref(), Some("port:/dev/ttyUSB0"));
+    }
+
+    #[test]
+    fn a_board_that_does_not_answer_is_unknown() {
+        let mut device = board("A50285BI", "usb");
+        apply_identity(&mut device, None);
+
+        assert_eq!(device.status, "unknown");
+        assert_eq!(device.firmware_name, None);
+    }
+
+    fn on_port(port: &str) -> UsbDevice {
+        UsbDevice {
+            port: port.to_string(),
+            ..board("A1B2C3", "usb")
+        }
+    }
+
+    fn ports(devices: &[UsbDevice]) -> Vec<&str> {
+        devices.iter().map(|d| d.port.as_str()).collect()
+    }
+
+    #[test]
+    fn diffs_scans_by_port() {
+        let previous = [on_port("/dev/ttyUSB0"), on_port("/dev/ttyUSB1")];
+        let current = [on_port("/dev/ttyUSB1"), on_port("/dev/ttyACM0")];
+
+        let (added, removed) = diff_devices(&previous, &current);
+        assert_eq!(ports(&added), ["/dev/ttyACM0"]);
+        assert_eq!(ports(&removed), ["/dev/ttyUSB0"]);
+
+        let (added, removed) = diff_devices(&current, &current);
+        assert!(added.is_empty() && removed.is_empty());
+    }
+
+    #[test]
+    fn polls_only_as_a_fallback_while_hotplug_events_arrive() {
+        assert!(poll_due(false, Duration::ZERO));
+        assert!(!poll_due(true, POLL_INTERVAL));
+        assert!(poll_due(true, HOTPLUG_FALLBACK_INTERVAL));
+    }
+}
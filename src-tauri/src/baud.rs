@@ -0,0 +1,181 @@
+// src/baud.rs
+//
+// Serial speed per board. Boards are read at the rate the user asked for, the
+// rate remembered on their device record or the settings default, in that
+// order. Auto-detection tries the common rates until the board produces lines
+// the protocol profile understands, and remembers the winner.
+
+use std::time::Duration;
+
+use log::{debug, info};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::errordefs::AppError;
+use crate::identify;
+use crate::measurement::ParsedLine;
+use crate::protocol::ProtocolProfile;
+use crate::reader::LineReader;
+use crate::transport::Transport;
+use crate::types::UsbDevice;
+
+/// Used when neither the request, the device record nor the settings say otherwise.
+pub const FALLBACK_BAUD_RATE: u32 = 9600;
+
+/// Rates tried by auto-detection, most common first.
+pub const CANDIDATE_BAUD_RATES: [u32; 5] = [9600, 115200, 57600, 38400, 19200];
+
+/// Time given to each candidate rate, including the reboot after the DTR reset.
+const DETECT_TIMEOUT_PER_RATE: Duration = Duration::from_secs(4);
+
+/// Rate remembered for a board from an earlier auto-detection.
+pub fn stored_baud_rate(conn: &Connection, device: &UsbDevice) -> rusqlite::Result<Option<u32>> {
+    conn.query_row(
+        "SELECT baud_rate FROM devices
+         WHERE vid = ?1 AND pid = ?2 AND serial_number IS ?3 AND baud_rate IS NOT NULL
+         ORDER BY last_seen DESC LIMIT 1",
+        params![device.vid, device.pid, device.serial_number],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+pub fn record_baud_rate(
+    conn: &Connection,
+    device: &UsbDevice,
+    baud_rate: u32,
+) -> rusqlite::Result<()> {
+    let updated = conn.execute(
+        "UPDATE devices SET baud_rate = ?4, last_seen = CURRENT_TIMESTAMP
+         WHERE vid = ?1 AND pid = ?2 AND serial_number IS ?3",
+        params![device.vid, device.pid, device.serial_number, baud_rate],
+    )?;
+
+    if updated == 0 {
+        conn.execute(
+            "INSERT INTO devices (vid, pid, serial_number, product, baud_rate)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                device.vid,
+                device.pid,
+                device.serial_number,
+                device.product,
+                baud_rate
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Candidate rates with the likely ones moved to the front.
+pub fn candidates(preferred: &[Option<u32>]) -> Vec<u32> {
+    let mut rates: Vec<u32> = Vec::new();
+    for rate in preferred
+        .iter()
+        .flatten()
+        .copied()
+        .chain(CANDIDATE_BAUD_RATES)
+    {
+        if rate > 0 && !rates.contains(&rate) {
+            rates.push(rate);
+        }
+    }
+    rates
+}
+
+/// Opens the board at each rate in turn and returns the first one at which it
+/// answers the identify command or prints a line the profile can parse. At a
+/// wrong rate the board only produces garbage, which matches neither.
+pub fn detect(
+    mut open: impl FnMut(u32) -> Result<Box<dyn Transport>, AppError>,
+    rates: &[u32],
+    profile: &ProtocolProfile,
+) -> Option<u32> {
+    let identify_pattern = profile.identify_regex().ok();
+
+    for &rate in rates {
+        let transport = match open(rate) {
+            Ok(transport) => transport,
+            Err(e) => {
+                debug!("Cannot open port at {} baud: {}", rate, e);
+                continue;
+            }
+        };
+        let Ok(mut parser) = profile.parser() else {
+            return None;
+        };
+
+        let mut reader = LineReader::new(transport);
+        let recognised = identify::probe(
+            &mut reader,
+            &profile.identify_command,
+            DETECT_TIMEOUT_PER_RATE,
+            |line| {
+                let identified = identify_pattern
+                    .as_ref()
+                    .is_some_and(|p| identify::parse_identity(p, line).is_some());
+                (identified || parser.parse_line(line) != ParsedLine::Other).then_some(())
+            },
+        );
+
+        if recognised.is_some() {
+            info!("Detected {} baud", rate);
+            return Some(rate);
+        }
+        debug!("No protocol lines at {} baud", rate);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{MemoryEvent, MemoryTransport};
+
+    #[test]
+    fn puts_preferred_rates_first_without_repeats() {
+        assert_eq!(
+            candidates(&[Some(57600), None, Some(9600), Some(0), Some(250000)]),
+            [57600, 9600, 250000, 115200, 38400, 19200]
+        );
+        assert_eq!(candidates(&[]), CANDIDATE_BAUD_RATES);
+    }
+
+    #[test]
+    fn detects_the_rate_at_which_the_board_makes_sense() {
+        let mut tried = Vec::new();
+        let open = |rate: u32| -> Result<Box<dyn Transport>, AppError> {
+            tried.push(rate);
+            let script = match rate {
+                9600 => return Err(AppError::Serial("port busy".to_string())),
+                57600 => vec![
+                    MemoryEvent::Data(b"\xf0\x8c~~\x13\r\n".to_vec()),
+                    MemoryEvent::Disconnect,
+                ],
+                _ => vec![MemoryEvent::Data(
+                    b"Output Voltage (ON): 1.1 V\r\n".to_vec(),
+                )],
+            };
+            Ok(Box::new(MemoryTransport::new(script)))
+        };
+
+        let rate = detect(open, &[9600, 57600, 115200], &ProtocolProfile::default());
+
+        assert_eq!(rate, Some(115200));
+        assert_eq!(tried, [9600, 57600, 115200]);
+    }
+
+    #[test]
+    fn accepts_an_identify_reply_as_a_match() {
+        let open = |_| -> Result<Box<dyn Transport>, AppError> {
+            Ok(Box::new(MemoryTransport::new([]).with_responder(Box::new(
+                |_| vec!["ID nexus-cancer 1.2.0".to_string()],
+            ))))
+        };
+
+        assert_eq!(
+            detect(open, &[38400], &ProtocolProfile::default()),
+            Some(38400)
+        );
+    }
+}
@@ -0,0 +1,421 @@
+// src/calibration.rs
+//
+// Per-device calibration curves. The rigs drift, so each board is calibrated
+// from time to time against reference standards of known voltage. A
+// calibration is a polynomial mapping the voltage the board reports onto the
+// true one (two coefficients: offset and gain) and applies from its
+// `valid_from` date until a newer one takes over. Every acquisition records
+// which calibration its readings were corrected with.
+
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::acquisition::TIMESTAMP_FORMAT;
+use crate::database::{log_event, Database};
+use crate::types::UsbDevice;
+
+/// Highest polynomial degree a calibration may be fitted with.
+pub const MAX_DEGREE: usize = 3;
+
+/// A reference standard as measured by the board.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReferencePoint {
+    /// True voltage of the standard.
+    pub reference: f64,
+    /// Voltage the board reported. Taken from `session_id` when omitted.
+    #[serde(default)]
+    pub measured: Option<f64>,
+    /// Acquisition run in which the board measured the standard.
+    #[serde(default)]
+    pub session_id: Option<i64>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Calibration {
+    pub id: i64,
+    pub vid: u16,
+    pub pid: u16,
+    pub serial_number: Option<String>,
+    /// Polynomial coefficients, constant term first: `c0 + c1·v + c2·v² …`.
+    pub coefficients: Vec<f64>,
+    pub valid_from: String,
+    pub reference_points: Vec<ReferencePoint>,
+    /// Root-mean-square error of the fit over the reference points, in volts.
+    pub residual: f64,
+    pub performed_by: Option<String>,
+    pub notes: Option<String>,
+    pub created_at: String,
+}
+
+impl Calibration {
+    pub fn apply(&self, voltage: f64) -> f64 {
+        evaluate(&self.coefficients, voltage)
+    }
+}
+
+fn evaluate(coefficients: &[f64], x: f64) -> f64 {
+    coefficients.iter().rev().fold(0.0, |acc, c| acc * x + c)
+}
+
+fn now() -> String {
+    Local::now().format(TIMESTAMP_FORMAT).to_string()
+}
+
+/// Least-squares polynomial through `(measured, reference)` pairs. A single
+/// point yields a pure offset.
+pub fn fit(points: &[(f64, f64)], degree: usize) -> Result<Vec<f64>, String> {
+    if points.is_empty() {
+        return Err("At least one reference point is needed.".into());
+    }
+    if degree == 0 || degree > MAX_DEGREE {
+        return Err(format!("Degree must be between 1 and {}.", MAX_DEGREE));
+    }
+    if points.len() == 1 {
+        let (measured, reference) = points[0];
+        return Ok(vec![reference - measured, 1.0]);
+    }
+    if points.len() <= degree {
+        return Err(format!(
+            "A degree {} calibration needs at least {} reference points.",
+            degree,
+            degree + 1
+        ));
+    }
+
+    // Normal equations A·c = b, solved by Gaussian elimination
+    let n = degree + 1;
+    let mut a = vec![vec![0.0; n + 1]; n];
+    for &(x, y) in points {
+        for (row, equation) in a.iter_mut().enumerate() {
+            for (col, cell) in equation.iter_mut().take(n).enumerate() {
+                *cell += x.powi((row + col) as i32);
+            }
+            equation[n] += y * x.powi(row as i32);
+        }
+    }
+
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+            .unwrap_or(col);
+        if a[pivot][col].abs() < 1e-12 {
+            return Err(
+                "Reference points do not determine a curve; measure more distinct standards."
+                    .into(),
+            );
+        }
+        a.swap(col, pivot);
+        let pivot_row = a[col].clone();
+        for (row, equation) in a.iter_mut().enumerate() {
+            if row != col {
+                let factor = equation[col] / pivot_row[col];
+                for (cell, p) in equation.iter_mut().zip(&pivot_row).skip(col) {
+                    *cell -= factor * p;
+                }
+            }
+        }
+    }
+
+    Ok((0..n).map(|i| a[i][n] / a[i][i]).collect())
+}
+
+fn rms_residual(coefficients: &[f64], points: &[(f64, f64)]) -> f64 {
+    let sum: f64 = points
+        .iter()
+        .map(|&(x, y)| (evaluate(coefficients, x) - y).powi(2))
+        .sum();
+    (sum / points.len() as f64).sqrt()
+}
+
+/// Accepts `YYYY-MM-DD` or a full timestamp and returns the stored form.
+fn normalize_valid_from(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date
+            .and_hms_opt(0, 0, 0)
+            .unwrap_or_default()
+            .format(TIMESTAMP_FORMAT)
+            .to_string());
+    }
+    [
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+    .map(|t| t.format(TIMESTAMP_FORMAT).to_string())
+    .ok_or_else(|| format!("Invalid valid-from date '{}'.", value))
+}
+
+/// Mean raw voltage the board reported during a session.
+fn session_mean(conn: &Connection, session_id: i64) -> rusqlite::Result<Option<f64>> {
+    conn.query_row(
+        "SELECT AVG(COALESCE(raw_voltage, voltage)) FROM acquisition_lines
+         WHERE session_id = ?1 AND voltage IS NOT NULL",
+        params![session_id],
+        |row| row.get(0),
+    )
+}
+
+const CALIBRATION_COLUMNS: &str = "id, vid, pid, serial_number, coefficients, valid_from,
+    reference_points, residual, performed_by, notes, created_at";
+
+fn map_calibration(row: &rusqlite::Row<'_>) -> rusqlite::Result<Calibration> {
+    let coefficients: String = row.get(4)?;
+    let reference_points: Option<String> = row.get(6)?;
+    Ok(Calibration {
+        id: row.get(0)?,
+        vid: row.get(1)?,
+        pid: row.get(2)?,
+        serial_number: row.get(3)?,
+        coefficients: serde_json::from_str(&coefficients).unwrap_or_else(|_| vec![0.0, 1.0]),
+        valid_from: row.get(5)?,
+        reference_points: reference_points
+            .and_then(|p| serde_json::from_str(&p).ok())
+            .unwrap_or_default(),
+        residual: row.get(7)?,
+        performed_by: row.get(8)?,
+        notes: row.get(9)?,
+        created_at: row.get(10)?,
+    })
+}
+
+/// Calibration in force for a board right now, if it was ever calibrated.
+pub fn active_calibration(
+    conn: &Connection,
+    device: &UsbDevice,
+) -> rusqlite::Result<Option<Calibration>> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM device_calibrations
+             WHERE vid = ?1 AND pid = ?2 AND serial_number IS ?3 AND valid_from <= ?4
+             ORDER BY valid_from DESC, id DESC LIMIT 1",
+            CALIBRATION_COLUMNS
+        ),
+        params![device.vid, device.pid, device.serial_number, now()],
+        map_calibration,
+    )
+    .optional()
+}
+
+/* ----------------------------------------
+   TAURI COMMANDS
+----------------------------------------- */
+
+#[derive(Deserialize, Debug)]
+pub struct CalibrationRequest {
+    pub vid: u16,
+    pub pid: u16,
+    pub serial_number: Option<String>,
+    pub points: Vec<ReferencePoint>,
+    /// Polynomial degree; 1 (offset and gain) when omitted.
+    #[serde(default)]
+    pub degree: Option<usize>,
+    /// Date or timestamp from which the calibration applies; now when omitted.
+    #[serde(default)]
+    pub valid_from: Option<String>,
+    #[serde(default)]
+    pub performed_by: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+/// Fits a calibration for a board from reference standards and stores it.
+#[tauri::command]
+pub fn calibrate_device(
+    db: State<'_, Database>,
+    request: CalibrationRequest,
+) -> Result<Calibration, String> {
+    let CalibrationRequest {
+        vid,
+        pid,
+        serial_number,
+        points,
+        degree,
+        valid_from,
+        performed_by,
+        notes,
+    } = request;
+    let valid_from = match valid_from.as_deref() {
+        Some(value) if !value.trim().is_empty() => normalize_valid_from(value)?,
+        _ => now(),
+    };
+
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+
+    let mut resolved = Vec::with_capacity(points.len());
+    let mut pairs = Vec::with_capacity(points.len());
+    for point in points {
+        let measured = match (point.measured, point.session_id) {
+            (Some(measured), _) => measured,
+            (None, Some(session_id)) => session_mean(&conn, session_id)
+                .map_err(|e| e.to_string())?
+                .ok_or(format!("Session {} has no voltage readings.", session_id))?,
+            (None, None) => {
+                return Err(format!(
+                    "Reference {} V needs a measured value or a session.",
+                    point.reference
+                ))
+            }
+        };
+        pairs.push((measured, point.reference));
+        resolved.push(ReferencePoint {
+            measured: Some(measured),
+            ..point
+        });
+    }
+
+    let coefficients = fit(&pairs, degree.unwrap_or(1))?;
+    let residual = rms_residual(&coefficients, &pairs);
+
+    conn.execute(
+        "INSERT INTO device_calibrations (
+            vid, pid, serial_number, coefficients, valid_from, reference_points,
+            residual, performed_by, notes, created_at
+         ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        params![
+            vid,
+            pid,
+            serial_number,
+            serde_json::to_string(&coefficients).map_err(|e| e.to_string())?,
+            valid_from,
+            serde_json::to_string(&resolved).map_err(|e| e.to_string())?,
+            residual,
+            performed_by,
+            notes,
+            now()
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    let id = conn.last_insert_rowid();
+
+    log_event(
+        &conn,
+        &format!(
+            "Calibrated device (VID:{}, PID:{}, SN:{}) from {} reference point(s), RMS error {:.4} V",
+            vid,
+            pid,
+            serial_number.as_deref().unwrap_or("None"),
+            pairs.len(),
+            residual
+        ),
+    )
+    .map_err(|e| e.to_string())?;
+
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM device_calibrations WHERE id = ?1",
+            CALIBRATION_COLUMNS
+        ),
+        params![id],
+        map_calibration,
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// Calibration history of a board, newest first.
+#[tauri::command]
+pub fn get_device_calibrations(
+    db: State<'_, Database>,
+    vid: u16,
+    pid: u16,
+    serial_number: Option<String>,
+) -> Result<Vec<Calibration>, String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM device_calibrations
+             WHERE vid = ?1 AND pid = ?2 AND serial_number IS ?3
+             ORDER BY valid_from DESC, id DESC",
+            CALIBRATION_COLUMNS
+        ))
+        .map_err(|e| e.to_string())?;
+
+    let rows = stmt
+        .query_map(params![vid, pid, serial_number], map_calibration)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn evaluates_constant_term_first() {
+        assert_eq!(evaluate(&[0.5, 2.0, 1.0], 3.0), 0.5 + 6.0 + 9.0);
+        assert_eq!(evaluate(&[], 3.0), 0.0);
+    }
+
+    #[test]
+    fn fits_a_line_through_exact_points() {
+        let points = [(0.2, 0.5), (1.0, 2.1), (2.0, 4.1)];
+        let coefficients = fit(&points, 1).unwrap();
+
+        assert_close(&coefficients, &[0.1, 2.0]);
+        assert!(rms_residual(&coefficients, &points) < 1e-9);
+    }
+
+    #[test]
+    fn fits_a_quadratic() {
+        let points: Vec<(f64, f64)> = [0.0, 0.5, 1.0, 2.0]
+            .iter()
+            .map(|&x| (x, 0.1 + 0.9 * x + 0.05 * x * x))
+            .collect();
+
+        assert_close(&fit(&points, 2).unwrap(), &[0.1, 0.9, 0.05]);
+    }
+
+    #[test]
+    fn a_single_point_is_an_offset() {
+        assert_close(&fit(&[(1.2, 1.0)], 2).unwrap(), &[-0.2, 1.0]);
+    }
+
+    #[test]
+    fn refuses_fits_the_points_cannot_support() {
+        assert!(fit(&[], 1).is_err());
+        assert!(fit(&[(1.0, 1.0), (2.0, 2.0)], 0).is_err());
+        assert!(fit(&[(1.0, 1.0), (2.0, 2.0)], MAX_DEGREE + 1).is_err());
+        assert!(fit(&[(1.0, 1.0), (2.0, 2.0)], 2).is_err());
+        // Repeating one standard does not pin down a slope
+        assert!(fit(&[(1.0, 1.1), (1.0, 1.1), (1.0, 1.1)], 1).is_err());
+    }
+
+    #[test]
+    fn reports_the_rms_residual() {
+        let residual = rms_residual(&[0.0, 1.0], &[(1.0, 1.1), (2.0, 1.9)]);
+
+        assert!((residual - 0.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn normalizes_valid_from() {
+        assert_eq!(
+            normalize_valid_from(" 2024-03-01 ").unwrap(),
+            "2024-03-01 00:00:00.000"
+        );
+        assert_eq!(
+            normalize_valid_from("2024-03-01T08:30:15").unwrap(),
+            "2024-03-01 08:30:15.000"
+        );
+        assert_eq!(
+            normalize_valid_from("2024-03-01 08:30").unwrap(),
+            "2024-03-01 08:30:00.000"
+        );
+        assert!(normalize_valid_from("01/03/2024").is_err());
+    }
+}
@@ -3,6 +3,14 @@ use std::{
     sync::{Arc, Mutex},
 };
 
+use crate::acquisition;
+use crate::arduino;
+use crate::firmware;
+use crate::identify;
+use crate::quality;
+use crate::results;
+use crate::search::{self, AdmissionFilter};
+use crate::testrun;
 use crate::types::UsbDevice;
 use serde::Serialize;
 use serde_json::Number;
@@ -10,7 +18,8 @@ use tauri::path::BaseDirectory;
 use tauri::{AppHandle, Manager, Runtime, State};
 
 use log::{error, info, warn};
-use rusqlite::{params, Connection};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
 use rusqlite_migration::{Migrations, M};
 use serde::Deserialize;
 
@@ -25,7 +34,9 @@ pub struct GlobalStats {
 
 #[derive(serde::Serialize)]
 pub struct PatientRecord {
+    /// Encounter id; one record per admission number.
     pub id: i32,
+    pub patient_id: i32,
     pub admission_no: String,
     pub national_id: Option<String>,
     pub firstname: String,
@@ -77,26 +88,37 @@ pub struct AdmissionPayload {
     pub diabetes_test: Option<i32>, // Matches the SQLite INTEGER
     pub reference: String,          // JSON String
     pub cancer_tests: String,       // JSON String
+    // Acquisition sessions that produced this result, besides the sample sessions
+    #[serde(default)]
+    pub session_ids: Vec<i64>,
+    // Required to save results from sessions that failed their quality check
+    #[serde(default)]
+    pub quality_override: Option<QualityOverride>,
+    // Sessions that measured each sample; their OFF readings are what is stored
+    #[serde(default)]
+    pub reference_session_id: Option<i64>,
+    #[serde(default)]
+    pub cancer_session_id: Option<i64>,
+}
+
+#[derive(Deserialize, serde::Serialize, Debug)]
+pub struct QualityOverride {
+    pub by: String,
+    pub reason: String,
 }
 
 /* ----------------------------------------
    LOG EVENT HELPER
 ----------------------------------------- */
 
-fn log_event(conn: &Connection, message: &str) -> Result<(), rusqlite::Error> {
+pub fn log_event(conn: &Connection, message: &str) -> Result<(), rusqlite::Error> {
     conn.execute("INSERT INTO event_logs (message) VALUES (?1)", [message])?;
     Ok(())
 }
 
-pub fn init_database(app: &AppHandle) -> Result<Connection, Box<dyn std::error::Error>> {
-    let base_dir = app.path().resolve("data", BaseDirectory::AppData)?;
-    fs::create_dir_all(&base_dir)?;
-    let db_path = base_dir.join("app.db");
-
-    let mut conn = Connection::open(&db_path)?;
-
-    // Define the migrations
-    let migrations = Migrations::new(vec![
+/// Schema migrations, applied in order; `user_version` counts the ones applied.
+pub fn migrations() -> Migrations<'static> {
+    Migrations::new(vec![
         // M0: Initial Database Setup
         M::up(
             "
@@ -171,10 +193,606 @@ pub fn init_database(app: &AppHandle) -> Result<Connection, Box<dyn std::error::
                 ON devices (custom_name) WHERE custom_name IS NOT NULL AND custom_name != '';
         ",
         ),
-    ]);
+        // M1: Raw acquisition sessions and every line received during them
+        M::up(
+            "
+            CREATE TABLE IF NOT EXISTS acquisition_sessions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                port TEXT NOT NULL,
+                vid INTEGER,
+                pid INTEGER,
+                device_serial TEXT,
+                admission_no TEXT,
+                admission_id INTEGER REFERENCES admissions(id) ON DELETE SET NULL,
+                baud_rate INTEGER NOT NULL,
+                status TEXT NOT NULL DEFAULT 'running',
+                status_message TEXT,
+                started_at DATETIME NOT NULL,
+                ended_at DATETIME
+            );
+
+            CREATE TABLE IF NOT EXISTS acquisition_lines (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id INTEGER NOT NULL
+                    REFERENCES acquisition_sessions(id) ON DELETE CASCADE,
+                line_no INTEGER NOT NULL,
+                received_at DATETIME NOT NULL,
+                raw TEXT NOT NULL,
+                phase TEXT CHECK(phase IN ('ON', 'OFF')),
+                voltage REAL,
+                cycle_index INTEGER,
+                unit TEXT
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_acquisition_lines_session
+                ON acquisition_lines (session_id, line_no);
+
+            CREATE INDEX IF NOT EXISTS idx_acquisition_sessions_admission
+                ON acquisition_sessions (admission_no);
+
+            CREATE INDEX IF NOT EXISTS idx_acquisition_sessions_device
+                ON acquisition_sessions (port, device_serial);
+        ",
+        ),
+        // M2: Per-device measurement protocol profiles
+        M::up(
+            r"
+            CREATE TABLE IF NOT EXISTS protocol_profiles (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT UNIQUE NOT NULL,
+                end_marker TEXT NOT NULL,
+                no_data_timeout_ms INTEGER NOT NULL DEFAULT 10000,
+                dtr_reset_ms INTEGER NOT NULL DEFAULT 250,
+                expected_cycles INTEGER NOT NULL DEFAULT 0,
+                measurement_pattern TEXT NOT NULL,
+                cycle_pattern TEXT
+            );
+
+            INSERT INTO protocol_profiles (
+                name, end_marker, no_data_timeout_ms, dtr_reset_ms,
+                expected_cycles, measurement_pattern, cycle_pattern
+            ) VALUES
+            (
+                'cancer-cell', 'cycles completed', 10000, 250, 3,
+                '^Output Voltage\s*\((?P<phase>(?i:ON|OFF))\)\s*:\s*(?P<value>[-+]?\d*\.?\d+)\s*(?P<unit>\S+)?',
+                '(?i)^cycle\s*[#:]?\s*(?P<cycle>\d+)\b'
+            ),
+            (
+                'glucose', 'cycles completed', 10000, 250, 1,
+                '^(?:Output Voltage|Glucose)\s*(?:\((?P<phase>(?i:ON|OFF))\))?\s*:\s*(?P<value>[-+]?\d*\.?\d+)\s*(?P<unit>\S+)?',
+                NULL
+            );
+
+            ALTER TABLE devices ADD COLUMN protocol_profile_id INTEGER
+                REFERENCES protocol_profiles(id) ON DELETE SET NULL;
+
+            ALTER TABLE acquisition_sessions ADD COLUMN protocol_profile_id INTEGER
+                REFERENCES protocol_profiles(id) ON DELETE SET NULL;
+        ",
+        ),
+        // M3: Firmware identification handshake
+        M::up(
+            r"
+            ALTER TABLE protocol_profiles ADD COLUMN identify_command TEXT NOT NULL
+                DEFAULT 'ID?';
+
+            ALTER TABLE protocol_profiles ADD COLUMN identify_pattern TEXT NOT NULL
+                DEFAULT '(?i)^ID\s+(?P<name>[\w.-]+)\s+v?(?P<version>\d+(?:\.\d+)*)(?:\s+(?P<role>[\w-]+))?';
+
+            ALTER TABLE devices ADD COLUMN firmware_name TEXT;
+            ALTER TABLE devices ADD COLUMN firmware_version TEXT;
+            ALTER TABLE devices ADD COLUMN role TEXT CHECK(role IN ('cancer', 'glucose'));
+        ",
+        ),
+        // M4: Explicit device roles; carry over roles assigned through custom names
+        M::up(
+            "
+            ALTER TABLE devices ADD COLUMN role_source TEXT
+                CHECK(role_source IN ('firmware', 'manual'));
+
+            UPDATE devices SET role_source = 'firmware' WHERE role IS NOT NULL;
+
+            UPDATE devices SET role = 'cancer', role_source = 'manual'
+                WHERE custom_name = 'Cancer Screening Unit';
+
+            UPDATE devices SET role = 'glucose', role_source = 'manual'
+                WHERE custom_name = 'Glucose Monitoring Unit'
+                   OR LOWER(custom_name) LIKE '%glucose%'
+                   OR LOWER(custom_name) LIKE '%diabetes%';
+        ",
+        ),
+        // M5: Baud rate found by auto-detection
+        M::up(
+            "
+            ALTER TABLE devices ADD COLUMN baud_rate INTEGER;
+        ",
+        ),
+        // M6: Test runs measuring several devices together for one admission
+        M::up(
+            "
+            CREATE TABLE IF NOT EXISTS test_runs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                admission_no TEXT,
+                status TEXT NOT NULL DEFAULT 'running'
+                    CHECK (status IN ('running', 'completed', 'partially_failed', 'failed', 'cancelled', 'interrupted')),
+                started_at DATETIME NOT NULL,
+                ended_at DATETIME
+            );
+
+            CREATE TABLE IF NOT EXISTS test_run_devices (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                test_run_id INTEGER NOT NULL REFERENCES test_runs(id) ON DELETE CASCADE,
+                port TEXT NOT NULL,
+                role TEXT,
+                status TEXT NOT NULL DEFAULT 'running'
+                    CHECK (status IN ('running', 'completed', 'failed', 'cancelled')),
+                status_message TEXT,
+                acquisition_session_id INTEGER
+                    REFERENCES acquisition_sessions(id) ON DELETE SET NULL,
+                UNIQUE (test_run_id, port)
+            );
+
+            ALTER TABLE acquisition_sessions ADD COLUMN test_run_id INTEGER
+                REFERENCES test_runs(id) ON DELETE SET NULL;
+
+            CREATE INDEX IF NOT EXISTS idx_test_runs_admission ON test_runs (admission_no);
+        ",
+        ),
+        // M7: Signal quality limits per profile and verdicts per session
+        M::up(
+            "
+            ALTER TABLE protocol_profiles ADD COLUMN min_voltage REAL NOT NULL DEFAULT 0.0;
+            ALTER TABLE protocol_profiles ADD COLUMN max_voltage REAL NOT NULL DEFAULT 5.0;
+            ALTER TABLE protocol_profiles ADD COLUMN off_readings_per_cycle INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE protocol_profiles ADD COLUMN max_cycle_spread REAL NOT NULL DEFAULT 0.0;
+
+            ALTER TABLE acquisition_sessions ADD COLUMN quality_verdict TEXT
+                CHECK (quality_verdict IN ('pass', 'warn', 'fail'));
+            ALTER TABLE acquisition_sessions ADD COLUMN quality_issues TEXT;
+            ALTER TABLE acquisition_sessions ADD COLUMN quality_override_by TEXT;
+            ALTER TABLE acquisition_sessions ADD COLUMN quality_override_reason TEXT;
+        ",
+        ),
+        // M8: Device calibration curves, applied to readings as they are stored
+        M::up(
+            "
+            CREATE TABLE IF NOT EXISTS device_calibrations (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                vid INTEGER NOT NULL,
+                pid INTEGER NOT NULL,
+                serial_number TEXT,
+                coefficients TEXT NOT NULL,
+                valid_from DATETIME NOT NULL,
+                reference_points TEXT,
+                residual REAL NOT NULL DEFAULT 0,
+                performed_by TEXT,
+                notes TEXT,
+                created_at DATETIME NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_device_calibrations_device
+                ON device_calibrations (vid, pid, serial_number, valid_from);
+
+            ALTER TABLE acquisition_sessions ADD COLUMN calibration_id INTEGER
+                REFERENCES device_calibrations(id) ON DELETE SET NULL;
+            ALTER TABLE acquisition_lines ADD COLUMN raw_voltage REAL;
+        ",
+        ),
+        // M9: Quality control materials and the results measured on each device
+        M::up(
+            "
+            CREATE TABLE IF NOT EXISTS qc_materials (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                lot_number TEXT,
+                level TEXT,
+                target_mean REAL NOT NULL,
+                target_sd REAL NOT NULL CHECK (target_sd > 0),
+                expires_on DATE,
+                active INTEGER NOT NULL DEFAULT 1,
+                created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                UNIQUE (name, lot_number, level)
+            );
+
+            CREATE TABLE IF NOT EXISTS qc_results (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                material_id INTEGER NOT NULL REFERENCES qc_materials(id) ON DELETE CASCADE,
+                vid INTEGER NOT NULL,
+                pid INTEGER NOT NULL,
+                serial_number TEXT,
+                value REAL NOT NULL,
+                z_score REAL NOT NULL,
+                violations TEXT,
+                status TEXT NOT NULL CHECK (status IN ('accepted', 'warning', 'rejected')),
+                acquisition_session_id INTEGER
+                    REFERENCES acquisition_sessions(id) ON DELETE SET NULL,
+                performed_by TEXT,
+                measured_at DATETIME NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_qc_results_device
+                ON qc_results (vid, pid, serial_number, material_id, measured_at);
+        ",
+        ),
+        // M10: Usage and maintenance history of each device
+        M::up(
+            "
+            CREATE TABLE IF NOT EXISTS device_events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                vid INTEGER NOT NULL,
+                pid INTEGER NOT NULL,
+                serial_number TEXT,
+                kind TEXT NOT NULL CHECK (kind IN (
+                    'first_seen', 'connected', 'disconnected', 'run', 'error', 'timeout',
+                    'maintenance'
+                )),
+                port TEXT,
+                acquisition_session_id INTEGER
+                    REFERENCES acquisition_sessions(id) ON DELETE SET NULL,
+                message TEXT,
+                performed_by TEXT,
+                occurred_at DATETIME NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_device_events_device
+                ON device_events (vid, pid, serial_number, occurred_at);
+            CREATE INDEX IF NOT EXISTS idx_device_events_session
+                ON device_events (acquisition_session_id);
+        ",
+        ),
+        // M11: Port each device was last seen on by the watcher
+        M::up(
+            "
+            ALTER TABLE devices ADD COLUMN last_port TEXT;
+        ",
+        ),
+        // M12: Where a device's serial number comes from, as boards without a
+        // USB serial are keyed by their firmware id or USB location instead
+        M::up(
+            "
+            ALTER TABLE devices ADD COLUMN identity_source TEXT
+                CHECK (identity_source IN ('usb', 'firmware', 'location', 'port'));
+            UPDATE devices SET identity_source = 'usb' WHERE serial_number IS NOT NULL;
+        ",
+        ),
+        // M13: Firmware each acquisition was recorded with, for audits
+        M::up(
+            "
+            ALTER TABLE acquisition_sessions ADD COLUMN firmware_name TEXT;
+            ALTER TABLE acquisition_sessions ADD COLUMN firmware_version TEXT;
+        ",
+        ),
+        // M14: Measured values in tables of their own instead of JSON strings
+        // on the admission; existing admissions are backfilled
+        M::up(
+            "
+            CREATE TABLE IF NOT EXISTS test_results (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                admission_id INTEGER NOT NULL REFERENCES admissions(id) ON DELETE CASCADE,
+                sample_type TEXT NOT NULL CHECK (sample_type IN ('reference', 'cancer')),
+                acquisition_session_id INTEGER
+                    REFERENCES acquisition_sessions(id) ON DELETE SET NULL,
+                vid INTEGER,
+                pid INTEGER,
+                device_serial TEXT,
+                created_at DATETIME NOT NULL DEFAULT (datetime('now', 'localtime'))
+            );
+
+            CREATE TABLE IF NOT EXISTS result_values (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                result_id INTEGER NOT NULL REFERENCES test_results(id) ON DELETE CASCADE,
+                position INTEGER NOT NULL,
+                cycle_index INTEGER,
+                phase TEXT CHECK (phase IN ('ON', 'OFF')),
+                value REAL NOT NULL,
+                unit TEXT
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_test_results_admission
+                ON test_results (admission_id, sample_type);
+            CREATE INDEX IF NOT EXISTS idx_test_results_device
+                ON test_results (vid, pid, device_serial);
+            CREATE INDEX IF NOT EXISTS idx_result_values_result
+                ON result_values (result_id, position);
+
+            INSERT INTO test_results (admission_id, sample_type, created_at)
+            SELECT a.id, s.sample_type, COALESCE(a.timestamp, datetime('now', 'localtime'))
+            FROM admissions a
+            JOIN (SELECT 'reference' AS sample_type UNION ALL SELECT 'cancer') s
+            WHERE json_type(
+                CASE WHEN json_valid(CASE s.sample_type WHEN 'reference' THEN a.reference ELSE a.cancer_tests END)
+                     THEN CASE s.sample_type WHEN 'reference' THEN a.reference ELSE a.cancer_tests END
+                END,
+                '$.voltage_off'
+            ) = 'array';
+
+            -- Readings saved as numeric strings are converted too
+            INSERT INTO result_values (result_id, position, phase, value, unit)
+            SELECT r.id, j.key, 'OFF', CAST(TRIM(j.value) AS REAL), 'V'
+            FROM test_results r
+            JOIN admissions a ON a.id = r.admission_id,
+                json_each(
+                    CASE r.sample_type WHEN 'reference' THEN a.reference ELSE a.cancer_tests END,
+                    '$.voltage_off'
+                ) j
+            WHERE j.type IN ('integer', 'real')
+               OR (j.type = 'text'
+                   AND CASE WHEN json_valid(TRIM(j.value)) THEN json_type(TRIM(j.value)) END
+                       IN ('integer', 'real'));
+
+            DELETE FROM test_results
+            WHERE NOT EXISTS (SELECT 1 FROM result_values v WHERE v.result_id = test_results.id);
+        ",
+        ),
+        // M15: Patients keep one row per person; each admission number becomes
+        // an encounter of that patient. Old rows sharing a national ID are
+        // merged into one patient, each merge recorded in `patient_merges` as
+        // `merge_patients` does. The JSON result columns moved out in M14.
+        M::up(
+            "
+            CREATE TABLE patients_new (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                national_id TEXT,
+                firstname TEXT NOT NULL,
+                lastname TEXT NOT NULL,
+                contact_person TEXT,
+                telephone_1 TEXT,
+                telephone_2 TEXT,
+                created_at DATETIME NOT NULL DEFAULT (datetime('now', 'localtime'))
+            );
+
+            -- Rows sharing a national ID are one person; the latest row wins
+            CREATE TEMP TABLE patient_keeper AS
+            SELECT p.id AS old_id,
+                   COALESCE(
+                       (SELECT MAX(q.id) FROM patients q
+                        WHERE NULLIF(TRIM(q.national_id), '') = NULLIF(TRIM(p.national_id), '')),
+                       p.id
+                   ) AS patient_id
+            FROM patients p;
+
+            -- Contacts missing from that row are taken from the latest row that has them
+            INSERT INTO patients_new (
+                id, national_id, firstname, lastname, contact_person, telephone_1, telephone_2
+            )
+            SELECT p.id, NULLIF(TRIM(p.national_id), ''), p.firstname, p.lastname,
+                   COALESCE(p.contact_person,
+                       (SELECT q.contact_person FROM patients q JOIN patient_keeper k ON k.old_id = q.id
+                        WHERE k.patient_id = p.id AND q.contact_person IS NOT NULL
+                        ORDER BY q.id DESC LIMIT 1)),
+                   COALESCE(p.telephone_1,
+                       (SELECT q.telephone_1 FROM patients q JOIN patient_keeper k ON k.old_id = q.id
+                        WHERE k.patient_id = p.id AND q.telephone_1 IS NOT NULL
+                        ORDER BY q.id DESC LIMIT 1)),
+                   COALESCE(p.telephone_2,
+                       (SELECT q.telephone_2 FROM patients q JOIN patient_keeper k ON k.old_id = q.id
+                        WHERE k.patient_id = p.id AND q.telephone_2 IS NOT NULL
+                        ORDER BY q.id DESC LIMIT 1))
+            FROM patients p
+            WHERE p.id IN (SELECT patient_id FROM patient_keeper);
+
+            CREATE TABLE IF NOT EXISTS patient_merges (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                target_patient_id INTEGER NOT NULL,
+                merged_patient_id INTEGER NOT NULL,
+                merged_record JSON NOT NULL,
+                encounters_moved INTEGER NOT NULL DEFAULT 0,
+                merged_by TEXT NOT NULL,
+                reason TEXT,
+                merged_at DATETIME NOT NULL DEFAULT (datetime('now', 'localtime'))
+            );
+
+            INSERT INTO patient_merges (
+                target_patient_id, merged_patient_id, merged_record, encounters_moved,
+                merged_by, reason
+            )
+            SELECT k.patient_id, p.id,
+                   json_object(
+                       'id', p.id, 'admission_no', p.admission_no, 'national_id', p.national_id,
+                       'firstname', p.firstname, 'lastname', p.lastname,
+                       'contact_person', p.contact_person, 'telephone_1', p.telephone_1,
+                       'telephone_2', p.telephone_2, 'location', p.location,
+                       'test_type', p.test_type, 'classification', p.classification,
+                       'doctor', p.doctor
+                   ),
+                   1, 'migration', 'Shared national ID when patients became one row per person'
+            FROM patients p
+            JOIN patient_keeper k ON k.old_id = p.id
+            WHERE k.patient_id <> p.id;
+
+            CREATE TABLE encounters (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                patient_id INTEGER NOT NULL REFERENCES patients(id) ON DELETE CASCADE,
+                admission_no TEXT NOT NULL UNIQUE,
+                location TEXT,
+                test_type TEXT,
+                classification TEXT CHECK (classification IN ('inpatient', 'outpatient')),
+                doctor TEXT,
+                created_at DATETIME NOT NULL DEFAULT (datetime('now', 'localtime'))
+            );
 
-    // Apply migrations to bring the database to the latest version
-    migrations.to_latest(&mut conn)?;
+            INSERT INTO encounters (
+                id, patient_id, admission_no, location, test_type, classification, doctor, created_at
+            )
+            SELECT p.id, k.patient_id, p.admission_no, p.location, p.test_type,
+                   p.classification, p.doctor,
+                   COALESCE(
+                       (SELECT MIN(a.timestamp) FROM admissions a WHERE a.admission_no = p.admission_no),
+                       datetime('now', 'localtime')
+                   )
+            FROM patients p
+            JOIN patient_keeper k ON k.old_id = p.id;
+
+            DROP TABLE patient_keeper;
+
+            CREATE TABLE admissions_new (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                admission_no TEXT NOT NULL,
+                doctor_in_charge TEXT NOT NULL,
+                technician TEXT,
+                diabetes_test INTEGER,
+                timestamp DATETIME DEFAULT (datetime('now', 'localtime')),
+                FOREIGN KEY (admission_no) REFERENCES encounters(admission_no)
+                    ON UPDATE CASCADE
+                    ON DELETE CASCADE
+            );
+
+            INSERT INTO admissions_new (
+                id, admission_no, doctor_in_charge, technician, diabetes_test, timestamp
+            )
+            SELECT id, admission_no, doctor_in_charge, technician, diabetes_test, timestamp
+            FROM admissions;
+
+            DROP TABLE admissions;
+            ALTER TABLE admissions_new RENAME TO admissions;
+
+            DROP TABLE patients;
+            ALTER TABLE patients_new RENAME TO patients;
+
+            CREATE UNIQUE INDEX IF NOT EXISTS idx_patients_national_id
+                ON patients (national_id) WHERE national_id IS NOT NULL;
+            CREATE INDEX IF NOT EXISTS idx_patients_name ON patients (lastname, firstname);
+            CREATE INDEX IF NOT EXISTS idx_encounters_patient ON encounters (patient_id);
+            CREATE INDEX IF NOT EXISTS idx_admissions_admission_no ON admissions (admission_no);
+        ",
+        )
+        .foreign_key_check(),
+        // M16: Audit trail of merged duplicate patients (M15 already creates
+        // the table, to record the merges it makes)
+        M::up(
+            "
+            CREATE TABLE IF NOT EXISTS patient_merges (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                target_patient_id INTEGER NOT NULL,
+                merged_patient_id INTEGER NOT NULL,
+                merged_record JSON NOT NULL,
+                encounters_moved INTEGER NOT NULL DEFAULT 0,
+                merged_by TEXT NOT NULL,
+                reason TEXT,
+                merged_at DATETIME NOT NULL DEFAULT (datetime('now', 'localtime'))
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_patient_merges_target
+                ON patient_merges (target_patient_id);
+        ",
+        ),
+        // M17: Full-text index of patients, one row per encounter, kept in
+        // sync by triggers
+        M::up(
+            "
+            CREATE VIRTUAL TABLE IF NOT EXISTS patient_search USING fts5(
+                admission_no, national_id, firstname, lastname,
+                tokenize = 'unicode61 remove_diacritics 2',
+                prefix = '2 3'
+            );
+
+            INSERT INTO patient_search (rowid, admission_no, national_id, firstname, lastname)
+            SELECT e.id, e.admission_no, p.national_id, p.firstname, p.lastname
+            FROM encounters e
+            JOIN patients p ON p.id = e.patient_id;
+
+            CREATE TRIGGER IF NOT EXISTS encounters_search_insert AFTER INSERT ON encounters
+            BEGIN
+                INSERT INTO patient_search (rowid, admission_no, national_id, firstname, lastname)
+                SELECT new.id, new.admission_no, p.national_id, p.firstname, p.lastname
+                FROM patients p WHERE p.id = new.patient_id;
+            END;
+
+            CREATE TRIGGER IF NOT EXISTS encounters_search_update
+            AFTER UPDATE OF admission_no, patient_id ON encounters
+            BEGIN
+                DELETE FROM patient_search WHERE rowid = old.id;
+                INSERT INTO patient_search (rowid, admission_no, national_id, firstname, lastname)
+                SELECT new.id, new.admission_no, p.national_id, p.firstname, p.lastname
+                FROM patients p WHERE p.id = new.patient_id;
+            END;
+
+            CREATE TRIGGER IF NOT EXISTS encounters_search_delete AFTER DELETE ON encounters
+            BEGIN
+                DELETE FROM patient_search WHERE rowid = old.id;
+            END;
+
+            CREATE TRIGGER IF NOT EXISTS patients_search_update
+            AFTER UPDATE OF national_id, firstname, lastname ON patients
+            BEGIN
+                DELETE FROM patient_search
+                WHERE rowid IN (SELECT id FROM encounters WHERE patient_id = new.id);
+                INSERT INTO patient_search (rowid, admission_no, national_id, firstname, lastname)
+                SELECT e.id, e.admission_no, new.national_id, new.firstname, new.lastname
+                FROM encounters e WHERE e.patient_id = new.id;
+            END;
+        ",
+        ),
+        // M18: The cancer-cell firmware sends three OFF readings per cycle; M7
+        // left the check off on its profile
+        M::up(
+            "
+            UPDATE protocol_profiles SET off_readings_per_cycle = 3
+            WHERE name = 'cancer-cell' AND off_readings_per_cycle = 0;
+        ",
+        ),
+        // M19: Calibration the stored values were corrected with, from their session
+        M::up(
+            "
+            ALTER TABLE test_results ADD COLUMN calibration_id INTEGER
+                REFERENCES device_calibrations(id) ON DELETE SET NULL;
+
+            UPDATE test_results SET calibration_id = (
+                SELECT s.calibration_id FROM acquisition_sessions s
+                WHERE s.id = test_results.acquisition_session_id
+            )
+            WHERE acquisition_session_id IS NOT NULL;
+        ",
+        ),
+        // M20: Firmware the stored values were recorded with, from their session
+        M::up(
+            "
+            ALTER TABLE test_results ADD COLUMN firmware_name TEXT;
+            ALTER TABLE test_results ADD COLUMN firmware_version TEXT;
+
+            UPDATE test_results SET
+                firmware_name = (SELECT s.firmware_name FROM acquisition_sessions s
+                                 WHERE s.id = test_results.acquisition_session_id),
+                firmware_version = (SELECT s.firmware_version FROM acquisition_sessions s
+                                    WHERE s.id = test_results.acquisition_session_id)
+            WHERE acquisition_session_id IS NOT NULL;
+        ",
+        ),
+        // M21: Control-material runs, kept apart from patient sessions
+        M::up(
+            "
+            ALTER TABLE acquisition_sessions ADD COLUMN is_qc BOOLEAN NOT NULL DEFAULT 0;
+
+            UPDATE acquisition_sessions SET is_qc = 1
+            WHERE admission_id IS NULL
+              AND id IN (SELECT acquisition_session_id FROM qc_results);
+        ",
+        ),
+    ])
+}
+
+/// In-memory database, migrated and configured as `init_database` does.
+#[cfg(test)]
+pub fn open_test_database() -> Connection {
+    let mut conn = Connection::open_in_memory().unwrap();
+    conn.pragma_update(None, "foreign_keys", "OFF").unwrap();
+    migrations().to_latest(&mut conn).unwrap();
+    conn.pragma_update(None, "foreign_keys", "ON").unwrap();
+    conn
+}
+
+pub fn init_database(app: &AppHandle) -> Result<Connection, Box<dyn std::error::Error>> {
+    let base_dir = app.path().resolve("data", BaseDirectory::AppData)?;
+    fs::create_dir_all(&base_dir)?;
+    let db_path = base_dir.join("app.db");
+
+    let mut conn = Connection::open(&db_path)?;
+
+    // Apply migrations to bring the database to the latest version. Foreign keys
+    // stay off meanwhile: rebuilding a table drops the old one, which would
+    // otherwise cascade into every row referencing it
+    conn.pragma_update(None, "foreign_keys", "OFF")?;
+    migrations().to_latest(&mut conn)?;
 
     // Performance and safety pragmas
     conn.execute_batch(
@@ -185,6 +803,21 @@ pub fn init_database(app: &AppHandle) -> Result<Connection, Box<dyn std::error::
     ",
     )?;
 
+    let interrupted = acquisition::mark_interrupted_sessions(&conn)?;
+    if interrupted > 0 {
+        warn!(
+            "Marked {} unfinished acquisition session(s) as interrupted",
+            interrupted
+        );
+    }
+    let interrupted_runs = testrun::mark_interrupted_runs(&conn)?;
+    if interrupted_runs > 0 {
+        warn!(
+            "Marked {} unfinished test run(s) as interrupted",
+            interrupted_runs
+        );
+    }
+
     log_event(
         &conn,
         "Application successfully connected and migrated database.",
@@ -211,12 +844,15 @@ pub struct PatientData {
     pub classification: String,
     pub doctor_in_charge: Option<String>,
     pub diabetes_test: Option<Number>,
-    pub cancer_test: Option<CancerTest>,
-}
-
-#[derive(serde::Serialize, serde::Deserialize)]
-pub struct CancerTest {
-    pub voltage_off: Vec<f64>,
+    // Session that measured the cancer sample; its OFF readings are what is stored
+    #[serde(default)]
+    pub cancer_session_id: Option<i64>,
+    // Acquisition sessions that produced this result, besides the sample session
+    #[serde(default)]
+    pub session_ids: Vec<i64>,
+    // Required to save results from sessions that failed their quality check
+    #[serde(default)]
+    pub quality_override: Option<QualityOverride>,
 }
 
 #[derive(Deserialize)]
@@ -232,44 +868,182 @@ pub struct AdmissionData {
 }
 
 /* ----------------------------------------
-   SAVE PATIENT
+   PATIENTS AND ENCOUNTERS
 ----------------------------------------- */
 
-#[tauri::command]
-pub fn save_patient(db: State<'_, Database>, data: PatientData) -> Result<(), String> {
-    let conn = db.0.lock().map_err(|e| e.to_string())?;
+/// Columns read into a `PatientRecord`, from `encounters e JOIN patients p`.
+const PATIENT_RECORD_COLUMNS: &str = "
+    e.id, p.id, e.admission_no, p.national_id, p.firstname, p.lastname, e.test_type, e.location,
+    p.contact_person, p.telephone_1, p.telephone_2, e.classification, e.doctor";
+
+fn map_patient_record(row: &rusqlite::Row) -> rusqlite::Result<PatientRecord> {
+    Ok(PatientRecord {
+        id: row.get(0)?,
+        patient_id: row.get(1)?,
+        admission_no: row.get(2)?,
+        national_id: row.get(3)?,
+        firstname: row.get(4)?,
+        lastname: row.get(5)?,
+        test_type: row.get(6)?,
+        location: row.get(7)?,
+        contact_person: row.get(8)?,
+        telephone_1: row.get(9)?,
+        telephone_2: row.get(10)?,
+        classification: row.get(11)?,
+        doctor: row.get(12)?,
+    })
+}
 
-    conn.execute(
-        "
-        INSERT INTO patients (
-            admission_no, national_id, firstname, lastname,
-            contact_person, telephone_1, telephone_2,
-            classification, doctor
+fn normalized_national_id(data: &PatientData) -> Option<&str> {
+    data.national_id
+        .as_deref()
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+}
+
+/// A national ID identifies one patient, so it cannot be given to `patient_id`
+/// while another record holds it; those two records are the same person and
+/// have to be merged instead.
+fn ensure_national_id_free(
+    conn: &Connection,
+    patient_id: i64,
+    national_id: Option<&str>,
+) -> Result<(), String> {
+    let holder: Option<(i64, String, String)> = conn
+        .query_row(
+            "SELECT id, firstname, lastname FROM patients WHERE national_id = ?1 AND id != ?2",
+            params![national_id, patient_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    match holder {
+        Some((id, firstname, lastname)) => Err(format!(
+            "National ID '{}' already belongs to patient {} ({} {}). If this is the same person, merge the two patient records (merge_patients) instead.",
+            national_id.unwrap_or_default(),
+            id,
+            firstname,
+            lastname
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Creates or updates the patient and the encounter of `data.admission_no`.
+/// The patient is the one already holding that admission number, else the one
+/// with the same national ID, so a returning patient gets a new encounter
+/// rather than a second patient record. Returns (patient id, encounter id).
+pub fn upsert_patient_encounter(
+    conn: &Connection,
+    data: &PatientData,
+) -> Result<(i64, i64), String> {
+    let national_id = normalized_national_id(data);
+
+    let existing: Option<i64> = match conn
+        .query_row(
+            "SELECT patient_id FROM encounters WHERE admission_no = ?1",
+            params![data.admission_no],
+            |row| row.get(0),
         )
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
-        ON CONFLICT(admission_no) DO UPDATE SET
-            national_id = excluded.national_id,
-            firstname = excluded.firstname,
-            lastname = excluded.lastname,
-            contact_person = excluded.contact_person,
-            telephone_1 = excluded.telephone_1,
-            telephone_2 = excluded.telephone_2,
+        .optional()
+        .map_err(|e| e.to_string())?
+    {
+        Some(id) => Some(id),
+        None => conn
+            .query_row(
+                "SELECT id FROM patients WHERE national_id = ?1",
+                params![national_id],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?,
+    };
+
+    let patient_id = match existing {
+        Some(id) => {
+            ensure_national_id_free(conn, id, national_id)?;
+            conn.execute(
+                "UPDATE patients SET
+                    national_id = COALESCE(?2, national_id),
+                    firstname = ?3,
+                    lastname = ?4,
+                    contact_person = ?5,
+                    telephone_1 = ?6,
+                    telephone_2 = ?7
+                 WHERE id = ?1",
+                params![
+                    id,
+                    national_id,
+                    data.firstname,
+                    data.lastname,
+                    data.contact_person,
+                    data.telephone_1,
+                    data.telephone_2
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+            id
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO patients (
+                    national_id, firstname, lastname, contact_person, telephone_1, telephone_2
+                 ) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    national_id,
+                    data.firstname,
+                    data.lastname,
+                    data.contact_person,
+                    data.telephone_1,
+                    data.telephone_2
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+            conn.last_insert_rowid()
+        }
+    };
+
+    conn.execute(
+        "INSERT INTO encounters (
+            patient_id, admission_no, location, test_type, classification, doctor
+         ) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT(admission_no) DO UPDATE SET
+            location = COALESCE(excluded.location, encounters.location),
+            test_type = COALESCE(excluded.test_type, encounters.test_type),
             classification = excluded.classification,
-            doctor = excluded.doctor;
-        ",
+            doctor = excluded.doctor",
         params![
+            patient_id,
             data.admission_no,
-            data.national_id,
-            data.firstname,
-            data.lastname,
-            data.contact_person,
-            data.telephone_1,
-            data.telephone_2,
+            data.location,
+            data.test_type,
             data.classification,
             data.doctor_in_charge
         ],
     )
     .map_err(|e| e.to_string())?;
+    let encounter_id = conn
+        .query_row(
+            "SELECT id FROM encounters WHERE admission_no = ?1",
+            params![data.admission_no],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+
+    Ok((patient_id, encounter_id))
+}
+
+/* ----------------------------------------
+   SAVE PATIENT
+----------------------------------------- */
+
+#[tauri::command]
+pub fn save_patient(db: State<'_, Database>, data: PatientData) -> Result<(), String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    upsert_patient_encounter(&tx, &data)?;
+    tx.commit().map_err(|e| e.to_string())?;
 
     Ok(())
 }
@@ -308,31 +1082,163 @@ pub fn save_patient(db: State<'_, Database>, data: PatientData) -> Result<(), St
 //     Ok(())
 // }
 
+/// Checks the sessions whose readings are saved with an admission. Only completed
+/// patient runs not saved with another admission qualify, and runs that failed their
+/// quality check need a named override; returns the override that will be recorded.
+fn check_sessions<'a>(
+    conn: &Connection,
+    session_ids: &[i64],
+    quality_override: Option<&'a QualityOverride>,
+) -> Result<Option<&'a QualityOverride>, String> {
+    // Only completed patient runs not saved with another admission are linked
+    for id in session_ids {
+        let session: Option<(String, bool, Option<i64>)> = conn
+            .query_row(
+                "SELECT status, is_qc, admission_id FROM acquisition_sessions WHERE id = ?1",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()
+            .map_err(|e| format!("Database Error: {}", e))?;
+        match session {
+            None => return Err(format!("Acquisition session {} not found.", id)),
+            Some((_, true, _)) => {
+                return Err(format!(
+                    "Session {} is a QC run and cannot be saved with a patient.",
+                    id
+                ))
+            }
+            Some((_, _, Some(admission_id))) => {
+                return Err(format!(
+                    "Session {} is already saved with admission record {}.",
+                    id, admission_id
+                ))
+            }
+            Some((status, _, _)) if status != "completed" => {
+                return Err(format!("Session {} ended as '{}'.", id, status))
+            }
+            Some(_) => {}
+        }
+    }
+
+    // Results from runs that failed their quality check need a named override
+    let mut failed = Vec::new();
+    for id in session_ids {
+        let report =
+            quality::session_report(conn, *id).map_err(|e| format!("Database Error: {}", e))?;
+        if report.verdict == quality::Verdict::Fail {
+            let reasons: Vec<String> = report.issues.into_iter().map(|i| i.message).collect();
+            failed.push(format!("session {}: {}", id, reasons.join(" ")));
+        }
+    }
+    if failed.is_empty() {
+        Ok(None)
+    } else {
+        let Some(quality_override) =
+            quality_override.filter(|o| !o.by.trim().is_empty() && !o.reason.trim().is_empty())
+        else {
+            return Err(format!(
+                "Quality check failed ({}). An override with a name and reason is required to save.",
+                failed.join("; ")
+            ));
+        };
+        Ok(Some(quality_override))
+    }
+}
+
+/// Stamps the override on the failed sessions and logs who approved it.
+fn record_quality_override(
+    tx: &Connection,
+    session_ids: &[i64],
+    admission_no: &str,
+    quality_override: &QualityOverride,
+) -> Result<(), String> {
+    for id in session_ids {
+        tx.execute(
+            "UPDATE acquisition_sessions
+             SET quality_override_by = ?2, quality_override_reason = ?3
+             WHERE id = ?1 AND quality_verdict = 'fail'",
+            params![id, quality_override.by, quality_override.reason],
+        )
+        .map_err(|e| format!("Database Error: {}", e))?;
+    }
+    log_event(
+        tx,
+        &format!(
+            "Quality check overridden by {} for admission {}: {}",
+            quality_override.by, admission_no, quality_override.reason
+        ),
+    )
+    .map_err(|e| format!("Database Error: {}", e))
+}
+
 #[tauri::command]
 pub async fn save_admission(db: State<'_, Database>, data: AdmissionPayload) -> Result<(), String> {
     // Get database connection from the Mutex inside your Database state
     let conn = db.0.lock().map_err(|e| e.to_string())?;
 
-    conn.execute(
+    // Values are only stored from recorded sessions, never from the client
+    let samples = [
+        ("reference", &data.reference, data.reference_session_id),
+        ("cancer", &data.cancer_tests, data.cancer_session_id),
+    ];
+    let mut session_ids = data.session_ids.clone();
+    for (sample_type, json, session_id) in samples {
+        let values = results::parse_legacy(json).map_err(|e| {
+            format!(
+                "The {} readings must look like {{\"voltage_off\": [numbers]}}: {}",
+                sample_type, e
+            )
+        })?;
+        match session_id {
+            Some(id) if !session_ids.contains(&id) => session_ids.push(id),
+            Some(_) => {}
+            None if !values.is_empty() => {
+                return Err(format!(
+                    "The {} readings are not from a recorded acquisition session.",
+                    sample_type
+                ));
+            }
+            None => {}
+        }
+    }
+
+    let quality_override = check_sessions(&conn, &session_ids, data.quality_override.as_ref())?;
+
+    let tx = conn
+        .unchecked_transaction()
+        .map_err(|e| format!("Database Error: {}", e))?;
+    if let Some(quality_override) = quality_override {
+        record_quality_override(&tx, &session_ids, &data.admission_no, quality_override)?;
+    }
+
+    tx.execute(
         "INSERT INTO admissions (
             admission_no, 
             doctor_in_charge, 
             technician, 
-            diabetes_test, 
-            reference, 
-            cancer_tests
-        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            diabetes_test
+        ) VALUES (?1, ?2, ?3, ?4)",
         (
             &data.admission_no,
             &data.doctor_in_charge,
             &data.technician,
             &data.diabetes_test,
-            &data.reference,
-            &data.cancer_tests,
         ),
     )
     .map_err(|e| format!("Database Error: {}", e))?;
 
+    let admission_id = tx.last_insert_rowid();
+    for (sample_type, _, session_id) in samples {
+        if let Some(id) = session_id {
+            results::store_session_sample(&tx, admission_id, sample_type, id)
+                .map_err(|e| format!("Database Error: {}", e))?;
+        }
+    }
+    acquisition::link_sessions_to_admission(&tx, admission_id, &data.admission_no, &session_ids)
+        .map_err(|e| format!("Database Error: {}", e))?;
+    tx.commit().map_err(|e| format!("Database Error: {}", e))?;
+
     Ok(())
 }
 
@@ -371,44 +1277,22 @@ pub fn save_patient_with_admission(
     println!("=== save_patient_with_admission CALLED ===");
     let conn = db.0.lock().map_err(|e| e.to_string())?;
 
-    // --- 1. Upsert patient ---
-    conn.execute(
-        "
-        INSERT INTO patients (
-            admission_no, national_id, firstname, lastname,
-            contact_person, telephone_1, telephone_2,
-            classification, doctor
-        )
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
-        ON CONFLICT(admission_no) DO UPDATE SET
-            national_id = excluded.national_id,
-            firstname = excluded.firstname,
-            lastname = excluded.lastname,
-            contact_person = excluded.contact_person,
-            telephone_1 = excluded.telephone_1,
-            telephone_2 = excluded.telephone_2,
-            classification = excluded.classification,
-            doctor = excluded.doctor;
-        ",
-        params![
-            data.admission_no,
-            data.national_id,
-            data.firstname,
-            data.lastname,
-            data.contact_person,
-            data.telephone_1,
-            data.telephone_2,
-            data.classification,
-            data.doctor_in_charge
-        ],
-    )
-    .map_err(|e| e.to_string())?;
+    // --- 1. Readings are only stored from recorded sessions that pass their check ---
+    let mut session_ids = data.session_ids.clone();
+    if let Some(id) = data.cancer_session_id {
+        if !session_ids.contains(&id) {
+            session_ids.push(id);
+        }
+    }
+    let quality_override = check_sessions(&conn, &session_ids, data.quality_override.as_ref())?;
 
-    // --- 2. Serialize cancer_test to JSON ---
-    let cancer_json = match &data.cancer_test {
-        Some(ct) => serde_json::to_string(ct).map_err(|e| e.to_string())?,
-        None => "{}".to_string(),
-    };
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+
+    // --- 2. Upsert patient and encounter ---
+    upsert_patient_encounter(&tx, &data)?;
+    if let Some(quality_override) = quality_override {
+        record_quality_override(&tx, &session_ids, &data.admission_no, quality_override)?;
+    }
 
     // --- 3. Convert diabetes_test to f64 for SQLite ---
     let diabetes_value: Option<f64> = data
@@ -417,24 +1301,29 @@ pub fn save_patient_with_admission(
         .map(|n| n.as_f64().unwrap_or(0.0));
 
     // --- 4. Insert a new admission ---
-    conn.execute(
+    tx.execute(
         "
         INSERT INTO admissions (
             admission_no,
             doctor_in_charge,
-            cancer_tests,
             diabetes_test
         )
-        VALUES (?1, ?2, ?3, ?4)
+        VALUES (?1, ?2, ?3)
         ",
-        params![
-            data.admission_no,
-            data.doctor_in_charge,
-            cancer_json,
-            diabetes_value
-        ],
+        params![data.admission_no, data.doctor_in_charge, diabetes_value],
     )
     .map_err(|e| e.to_string())?;
+    let admission_id = tx.last_insert_rowid();
+
+    // --- 5. Store the cancer cell readings and link the sessions ---
+    if let Some(id) = data.cancer_session_id {
+        results::store_session_sample(&tx, admission_id, "cancer", id)
+            .map_err(|e| e.to_string())?;
+    }
+    acquisition::link_sessions_to_admission(&tx, admission_id, &data.admission_no, &session_ids)
+        .map_err(|e| e.to_string())?;
+
+    tx.commit().map_err(|e| e.to_string())?;
 
     Ok(())
 }
@@ -444,35 +1333,19 @@ pub fn get_all_patients(db: State<'_, Database>) -> Result<Vec<PatientRecord>, S
     let conn = db.0.lock().map_err(|e| e.to_string())?;
 
     let mut stmt = conn
-        .prepare(
+        .prepare(&format!(
             "
-            SELECT
-                id, admission_no, national_id, firstname, lastname, test_type, location,
-                contact_person, telephone_1, telephone_2,
-                classification, doctor
-            FROM patients
-            ORDER BY id DESC
+            SELECT {}
+            FROM encounters e
+            INNER JOIN patients p ON p.id = e.patient_id
+            ORDER BY e.id DESC
             ",
-        )
+            PATIENT_RECORD_COLUMNS
+        ))
         .map_err(|e| e.to_string())?;
 
     let rows = stmt
-        .query_map([], |row| {
-            Ok(PatientRecord {
-                id: row.get(0)?,
-                admission_no: row.get(1)?,
-                national_id: row.get(2)?,
-                firstname: row.get(3)?,
-                lastname: row.get(4)?,
-                test_type: row.get(5)?,
-                location: row.get(6)?,
-                contact_person: row.get(7)?,
-                telephone_1: row.get(8)?,
-                telephone_2: row.get(9)?,
-                classification: row.get(10)?,
-                doctor: row.get(11)?,
-            })
-        })
+        .query_map([], map_patient_record)
         .map_err(|e| e.to_string())?
         .collect::<Result<Vec<_>, _>>()
         .map_err(|e| e.to_string())?;
@@ -487,43 +1360,52 @@ pub fn search_patients(
 ) -> Result<Vec<PatientRecord>, String> {
     let conn = db.0.lock().map_err(|e| e.to_string())?;
 
-    let like = format!("%{}%", query);
+    let mut stmt = conn
+        .prepare(&format!(
+            "
+            SELECT {}
+            FROM encounters e
+            INNER JOIN patients p ON p.id = e.patient_id
+            {}
+            ORDER BY s.relevance ASC, p.lastname ASC, p.firstname ASC, e.id DESC
+            ",
+            PATIENT_RECORD_COLUMNS,
+            search::encounter_match_join(&query)
+        ))
+        .map_err(|e| e.to_string())?;
+
+    let rows = stmt
+        .query_map([], map_patient_record)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(rows)
+}
+
+/// Every encounter of one patient, latest first.
+#[tauri::command]
+pub fn get_patient_encounters(
+    db: State<'_, Database>,
+    patient_id: i64,
+) -> Result<Vec<PatientRecord>, String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
 
     let mut stmt = conn
-        .prepare(
+        .prepare(&format!(
             "
-            SELECT
-                id, admission_no, national_id, firstname, lastname, test_type, location,
-                contact_person, telephone_1, telephone_2,
-                classification, doctor
-            FROM patients
-            WHERE
-                admission_no LIKE ?1
-                OR national_id LIKE ?1
-                OR firstname LIKE ?1
-                OR lastname LIKE ?1
-            ORDER BY lastname ASC, firstname ASC
+            SELECT {}
+            FROM encounters e
+            INNER JOIN patients p ON p.id = e.patient_id
+            WHERE p.id = ?1
+            ORDER BY e.id DESC
             ",
-        )
+            PATIENT_RECORD_COLUMNS
+        ))
         .map_err(|e| e.to_string())?;
 
     let rows = stmt
-        .query_map([like], |row| {
-            Ok(PatientRecord {
-                id: row.get(0)?,
-                admission_no: row.get(1)?,
-                national_id: row.get(2)?,
-                firstname: row.get(3)?,
-                lastname: row.get(4)?,
-                test_type: row.get(5)?,
-                location: row.get(6)?,
-                contact_person: row.get(7)?,
-                telephone_1: row.get(8)?,
-                telephone_2: row.get(9)?,
-                classification: row.get(10)?,
-                doctor: row.get(11)?,
-            })
-        })
+        .query_map(params![patient_id], map_patient_record)
         .map_err(|e| e.to_string())?
         .collect::<Result<Vec<_>, _>>()
         .map_err(|e| e.to_string())?;
@@ -534,33 +1416,35 @@ pub fn search_patients(
 #[tauri::command]
 pub fn search_admissions_by_patient(
     db: State<'_, Database>,
-    query: String,
+    filter: AdmissionFilter,
     limit: u32,  // New parameter
     offset: u32, // New parameter
 ) -> Result<Vec<AdmissionRecord>, String> {
     let conn = db.0.lock().map_err(|e| e.to_string())?;
-
-    let like = format!("%{}%", query);
+    let (from, mut values) = filter.sql_from()?;
+    values.push(Value::Integer(limit.into()));
+    values.push(Value::Integer(offset.into()));
 
     let mut stmt = conn
-        .prepare(
+        .prepare(&format!(
             "
             SELECT 
                 a.id, a.admission_no, a.doctor_in_charge, a.technician, 
-                a.diabetes_test, a.reference, a.cancer_tests, a.timestamp,
-                p.firstname, p.lastname, p.national_id, p.classification, p.doctor
-            FROM admissions a
-            INNER JOIN patients p ON a.admission_no = p.admission_no
-            WHERE p.firstname LIKE ?1 OR p.lastname LIKE ?1 
-               OR p.admission_no LIKE ?1 OR p.national_id LIKE ?1
-            ORDER BY a.timestamp DESC
-            LIMIT ?2 OFFSET ?3 -- Added Pagination
+                a.diabetes_test, {}, {}, a.timestamp,
+                p.firstname, p.lastname, p.national_id, e.classification, e.doctor
+            {}
+            ORDER BY {}
+            LIMIT ? OFFSET ? -- Added Pagination
             ",
-        )
+            results::sample_json_sql("a.id", "reference"),
+            results::sample_json_sql("a.id", "cancer"),
+            from,
+            filter.order_by()
+        ))
         .map_err(|e| e.to_string())?;
 
     let rows = stmt
-        .query_map([like, limit.to_string(), offset.to_string()], |row| {
+        .query_map(params_from_iter(values), |row| {
             // ... (keep existing struct mapping)
             Ok(AdmissionRecord {
                 admission_id: row.get(0)?,
@@ -586,18 +1470,17 @@ pub fn search_admissions_by_patient(
 }
 
 #[tauri::command]
-pub fn get_admissions_count(db: State<'_, Database>, query: String) -> Result<u32, String> {
+pub fn get_admissions_count(
+    db: State<'_, Database>,
+    filter: AdmissionFilter,
+) -> Result<u32, String> {
     let conn = db.0.lock().map_err(|e| e.to_string())?;
-    let like = format!("%{}%", query);
+    let (from, values) = filter.sql_from()?;
 
     let count: u32 = conn
         .query_row(
-            "SELECT COUNT(*) 
-         FROM admissions a
-         INNER JOIN patients p ON a.admission_no = p.admission_no
-         WHERE p.firstname LIKE ?1 OR p.lastname LIKE ?1 
-            OR p.admission_no LIKE ?1 OR p.national_id LIKE ?1",
-            [like],
+            &format!("SELECT COUNT(*) {}", from),
+            params_from_iter(values),
             |row| row.get(0),
         )
         .map_err(|e| e.to_string())?;
@@ -617,6 +1500,15 @@ pub fn update_device_alias(
     serial_number: Option<String>,
     new_alias: String,
 ) -> Result<(), String> {
+    // Boards without a USB serial are keyed by the stand-in the watcher gave them;
+    // a NULL serial never conflicts and would insert a duplicate row
+    let serial_number = serial_number.or_else(|| {
+        arduino::connected_devices()
+            .into_iter()
+            .find(|d| d.port == port_name && d.vid as i32 == vid && d.pid as i32 == pid)
+            .and_then(|d| d.serial_number)
+    });
+
     let conn = db.0.lock().map_err(|e| e.to_string())?;
 
     // Check if the alias is set to an empty string.
@@ -635,48 +1527,251 @@ pub fn update_device_alias(
         VALUES (
             ?1, ?2, ?3, ?4, ?5
         )
-        -- Conflict Resolution uses the unique index on hardware identifiers
-        ON CONFLICT(vid, pid, serial_number) DO UPDATE SET
-            custom_name = excluded.custom_name,
-            product = excluded.product,
-            last_seen = CURRENT_TIMESTAMP;
-        ",
-        params![product, vid, pid, serial_number, alias_to_save,],
-    );
+        -- Conflict Resolution uses the unique index on hardware identifiers
+        ON CONFLICT(vid, pid, serial_number) DO UPDATE SET
+            custom_name = excluded.custom_name,
+            product = excluded.product,
+            last_seen = CURRENT_TIMESTAMP;
+        ",
+        params![product, vid, pid, serial_number, alias_to_save,],
+    );
+
+    // --- Error Handling Remains the Same ---
+    match result {
+        Ok(_) => {
+            log_event(
+                &conn,
+                &format!(
+                    "Updated alias for device (VID:{}, PID:{}, SN:{}) to '{}'. Current port: {}",
+                    vid,
+                    pid,
+                    serial_number.as_deref().unwrap_or("None"),
+                    new_alias,
+                    port_name
+                ),
+            )
+            .map_err(|e| e.to_string())?;
+            Ok(())
+        }
+        Err(e) => {
+            if e.to_string()
+                .contains("UNIQUE constraint failed: devices.custom_name")
+            {
+                return Err(format!("The name '{}' is already in use by another device. Please choose a unique name.", new_alias));
+            }
+            log_event(
+                &conn,
+                &format!("Database error during device alias update: {}", e),
+            )
+            .unwrap_or_default();
+            Err(e.to_string())
+        }
+    }
+}
+
+/// Assigns a board to the cancer or glucose tests. A manual role wins over the
+/// one the firmware reports; `None` hands the decision back to the firmware.
+#[tauri::command]
+pub fn set_device_role(
+    db: State<'_, Database>,
+    vid: i32,
+    pid: i32,
+    serial_number: Option<String>,
+    product: Option<String>,
+    role: Option<String>,
+) -> Result<(), String> {
+    let role = role
+        .map(|r| identify::normalize_role(&r).ok_or(format!("Unknown device role '{}'.", r)))
+        .transpose()?;
+    let role_source = role.as_ref().map(|_| "manual");
+
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+
+    conn.execute(
+        "INSERT INTO devices (vid, pid, serial_number, product, role, role_source)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT(vid, pid, serial_number) DO UPDATE SET
+            role = excluded.role,
+            role_source = excluded.role_source,
+            last_seen = CURRENT_TIMESTAMP",
+        params![vid, pid, serial_number, product, role, role_source],
+    )
+    .map_err(|e| e.to_string())?;
+
+    log_event(
+        &conn,
+        &format!(
+            "Set role of device (VID:{}, PID:{}, SN:{}) to '{}'",
+            vid,
+            pid,
+            serial_number.as_deref().unwrap_or("None"),
+            role.as_deref().unwrap_or("firmware default")
+        ),
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// Folds duplicate device records into `target_id`: the target keeps its own
+/// settings and takes over the duplicates' where it has none, and everything
+/// recorded under a duplicate (runs, calibrations, QC, history) moves to it.
+/// Used for boards recorded without a serial number before they had a stable
+/// identity.
+#[tauri::command]
+pub fn merge_devices(
+    db: State<'_, Database>,
+    target_id: i64,
+    duplicate_ids: Vec<i64>,
+) -> Result<(), String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+
+    let key = |id: i64| {
+        tx.query_row(
+            "SELECT vid, pid, serial_number FROM devices WHERE id = ?1",
+            params![id],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            },
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or(format!("Device {} not found.", id))
+    };
+    let (vid, pid, serial_number) = key(target_id)?;
+
+    for &duplicate_id in duplicate_ids.iter().filter(|id| **id != target_id) {
+        let (dup_vid, dup_pid, dup_serial) = key(duplicate_id)?;
+        // Calibrations and QC history only carry over between the same hardware
+        if (dup_vid, dup_pid) != (vid, pid) {
+            return Err(format!(
+                "Device {} (VID:{}, PID:{}) is different hardware from device {} (VID:{}, PID:{}) and cannot be merged into it.",
+                duplicate_id, dup_vid, dup_pid, target_id, vid, pid
+            ));
+        }
+
+        // The alias is unique, so it leaves the duplicate before the target takes it
+        let alias: Option<String> = tx
+            .query_row(
+                "SELECT custom_name FROM devices WHERE id = ?1",
+                params![duplicate_id],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        tx.execute(
+            "UPDATE devices SET custom_name = NULL WHERE id = ?1",
+            params![duplicate_id],
+        )
+        .map_err(|e| e.to_string())?;
+
+        tx.execute(
+            "UPDATE devices AS t SET
+                custom_name = COALESCE(t.custom_name, ?3),
+                product = COALESCE(t.product, d.product),
+                device_unit = COALESCE(t.device_unit, d.device_unit),
+                firmware_name = COALESCE(t.firmware_name, d.firmware_name),
+                firmware_version = COALESCE(t.firmware_version, d.firmware_version),
+                role_source = CASE WHEN t.role IS NULL THEN d.role_source ELSE t.role_source END,
+                role = COALESCE(t.role, d.role),
+                protocol_profile_id = COALESCE(t.protocol_profile_id, d.protocol_profile_id),
+                baud_rate = COALESCE(t.baud_rate, d.baud_rate),
+                last_port = COALESCE(t.last_port, d.last_port),
+                last_seen = MAX(COALESCE(t.last_seen, ''), COALESCE(d.last_seen, ''))
+             FROM (SELECT * FROM devices WHERE id = ?2) AS d
+             WHERE t.id = ?1",
+            params![target_id, duplicate_id, alias],
+        )
+        .map_err(|e| e.to_string())?;
 
-    // --- Error Handling Remains the Same ---
-    match result {
-        Ok(_) => {
-            log_event(
-                &conn,
+        for table in ["device_calibrations", "qc_results", "device_events"] {
+            tx.execute(
                 &format!(
-                    "Updated alias for device (VID:{}, PID:{}, SN:{}) to '{}'. Current port: {}",
-                    vid,
-                    pid,
-                    serial_number.as_deref().unwrap_or("None"),
-                    new_alias,
-                    port_name
+                    "UPDATE {} SET vid = ?1, pid = ?2, serial_number = ?3
+                     WHERE vid = ?4 AND pid = ?5 AND serial_number IS ?6",
+                    table
                 ),
+                params![vid, pid, serial_number, dup_vid, dup_pid, dup_serial],
             )
             .map_err(|e| e.to_string())?;
-            Ok(())
-        }
-        Err(e) => {
-            if e.to_string()
-                .contains("UNIQUE constraint failed: devices.custom_name")
-            {
-                return Err(format!("The name '{}' is already in use by another device. Please choose a unique name.", new_alias));
-            }
-            log_event(
-                &conn,
-                &format!("Database error during device alias update: {}", e),
-            )
-            .unwrap_or_default();
-            Err(e.to_string())
         }
+        tx.execute(
+            "UPDATE acquisition_sessions SET vid = ?1, pid = ?2, device_serial = ?3
+             WHERE vid = ?4 AND pid = ?5 AND device_serial IS ?6",
+            params![vid, pid, serial_number, dup_vid, dup_pid, dup_serial],
+        )
+        .map_err(|e| e.to_string())?;
+
+        tx.execute("DELETE FROM devices WHERE id = ?1", params![duplicate_id])
+            .map_err(|e| e.to_string())?;
+
+        log_event(
+            &tx,
+            &format!(
+                "Merged device {} (VID:{}, PID:{}, SN:{}) into device {} (SN:{})",
+                duplicate_id,
+                dup_vid,
+                dup_pid,
+                dup_serial.as_deref().unwrap_or("None"),
+                target_id,
+                serial_number.as_deref().unwrap_or("None")
+            ),
+        )
+        .map_err(|e| e.to_string())?;
+    }
+
+    tx.commit().map_err(|e| e.to_string())
+}
+
+/// Records a board the watcher sees: creates its row the first time, later
+/// refreshes `last_seen` and the port it sits on.
+pub fn record_seen_device(conn: &Connection, device: &UsbDevice) -> rusqlite::Result<()> {
+    // `serial_number IS ?` rather than ON CONFLICT, as in `identify::record_identity`
+    let updated = conn.execute(
+        "UPDATE devices SET
+            last_port = ?4,
+            product = COALESCE(product, ?5),
+            identity_source = COALESCE(?6, identity_source),
+            last_seen = datetime('now', 'localtime')
+         WHERE vid = ?1 AND pid = ?2 AND serial_number IS ?3",
+        params![
+            device.vid,
+            device.pid,
+            device.serial_number,
+            device.port,
+            device.product,
+            device.identity_source
+        ],
+    )?;
+
+    if updated == 0 {
+        conn.execute(
+            "INSERT INTO devices (vid, pid, serial_number, product, last_port, identity_source)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                device.vid,
+                device.pid,
+                device.serial_number,
+                device.product,
+                device.port,
+                device.identity_source
+            ],
+        )?;
     }
+    Ok(())
 }
 
+fn same_hardware(a: &UsbDevice, b: &UsbDevice) -> bool {
+    a.vid == b.vid && a.pid == b.pid && a.serial_number == b.serial_number
+}
+
+/// Every board ever recorded merged with the ones connected right now:
+/// connected boards carry their live port and status, the others the port
+/// they were last seen on.
 #[tauri::command]
 // 🛑 CRITICAL FIX: Use the 'static lifetime to ensure type consistency across the production build.
 pub fn fetch_all_known_devices<R: Runtime>(
@@ -686,6 +1781,9 @@ pub fn fetch_all_known_devices<R: Runtime>(
     // New log added to confirm if the function is even entered
     info!("COMMAND INVOKED: fetch_all_known_devices. Attempting to acquire DB lock.");
 
+    // Taken before the DB lock: the watcher holds its device list while writing
+    let mut connected = arduino::connected_devices();
+
     let conn = match db.0.lock() {
         Ok(guard) => {
             info!("DB Lock acquired successfully.");
@@ -703,7 +1801,9 @@ pub fn fetch_all_known_devices<R: Runtime>(
     let mut stmt = conn
         .prepare(
             "
-        SELECT vid, pid, serial_number, product, custom_name, device_unit
+        SELECT vid, pid, serial_number, product, custom_name, device_unit,
+            firmware_name, firmware_version, role, last_port, last_seen,
+            identity_source, id
         FROM devices
         ORDER BY last_seen DESC;
         ",
@@ -718,8 +1818,9 @@ pub fn fetch_all_known_devices<R: Runtime>(
     let device_iter = stmt
         .query_map(params![], |row| {
             Ok(UsbDevice {
-                // ... (device field mapping remains the same)
-                port: "N/A".to_string(),
+                port: row
+                    .get::<_, Option<String>>(9)?
+                    .unwrap_or_else(|| "N/A".to_string()),
                 status: "disconnected".to_string(),
                 vid: row.get(0)?,
                 pid: row.get(1)?,
@@ -728,6 +1829,14 @@ pub fn fetch_all_known_devices<R: Runtime>(
                 custom_name: row.get(4)?,
                 device_unit: row.get(5)?,
                 board_name: row.get(3).unwrap_or("N/A".to_string()),
+                firmware_name: row.get(6)?,
+                firmware_version: row.get(7)?,
+                role: row.get(8)?,
+                last_seen: row.get(10)?,
+                firmware_status: None,
+                firmware_message: None,
+                identity_source: row.get(11)?,
+                device_id: row.get(12)?,
             })
         })
         .map_err(|e| {
@@ -740,7 +1849,30 @@ pub fn fetch_all_known_devices<R: Runtime>(
     let devices: Result<Vec<UsbDevice>, rusqlite::Error> = device_iter.collect();
 
     match devices {
-        Ok(d) => {
+        Ok(mut d) => {
+            for known in d.iter_mut() {
+                if let Some(i) = connected.iter().position(|c| same_hardware(c, known)) {
+                    let live = connected.remove(i);
+                    known.port = live.port;
+                    known.status = live.status;
+                    known.board_name = live.board_name;
+                    known.firmware_name = live.firmware_name.or(known.firmware_name.take());
+                    known.firmware_version =
+                        live.firmware_version.or(known.firmware_version.take());
+                    known.role = live.role.or(known.role.take());
+                    known.identity_source = live.identity_source;
+                    known.firmware_status = live.firmware_status;
+                    known.firmware_message = live.firmware_message;
+                } else if let (Some(name), Some(version)) =
+                    (&known.firmware_name, &known.firmware_version)
+                {
+                    let (status, message) = firmware::check(name, version);
+                    known.firmware_status = Some(status.as_str().to_string());
+                    known.firmware_message = message;
+                }
+            }
+            // Boards the watcher has not written yet
+            d.extend(connected);
             info!("Successfully fetched {} known devices.", d.len());
             Ok(d)
         }
@@ -774,31 +1906,17 @@ pub fn get_patient_by_admission_no(
     let conn = db.0.lock().map_err(|e| e.to_string())?;
 
     conn.query_row(
-        "
-        SELECT 
-            id, admission_no, national_id, firstname, lastname, test_type, location,
-            contact_person, telephone_1, telephone_2, 
-            classification, doctor
-        FROM patients 
-        WHERE admission_no = ?1
-        ",
+        &format!(
+            "
+            SELECT {}
+            FROM encounters e
+            INNER JOIN patients p ON p.id = e.patient_id
+            WHERE e.admission_no = ?1
+            ",
+            PATIENT_RECORD_COLUMNS
+        ),
         params![admission_no],
-        |row| {
-            Ok(PatientRecord {
-                id: row.get(0)?,
-                admission_no: row.get(1)?,
-                national_id: row.get(2)?,
-                firstname: row.get(3)?,
-                lastname: row.get(4)?,
-                test_type: row.get(5)?,
-                location: row.get(6)?,
-                contact_person: row.get(7)?,
-                telephone_1: row.get(8)?,
-                telephone_2: row.get(9)?,
-                classification: row.get(10)?,
-                doctor: row.get(11)?,
-            })
-        },
+        map_patient_record,
     )
     .map_err(|e| e.to_string())
 }
@@ -814,28 +1932,51 @@ pub fn delete_patient_by_admission_no(
 ) -> Result<(), String> {
     let conn = db.0.lock().map_err(|e| e.to_string())?;
 
-    let count = conn
-        .execute(
-            "DELETE FROM patients WHERE admission_no = ?1",
+    let patient_id: Option<i64> = conn
+        .query_row(
+            "SELECT patient_id FROM encounters WHERE admission_no = ?1",
             params![admission_no],
+            |row| row.get(0),
         )
+        .optional()
         .map_err(|e| e.to_string())?;
-
-    if count == 0 {
+    let Some(patient_id) = patient_id else {
         return Err(format!(
             "No patient found with admission number: {}",
             admission_no
         ));
-    }
+    };
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    tx.execute(
+        "DELETE FROM encounters WHERE admission_no = ?1",
+        params![admission_no],
+    )
+    .map_err(|e| e.to_string())?;
+    // The patient goes too once this was their only encounter
+    let patient_deleted = tx
+        .execute(
+            "DELETE FROM patients
+             WHERE id = ?1 AND NOT EXISTS (SELECT 1 FROM encounters WHERE patient_id = ?1)",
+            params![patient_id],
+        )
+        .map_err(|e| e.to_string())?
+        > 0;
 
     log_event(
-        &conn,
+        &tx,
         &format!(
-            "Deleted patient and all associated admissions for admission_no: {}",
-            admission_no
+            "Deleted encounter and all associated admissions for admission_no: {}{}",
+            admission_no,
+            if patient_deleted {
+                " (patient had no other encounters and was removed)"
+            } else {
+                ""
+            }
         ),
     )
     .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
 
     Ok(())
 }
@@ -844,53 +1985,74 @@ pub fn delete_patient_by_admission_no(
 pub fn update_patient_data(db: State<'_, Database>, data: PatientData) -> Result<(), String> {
     let conn = db.0.lock().map_err(|e| e.to_string())?;
 
-    let result = conn
-        .execute(
-            "
-        UPDATE patients SET
-            national_id = ?2,
-            firstname = ?3,
-            lastname = ?4,
-            location = ?5,
-            test_type = ?6,
-            contact_person = ?7,
-            telephone_1 = ?8,
-            telephone_2 = ?9,
-            classification = ?10,
-            doctor = ?11
-        WHERE admission_no = ?1;
-        ",
-            params![
-                data.admission_no,
-                data.national_id,
-                data.firstname,
-                data.lastname,
-                data.location,
-                data.test_type,
-                data.contact_person,
-                data.telephone_1,
-                data.telephone_2,
-                data.classification,
-                data.doctor_in_charge
-            ],
+    let ids: Option<(i64, i64)> = conn
+        .query_row(
+            "SELECT id, patient_id FROM encounters WHERE admission_no = ?1",
+            params![data.admission_no],
+            |row| Ok((row.get(0)?, row.get(1)?)),
         )
+        .optional()
         .map_err(|e| e.to_string())?;
-
-    if result == 0 {
+    let Some((encounter_id, patient_id)) = ids else {
         return Err(format!(
             "Patient with admission number '{}' not found for update.",
             data.admission_no
         ));
-    }
+    };
+
+    ensure_national_id_free(&conn, patient_id, normalized_national_id(&data))?;
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    tx.execute(
+        "
+        UPDATE patients SET
+            national_id = ?2,
+            firstname = ?3,
+            lastname = ?4,
+            contact_person = ?5,
+            telephone_1 = ?6,
+            telephone_2 = ?7
+        WHERE id = ?1;
+        ",
+        params![
+            patient_id,
+            normalized_national_id(&data),
+            data.firstname,
+            data.lastname,
+            data.contact_person,
+            data.telephone_1,
+            data.telephone_2
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+    tx.execute(
+        "
+        UPDATE encounters SET
+            location = ?2,
+            test_type = ?3,
+            classification = ?4,
+            doctor = ?5
+        WHERE id = ?1;
+        ",
+        params![
+            encounter_id,
+            data.location,
+            data.test_type,
+            data.classification,
+            data.doctor_in_charge
+        ],
+    )
+    .map_err(|e| e.to_string())?;
 
     log_event(
-        &conn,
+        &tx,
         &format!(
             "Updated patient metadata for admission_no: {}",
             data.admission_no
         ),
     )
     .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
 
     Ok(())
 }
@@ -899,46 +2061,12 @@ pub fn update_patient_data(db: State<'_, Database>, data: PatientData) -> Result
 
 #[tauri::command]
 pub fn upsert_patient_metadata(db: State<'_, Database>, data: PatientData) -> Result<(), String> {
-    // 🚨 UNIQUE DEBUG LOG
-
     let conn = db.0.lock().map_err(|e| e.to_string())?;
 
-    // --- 1. Upsert patient (This is the only necessary step) ---
-    let result = conn.execute(
-        "
-        INSERT INTO patients (
-            admission_no, national_id, firstname, lastname,
-            contact_person, telephone_1, telephone_2,
-            classification, doctor
-        )
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
-        ON CONFLICT(admission_no) DO UPDATE SET
-            national_id = excluded.national_id,
-            firstname = excluded.firstname,
-            lastname = excluded.lastname,
-            contact_person = excluded.contact_person,
-            telephone_1 = excluded.telephone_1,
-            telephone_2 = excluded.telephone_2,
-            classification = excluded.classification,
-            doctor = excluded.doctor;
-        ",
-        params![
-            data.admission_no,
-            data.national_id,
-            data.firstname,
-            data.lastname,
-            data.contact_person,
-            data.telephone_1,
-            data.telephone_2,
-            data.classification,
-            data.doctor_in_charge // Maps to the 'doctor' column in the patients table
-        ],
-    );
-
-    // Check for SQL errors
-    result.map_err(|e| e.to_string())?;
-
-    // Admission creation logic is explicitly omitted here.
+    // Patient and encounter only; admission creation is explicitly omitted here.
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    upsert_patient_encounter(&tx, &data)?;
+    tx.commit().map_err(|e| e.to_string())?;
 
     Ok(())
 }
@@ -947,60 +2075,33 @@ pub fn upsert_patient_metadata(db: State<'_, Database>, data: PatientData) -> Re
 pub fn create_patient(db: State<'_, Database>, data: PatientData) -> Result<(), String> {
     let conn = db.0.lock().map_err(|e| e.to_string())?;
 
-    let result = conn.execute(
-        "
-        INSERT INTO patients (
-            admission_no,
-            national_id,
-            firstname,
-            lastname,
-            test_type,
-            location,
-            contact_person,
-            telephone_1,
-            telephone_2,
-            classification,
-            doctor
+    let exists = conn
+        .query_row(
+            "SELECT 1 FROM encounters WHERE admission_no = ?1",
+            params![data.admission_no],
+            |_| Ok(()),
         )
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
-        ",
-        params![
-            data.admission_no,
-            data.national_id,
-            data.firstname,
-            data.lastname,
-            data.test_type,
-            data.location,
-            data.contact_person,
-            data.telephone_1,
-            data.telephone_2,
-            data.classification,
-            data.doctor_in_charge
-        ],
-    );
-
-    match result {
-        Ok(_) => {
-            log_event(
-                &conn,
-                &format!(
-                    "Created new patient with admission_no '{}'",
-                    data.admission_no
-                ),
-            )
-            .map_err(|e| e.to_string())?;
-            Ok(())
-        }
-        Err(e) => {
-            if e.to_string()
-                .contains("UNIQUE constraint failed: patients.admission_no")
-            {
-                Err("Patient with this admission number already exists.".into())
-            } else {
-                Err(e.to_string())
-            }
-        }
+        .optional()
+        .map_err(|e| e.to_string())?
+        .is_some();
+    if exists {
+        return Err("Patient with this admission number already exists.".into());
     }
+
+    // A known national ID adds the admission to that patient
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let (patient_id, _) = upsert_patient_encounter(&tx, &data)?;
+    log_event(
+        &tx,
+        &format!(
+            "Created admission_no '{}' for patient {}",
+            data.admission_no, patient_id
+        ),
+    )
+    .map_err(|e| e.to_string())?;
+    tx.commit().map_err(|e| e.to_string())?;
+
+    Ok(())
 }
 
 // #[tauri::command]
@@ -1130,6 +2231,16 @@ pub fn get_app_settings(app: tauri::AppHandle) -> Result<AppSettings, String> {
     }
 }
 
+/// Baud rate configured in the settings, if setup has been completed.
+pub fn default_baud_rate(conn: &Connection) -> rusqlite::Result<Option<u32>> {
+    conn.query_row(
+        "SELECT default_baud_rate FROM settings WHERE id = 1",
+        [],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
 fn default_settings() -> AppSettings {
     AppSettings {
         theme: "system".to_string(),
@@ -1145,34 +2256,36 @@ fn default_settings() -> AppSettings {
 #[tauri::command]
 pub fn get_global_admission_stats(
     db: State<'_, Database>,
-    query: String,
+    filter: AdmissionFilter,
 ) -> Result<GlobalStats, String> {
     let conn = db.0.lock().map_err(|e| e.to_string())?;
-    let like = format!("%{}%", query);
+    let (from, values) = filter.sql_from()?;
 
-    // We use SQLite's json_each to parse the "voltage_off" array inside the JSON strings
-    // and calculate the average across ALL matching rows.
-    let stats = conn.query_row(
-        "SELECT 
-            AVG(cancer_avg) as global_cancer,
-            AVG(ref_avg) as global_ref
+    // Average of each admission's mean OFF voltage, per sample type
+    let stats = conn
+        .query_row(
+            &format!(
+                "SELECT 
+            AVG(CASE WHEN sample_type = 'cancer' THEN sample_avg END) as global_cancer,
+            AVG(CASE WHEN sample_type = 'reference' THEN sample_avg END) as global_ref
          FROM (
-            SELECT 
-                (SELECT AVG(value) FROM json_each(json_extract(a.cancer_tests, '$.voltage_off'))) as cancer_avg,
-                (SELECT AVG(value) FROM json_each(json_extract(a.reference, '$.voltage_off'))) as ref_avg
-            FROM admissions a
-            INNER JOIN patients p ON a.admission_no = p.admission_no
-            WHERE p.firstname LIKE ?1 OR p.lastname LIKE ?1 
-               OR p.admission_no LIKE ?1 OR p.national_id LIKE ?1
+            SELECT t.sample_type, AVG(v.value) as sample_avg
+            FROM test_results t
+            INNER JOIN result_values v ON v.result_id = t.id AND v.phase = 'OFF'
+            WHERE t.admission_id IN (SELECT a.id {})
+            GROUP BY t.id
          )",
-        [like],
-        |row| {
-            Ok(GlobalStats {
-                avg_cancer: row.get::<_, Option<f64>>(0)?.unwrap_or(0.0),
-                avg_reference: row.get::<_, Option<f64>>(1)?.unwrap_or(0.0),
-            })
-        },
-    ).map_err(|e| e.to_string())?;
+                from
+            ),
+            params_from_iter(values),
+            |row| {
+                Ok(GlobalStats {
+                    avg_cancer: row.get::<_, Option<f64>>(0)?.unwrap_or(0.0),
+                    avg_reference: row.get::<_, Option<f64>>(1)?.unwrap_or(0.0),
+                })
+            },
+        )
+        .map_err(|e| e.to_string())?;
 
     Ok(stats)
 }
@@ -1180,25 +2293,27 @@ pub fn get_global_admission_stats(
 #[tauri::command]
 pub fn get_latest_5_admissions(
     db: State<'_, Database>,
-    query: String,
+    filter: AdmissionFilter,
 ) -> Result<Vec<AdmissionRecord>, String> {
     let conn = db.0.lock().map_err(|e| e.to_string())?;
-    let like = format!("%{}%", query);
+    let (from, values) = filter.sql_from()?;
 
     let mut stmt = conn
-        .prepare(
-            "SELECT a.*, p.firstname, p.lastname, p.national_id, p.classification, p.doctor
-         FROM admissions a
-         INNER JOIN patients p ON a.admission_no = p.admission_no
-         WHERE p.firstname LIKE ?1 OR p.lastname LIKE ?1 
-            OR p.admission_no LIKE ?1 OR p.national_id LIKE ?1
-         ORDER BY a.timestamp DESC
+        .prepare(&format!(
+            "SELECT a.id, a.admission_no, a.doctor_in_charge, a.technician, a.diabetes_test,
+            {} as reference, {} as cancer_tests, a.timestamp,
+            p.firstname, p.lastname, p.national_id, e.classification, e.doctor
+         {}
+         ORDER BY a.timestamp DESC, a.id DESC
          LIMIT 5",
-        )
+            results::sample_json_sql("a.id", "reference"),
+            results::sample_json_sql("a.id", "cancer"),
+            from
+        ))
         .map_err(|e| e.to_string())?;
 
     let rows = stmt
-        .query_map([like], |row| {
+        .query_map(params_from_iter(values), |row| {
             Ok(AdmissionRecord {
                 admission_id: row.get("id")?,
                 admission_no: row.get("admission_no")?,
@@ -1225,3 +2340,341 @@ pub fn get_latest_5_admissions(
 
     Ok(results)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// In-memory database with the first `version` migrations applied.
+    fn at_version(version: usize) -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "foreign_keys", "OFF").unwrap();
+        migrations().to_version(&mut conn, version).unwrap();
+        conn
+    }
+
+    fn migrate(conn: &mut Connection, version: usize) {
+        migrations().to_version(conn, version).unwrap();
+    }
+
+    /// Stored values of each sample of admission `admission_id`, in order.
+    fn stored_values(conn: &Connection, admission_id: i64, sample_type: &str) -> Vec<f64> {
+        let mut stmt = conn
+            .prepare(
+                "SELECT v.value FROM test_results r JOIN result_values v ON v.result_id = r.id
+                 WHERE r.admission_id = ?1 AND r.sample_type = ?2
+                 ORDER BY v.position",
+            )
+            .unwrap();
+        stmt.query_map(params![admission_id, sample_type], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn m14_moves_legacy_readings_into_their_own_tables() {
+        let mut conn = at_version(14);
+        conn.execute_batch(
+            r#"
+            INSERT INTO patients (admission_no, firstname, lastname) VALUES ('A1', 'Jane', 'Doe');
+            INSERT INTO admissions (admission_no, doctor_in_charge, reference, cancer_tests)
+            VALUES ('A1', 'Dr Who', '{"voltage_off": [0.45, " 0.46 ", "n/a", null, 1]}', '{}'),
+                   ('A1', 'Dr Who', 'not json', '{"voltage_off": ["-0.01"]}');
+            "#,
+        )
+        .unwrap();
+
+        migrate(&mut conn, 15);
+
+        assert_eq!(stored_values(&conn, 1, "reference"), [0.45, 0.46, 1.0]);
+        assert!(stored_values(&conn, 1, "cancer").is_empty());
+        assert!(stored_values(&conn, 2, "reference").is_empty());
+        assert_eq!(stored_values(&conn, 2, "cancer"), [-0.01]);
+        // Samples without a single reading leave no empty result behind
+        let results: i64 = conn
+            .query_row("SELECT COUNT(*) FROM test_results", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(results, 2);
+    }
+
+    #[test]
+    fn m15_merges_patients_sharing_a_national_id_on_record() {
+        let mut conn = at_version(15);
+        conn.execute_batch(
+            "
+            INSERT INTO patients (admission_no, national_id, firstname, lastname, contact_person, telephone_1)
+            VALUES ('A1', ' 123 ', 'Jane', 'Doe', 'John', '0712'),
+                   ('A2', '123', 'Jane', 'Doe-Smith', NULL, NULL),
+                   ('A3', '', 'Ann', 'Lee', NULL, NULL),
+                   ('A4', NULL, 'Ann', 'Lee', NULL, NULL);
+            INSERT INTO admissions (admission_no, doctor_in_charge, reference, cancer_tests)
+            VALUES ('A1', 'Dr Who', '{}', '{}');
+            INSERT INTO test_results (admission_id, sample_type) VALUES (1, 'cancer');
+            INSERT INTO result_values (result_id, position, value) VALUES (1, 0, 0.45);
+            ",
+        )
+        .unwrap();
+
+        migrate(&mut conn, 16);
+
+        // The latest row is kept and fills its missing contacts from the older one
+        type Patient = (i64, Option<String>, String, Option<String>, Option<String>);
+        let patients: Vec<Patient> = conn
+            .prepare(
+                "SELECT id, national_id, lastname, contact_person, telephone_1
+                 FROM patients ORDER BY id",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            patients,
+            [
+                (
+                    2,
+                    Some("123".to_string()),
+                    "Doe-Smith".to_string(),
+                    Some("John".to_string()),
+                    Some("0712".to_string())
+                ),
+                (3, None, "Lee".to_string(), None, None),
+                (4, None, "Lee".to_string(), None, None),
+            ]
+        );
+
+        let encounters: Vec<(String, i64)> = conn
+            .prepare("SELECT admission_no, patient_id FROM encounters ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            encounters,
+            [
+                ("A1".to_string(), 2),
+                ("A2".to_string(), 2),
+                ("A3".to_string(), 3),
+                ("A4".to_string(), 4)
+            ]
+        );
+
+        // The merged row is kept in the audit trail
+        let (target, merged, lastname, admission_no, merged_by): (
+            i64,
+            i64,
+            String,
+            String,
+            String,
+        ) = conn
+            .query_row(
+                "SELECT target_patient_id, merged_patient_id, merged_record ->> 'lastname',
+                        merged_record ->> 'admission_no', merged_by
+                 FROM patient_merges",
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            (
+                target,
+                merged,
+                lastname.as_str(),
+                admission_no.as_str(),
+                merged_by.as_str()
+            ),
+            (2, 1, "Doe", "A1", "migration")
+        );
+
+        // Rebuilding the tables keeps the admissions and the results stored with them
+        let admission_no: String = conn
+            .query_row("SELECT admission_no FROM admissions", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(admission_no, "A1");
+        assert_eq!(stored_values(&conn, 1, "cancer"), [0.45]);
+    }
+
+    /// Admission numbers of the encounters `query` finds, sorted.
+    fn find(conn: &Connection, query: &str) -> Vec<String> {
+        let mut found: Vec<String> = conn
+            .prepare(&format!(
+                "SELECT e.admission_no FROM encounters e {}",
+                search::encounter_match_join(query)
+            ))
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn m17_indexes_encounters_and_keeps_the_index_in_sync() {
+        let mut conn = at_version(17);
+        conn.execute_batch(
+            "
+            INSERT INTO patients (id, national_id, firstname, lastname)
+            VALUES (1, '12345678', 'Zoë', 'Wanjiru');
+            INSERT INTO encounters (patient_id, admission_no) VALUES (1, 'A100');
+            ",
+        )
+        .unwrap();
+
+        migrate(&mut conn, 18);
+
+        // Existing encounters are indexed, accents and case ignored
+        assert_eq!(find(&conn, "zoe"), ["A100"]);
+        assert_eq!(find(&conn, "1234"), ["A100"]);
+
+        conn.execute(
+            "INSERT INTO encounters (patient_id, admission_no) VALUES (1, 'A200')",
+            [],
+        )
+        .unwrap();
+        assert_eq!(find(&conn, "wanj"), ["A100", "A200"]);
+
+        conn.execute("UPDATE patients SET lastname = 'Kamau' WHERE id = 1", [])
+            .unwrap();
+        assert!(find(&conn, "wanjiru").is_empty());
+        assert_eq!(find(&conn, "zoe kamau"), ["A100", "A200"]);
+
+        conn.execute(
+            "UPDATE encounters SET admission_no = 'B200' WHERE admission_no = 'A200'",
+            [],
+        )
+        .unwrap();
+        assert!(find(&conn, "A200").is_empty());
+        assert_eq!(find(&conn, "B200"), ["B200"]);
+
+        conn.execute("DELETE FROM encounters WHERE admission_no = 'B200'", [])
+            .unwrap();
+        assert_eq!(find(&conn, "kamau"), ["A100"]);
+        let indexed: i64 = conn
+            .query_row("SELECT COUNT(*) FROM patient_search", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(indexed, 1);
+    }
+
+    fn seen(port: &str, serial_number: Option<&str>, product: &str) -> UsbDevice {
+        UsbDevice {
+            port: port.to_string(),
+            vid: 0x1A86,
+            pid: 0x7523,
+            serial_number: serial_number.map(str::to_string),
+            product: Some(product.to_string()),
+            identity_source: Some("usb".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn device_rows(conn: &Connection) -> Vec<(Option<String>, Option<String>, Option<String>)> {
+        conn.prepare("SELECT serial_number, product, last_port FROM devices ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn a_seen_board_gets_one_row_that_follows_its_port() {
+        let conn = open_test_database();
+
+        record_seen_device(&conn, &seen("/dev/ttyUSB0", Some("A1B2C3"), "USB Serial")).unwrap();
+        record_seen_device(&conn, &seen("/dev/ttyUSB1", Some("A1B2C3"), "Renamed")).unwrap();
+
+        assert_eq!(
+            device_rows(&conn),
+            [(
+                Some("A1B2C3".to_string()),
+                Some("USB Serial".to_string()),
+                Some("/dev/ttyUSB1".to_string())
+            )]
+        );
+        let last_seen: Option<String> = conn
+            .query_row("SELECT last_seen FROM devices", [], |row| row.get(0))
+            .unwrap();
+        assert!(last_seen.is_some());
+    }
+
+    #[test]
+    fn a_board_without_a_serial_is_not_added_twice() {
+        let conn = open_test_database();
+
+        record_seen_device(&conn, &seen("/dev/ttyUSB0", None, "USB Serial")).unwrap();
+        record_seen_device(&conn, &seen("/dev/ttyUSB0", None, "USB Serial")).unwrap();
+        record_seen_device(&conn, &seen("/dev/ttyUSB1", Some("A1B2C3"), "USB Serial")).unwrap();
+
+        assert_eq!(
+            device_rows(&conn),
+            [
+                (
+                    None,
+                    Some("USB Serial".to_string()),
+                    Some("/dev/ttyUSB0".to_string())
+                ),
+                (
+                    Some("A1B2C3".to_string()),
+                    Some("USB Serial".to_string()),
+                    Some("/dev/ttyUSB1".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn m4_turns_role_custom_names_into_manual_roles() {
+        let mut conn = at_version(4);
+        conn.execute_batch(
+            "
+            INSERT INTO devices (vid, pid, serial_number, custom_name, role) VALUES
+                (1, 1, 'a', 'Cancer Screening Unit', NULL),
+                (1, 1, 'b', 'Ward 3 diabetes', NULL),
+                (1, 1, 'c', 'Bench 1', 'cancer'),
+                (1, 1, 'd', 'Bench 2', NULL);
+            ",
+        )
+        .unwrap();
+
+        migrate(&mut conn, 5);
+
+        let roles: Vec<(Option<String>, Option<String>)> = conn
+            .prepare("SELECT role, role_source FROM devices ORDER BY serial_number")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let role = |role: &str, source: &str| (Some(role.to_string()), Some(source.to_string()));
+        assert_eq!(
+            roles,
+            [
+                role("cancer", "manual"),
+                role("glucose", "manual"),
+                role("cancer", "firmware"),
+                (None, None)
+            ]
+        );
+    }
+}
@@ -0,0 +1,584 @@
+// src/dedup.rs
+//
+// Duplicate patients. Before patients had an identity of their own every
+// admission created a patient row, and the save paths still create a new
+// patient whenever the national ID is missing or typed differently. Likely
+// duplicates are found by national ID, name similarity and phone number and
+// offered as scored pairs; `merge_patients` folds them into one record.
+
+use std::collections::{HashMap, HashSet};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use strsim::jaro_winkler;
+use tauri::State;
+
+use crate::database::{log_event, Database};
+
+/// Pairs scoring below this are not reported unless the caller asks for them.
+/// Identical names alone score 0.8.
+const DEFAULT_MIN_SCORE: f64 = 0.75;
+
+/// Name similarity (Jaro-Winkler) worth mentioning as a reason.
+const SIMILAR_NAME: f64 = 0.9;
+
+/// Pairs returned by `find_duplicate_patients` when no limit is given.
+const DEFAULT_CANDIDATE_LIMIT: u32 = 200;
+
+/// Phone numbers are compared on their last digits, so `+254 712 345 678`
+/// and `0712345678` match.
+const PHONE_DIGITS: usize = 9;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct PatientSummary {
+    pub id: i64,
+    pub national_id: Option<String>,
+    pub firstname: String,
+    pub lastname: String,
+    pub telephone_1: Option<String>,
+    pub telephone_2: Option<String>,
+    pub encounters: u32,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DuplicateCandidate {
+    /// Suggested survivor: the record with the most encounters.
+    pub patient: PatientSummary,
+    pub duplicate: PatientSummary,
+    /// 0 to 1; 1 means the national IDs match.
+    pub score: f64,
+    pub reasons: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PatientMergeRequest {
+    pub target_id: i64,
+    pub duplicate_ids: Vec<i64>,
+    pub merged_by: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// National ID without spaces, dashes or case differences.
+fn id_key(id: Option<&str>) -> Option<String> {
+    let key: String = id?
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    (!key.is_empty()).then_some(key)
+}
+
+fn phone_key(phone: Option<&str>) -> Option<String> {
+    let digits: Vec<char> = phone?.chars().filter(char::is_ascii_digit).collect();
+    (digits.len() >= 7).then(|| {
+        digits[digits.len().saturating_sub(PHONE_DIGITS)..]
+            .iter()
+            .collect()
+    })
+}
+
+fn name_key(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+struct Candidate {
+    summary: PatientSummary,
+    id_key: Option<String>,
+    phones: Vec<String>,
+    first: String,
+    last: String,
+}
+
+impl Candidate {
+    fn new(summary: PatientSummary) -> Self {
+        Candidate {
+            id_key: id_key(summary.national_id.as_deref()),
+            phones: [&summary.telephone_1, &summary.telephone_2]
+                .into_iter()
+                .filter_map(|p| phone_key(p.as_deref()))
+                .collect(),
+            first: name_key(&summary.firstname),
+            last: name_key(&summary.lastname),
+            summary,
+        }
+    }
+
+    /// Buckets a record is compared within; pairs sharing none are never
+    /// scored, which keeps the search far from comparing every pair.
+    fn blocks(&self) -> Vec<String> {
+        let mut blocks: Vec<String> = self.phones.iter().map(|p| format!("tel:{}", p)).collect();
+        if let Some(id) = &self.id_key {
+            blocks.push(format!("id:{}", id));
+        }
+        // Initials in either order, for first and last names entered swapped
+        let mut initials = [
+            self.first.chars().next().unwrap_or(' '),
+            self.last.chars().next().unwrap_or(' '),
+        ];
+        initials.sort();
+        blocks.push(format!("name:{}{}", initials[0], initials[1]));
+        blocks
+    }
+}
+
+fn score(a: &Candidate, b: &Candidate) -> (f64, Vec<String>) {
+    let mut reasons = Vec::new();
+
+    let full = |first: &str, last: &str| format!("{} {}", first, last);
+    let name = jaro_winkler(&full(&a.first, &a.last), &full(&b.first, &b.last)).max(jaro_winkler(
+        &full(&a.first, &a.last),
+        &full(&b.last, &b.first),
+    ));
+    let same_phone = a.phones.iter().any(|p| b.phones.contains(p));
+
+    let mut score = match (&a.id_key, &b.id_key) {
+        (Some(x), Some(y)) if x == y => {
+            reasons.push("same national ID".to_string());
+            1.0
+        }
+        _ => 0.8 * name + if same_phone { 0.2 } else { 0.0 },
+    };
+    if name >= SIMILAR_NAME {
+        reasons.push(format!("similar name ({:.2})", name));
+    }
+    if same_phone {
+        reasons.push("same phone number".to_string());
+    }
+    if matches!((&a.id_key, &b.id_key), (Some(x), Some(y)) if x != y) {
+        reasons.push("different national IDs".to_string());
+        score *= 0.5;
+    }
+    (score, reasons)
+}
+
+fn load_candidates(conn: &Connection) -> rusqlite::Result<Vec<Candidate>> {
+    let mut stmt = conn.prepare(
+        "SELECT p.id, p.national_id, p.firstname, p.lastname, p.telephone_1, p.telephone_2,
+                (SELECT COUNT(*) FROM encounters e WHERE e.patient_id = p.id)
+         FROM patients p",
+    )?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok(Candidate::new(PatientSummary {
+                id: row.get(0)?,
+                national_id: row.get(1)?,
+                firstname: row.get(2)?,
+                lastname: row.get(3)?,
+                telephone_1: row.get(4)?,
+                telephone_2: row.get(5)?,
+                encounters: row.get(6)?,
+            }))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rows)
+}
+
+/// Likely duplicate pairs, best first.
+pub fn find_duplicates(
+    conn: &Connection,
+    min_score: f64,
+    limit: usize,
+) -> rusqlite::Result<Vec<DuplicateCandidate>> {
+    let candidates = load_candidates(conn)?;
+
+    let mut blocks: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, candidate) in candidates.iter().enumerate() {
+        for block in candidate.blocks() {
+            blocks.entry(block).or_default().push(i);
+        }
+    }
+
+    let mut compared = HashSet::new();
+    let mut pairs = Vec::new();
+    for members in blocks.values() {
+        for (n, &i) in members.iter().enumerate() {
+            for &j in &members[n + 1..] {
+                if !compared.insert((i.min(j), i.max(j))) {
+                    continue;
+                }
+                let (a, b) = (&candidates[i], &candidates[j]);
+                let (score, reasons) = score(a, b);
+                if score < min_score {
+                    continue;
+                }
+                let (patient, duplicate) = if (b.summary.encounters, a.summary.id)
+                    > (a.summary.encounters, b.summary.id)
+                {
+                    (b, a)
+                } else {
+                    (a, b)
+                };
+                pairs.push(DuplicateCandidate {
+                    patient: patient.summary.clone(),
+                    duplicate: duplicate.summary.clone(),
+                    score,
+                    reasons,
+                });
+            }
+        }
+    }
+
+    pairs.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then(a.patient.id.cmp(&b.patient.id))
+            .then(a.duplicate.id.cmp(&b.duplicate.id))
+    });
+    pairs.truncate(limit);
+    Ok(pairs)
+}
+
+/// Folds duplicate patients into `target_id`: their encounters, and with them
+/// every admission and result, move to the target, which fills its empty
+/// fields from them. Each merged record is kept as JSON in `patient_merges`.
+pub fn merge(conn: &Connection, request: &PatientMergeRequest) -> Result<(), String> {
+    let merged_by = request.merged_by.trim();
+    if merged_by.is_empty() {
+        return Err("The name of whoever merges the patients is required.".into());
+    }
+    let reason = request
+        .reason
+        .as_deref()
+        .map(str::trim)
+        .filter(|r| !r.is_empty());
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+
+    let snapshot = |id: i64| {
+        tx.query_row(
+            "SELECT json_object(
+                'id', id, 'national_id', national_id, 'firstname', firstname,
+                'lastname', lastname, 'contact_person', contact_person,
+                'telephone_1', telephone_1, 'telephone_2', telephone_2,
+                'created_at', created_at
+             ) FROM patients WHERE id = ?1",
+            params![id],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or(format!("Patient {} not found.", id))
+    };
+    snapshot(request.target_id)?;
+
+    for &duplicate_id in request
+        .duplicate_ids
+        .iter()
+        .filter(|id| **id != request.target_id)
+    {
+        let record = snapshot(duplicate_id)?;
+
+        // The national ID is unique, so it leaves the duplicate before the target takes it
+        let national_id: Option<String> = tx
+            .query_row(
+                "SELECT national_id FROM patients WHERE id = ?1",
+                params![duplicate_id],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        tx.execute(
+            "UPDATE patients SET national_id = NULL WHERE id = ?1",
+            params![duplicate_id],
+        )
+        .map_err(|e| e.to_string())?;
+
+        tx.execute(
+            "UPDATE patients AS t SET
+                national_id = COALESCE(t.national_id, ?3),
+                contact_person = COALESCE(t.contact_person, d.contact_person),
+                telephone_1 = COALESCE(t.telephone_1, d.telephone_1),
+                telephone_2 = COALESCE(t.telephone_2, d.telephone_2),
+                created_at = MIN(t.created_at, d.created_at)
+             FROM (SELECT * FROM patients WHERE id = ?2) AS d
+             WHERE t.id = ?1",
+            params![request.target_id, duplicate_id, national_id],
+        )
+        .map_err(|e| e.to_string())?;
+
+        let moved = tx
+            .execute(
+                "UPDATE encounters SET patient_id = ?1 WHERE patient_id = ?2",
+                params![request.target_id, duplicate_id],
+            )
+            .map_err(|e| e.to_string())?;
+
+        tx.execute("DELETE FROM patients WHERE id = ?1", params![duplicate_id])
+            .map_err(|e| e.to_string())?;
+
+        tx.execute(
+            "INSERT INTO patient_merges (
+                target_patient_id, merged_patient_id, merged_record, encounters_moved,
+                merged_by, reason
+             ) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                request.target_id,
+                duplicate_id,
+                record,
+                moved as i64,
+                merged_by,
+                reason
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+
+        log_event(
+            &tx,
+            &format!(
+                "Merged patient {} into patient {} ({} encounter(s) moved) by {}",
+                duplicate_id, request.target_id, moved, merged_by
+            ),
+        )
+        .map_err(|e| e.to_string())?;
+    }
+
+    tx.commit().map_err(|e| e.to_string())
+}
+
+/* ----------------------------------------
+   TAURI COMMANDS
+----------------------------------------- */
+
+#[tauri::command]
+pub fn find_duplicate_patients(
+    db: State<'_, Database>,
+    min_score: Option<f64>,
+    limit: Option<u32>,
+) -> Result<Vec<DuplicateCandidate>, String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+    find_duplicates(
+        &conn,
+        min_score.unwrap_or(DEFAULT_MIN_SCORE),
+        limit.unwrap_or(DEFAULT_CANDIDATE_LIMIT) as usize,
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// Folds duplicate patients into one record; see `merge`.
+#[tauri::command]
+pub fn merge_patients(db: State<'_, Database>, request: PatientMergeRequest) -> Result<(), String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+    merge(&conn, &request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patient(
+        national_id: Option<&str>,
+        first: &str,
+        last: &str,
+        phone: Option<&str>,
+    ) -> Candidate {
+        Candidate::new(PatientSummary {
+            id: 1,
+            national_id: national_id.map(str::to_string),
+            firstname: first.to_string(),
+            lastname: last.to_string(),
+            telephone_1: phone.map(str::to_string),
+            telephone_2: None,
+            encounters: 0,
+        })
+    }
+
+    #[test]
+    fn normalizes_comparison_keys() {
+        assert_eq!(id_key(Some(" ab-12 345 ")), Some("AB12345".to_string()));
+        assert_eq!(id_key(Some(" - ")), None);
+        assert_eq!(
+            phone_key(Some("+254 712 345 678")),
+            Some("712345678".to_string())
+        );
+        assert_eq!(
+            phone_key(Some("0712-345678")),
+            Some("712345678".to_string())
+        );
+        assert_eq!(phone_key(Some("12 34")), None);
+        assert_eq!(name_key("  Mary-Jane  O'NEIL "), "mary jane o neil");
+    }
+
+    #[test]
+    fn swapped_names_share_a_block() {
+        let a = patient(None, "Amina", "Otieno", None);
+        let b = patient(None, "Otieno", "Amina", Some("0712345678"));
+
+        assert_eq!(a.blocks(), ["name:ao"]);
+        assert_eq!(b.blocks(), ["tel:712345678", "name:ao"]);
+    }
+
+    #[test]
+    fn a_matching_national_id_is_certain() {
+        let (score, reasons) = score(
+            &patient(Some("12345678"), "Amina", "Otieno", None),
+            &patient(Some("1234-5678"), "A.", "Otieno-Wanjiru", None),
+        );
+
+        assert_eq!(score, 1.0);
+        assert_eq!(reasons, ["same national ID"]);
+    }
+
+    #[test]
+    fn identical_names_and_phone_add_up() {
+        let (name_only, _) = score(
+            &patient(None, "Amina", "Otieno", None),
+            &patient(None, "otieno", "AMINA", None),
+        );
+        assert!((name_only - 0.8).abs() < 1e-9);
+
+        let (with_phone, reasons) = score(
+            &patient(None, "Amina", "Otieno", Some("+254 712 345 678")),
+            &patient(None, "Amina", "Otieno", Some("0712345678")),
+        );
+        assert!((with_phone - 1.0).abs() < 1e-9);
+        assert_eq!(reasons, ["similar name (1.00)", "same phone number"]);
+    }
+
+    #[test]
+    fn different_national_ids_halve_the_score() {
+        let (score, reasons) = score(
+            &patient(Some("12345678"), "Amina", "Otieno", None),
+            &patient(Some("87654321"), "Amina", "Otieno", None),
+        );
+
+        assert!((score - 0.4).abs() < 1e-9);
+        assert_eq!(reasons, ["similar name (1.00)", "different national IDs"]);
+        assert!(score < DEFAULT_MIN_SCORE);
+    }
+
+    #[test]
+    fn unrelated_patients_score_low() {
+        let (score, reasons) = score(
+            &patient(None, "Amina", "Otieno", Some("0712345678")),
+            &patient(None, "Joseph", "Kamau", Some("0798765432")),
+        );
+
+        assert!(score < DEFAULT_MIN_SCORE, "{}", score);
+        assert!(reasons.is_empty());
+    }
+
+    /// Fully migrated in-memory database holding two records of one patient:
+    /// patient 1 with encounter A1, patient 2 with encounters A2 and A3.
+    fn duplicated_patient() -> Connection {
+        let conn = crate::database::open_test_database();
+        conn.execute_batch(
+            "
+            INSERT INTO patients (id, national_id, firstname, lastname, contact_person, telephone_1)
+            VALUES (1, NULL, 'Amina', 'Otieno', 'Joseph', NULL),
+                   (2, '12345678', 'Amina', 'Otieno', NULL, '0712345678');
+            INSERT INTO encounters (patient_id, admission_no) VALUES (1, 'A1'), (2, 'A2'), (2, 'A3');
+            INSERT INTO admissions (admission_no, doctor_in_charge) VALUES ('A2', 'Dr Mwangi');
+            ",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn request(duplicate_ids: Vec<i64>, merged_by: &str) -> PatientMergeRequest {
+        PatientMergeRequest {
+            target_id: 1,
+            duplicate_ids,
+            merged_by: merged_by.to_string(),
+            reason: Some("  ".to_string()),
+        }
+    }
+
+    fn count(conn: &Connection, sql: &str) -> i64 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn merging_moves_encounters_and_keeps_the_duplicate_on_record() {
+        let conn = duplicated_patient();
+
+        merge(&conn, &request(vec![2, 1], " Nurse Akinyi ")).unwrap();
+
+        let target: (Option<String>, Option<String>, Option<String>) = conn
+            .query_row(
+                "SELECT national_id, contact_person, telephone_1 FROM patients WHERE id = 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            target,
+            (
+                Some("12345678".to_string()),
+                Some("Joseph".to_string()),
+                Some("0712345678".to_string())
+            )
+        );
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM patients"), 1);
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM encounters WHERE patient_id = 1"
+            ),
+            3
+        );
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM admissions"), 1);
+
+        let merge: (i64, i64, i64, String, Option<String>, String) = conn
+            .query_row(
+                "SELECT target_patient_id, merged_patient_id, encounters_moved, merged_by, reason,
+                        merged_record ->> 'national_id'
+                 FROM patient_merges",
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                        row.get(5)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            merge,
+            (
+                1,
+                2,
+                2,
+                "Nurse Akinyi".to_string(),
+                None,
+                "12345678".to_string()
+            )
+        );
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM event_logs
+                 WHERE message LIKE 'Merged patient 2 into patient 1 (2 encounter(s) moved)%'"
+            ),
+            1
+        );
+    }
+
+    #[test]
+    fn a_failed_merge_changes_nothing() {
+        let conn = duplicated_patient();
+
+        assert!(merge(&conn, &request(vec![2], "  ")).is_err());
+        assert_eq!(
+            merge(&conn, &request(vec![2, 99], "Nurse Akinyi")),
+            Err("Patient 99 not found.".to_string())
+        );
+
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM patients"), 2);
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(*) FROM encounters WHERE patient_id = 2"
+            ),
+            2
+        );
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM patient_merges"), 0);
+    }
+}
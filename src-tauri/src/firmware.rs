@@ -0,0 +1,213 @@
+// src/firmware.rs
+//
+// Firmware compatibility. The matrix in `firmware_compat.json` ships with the
+// app and lists, per firmware name, the oldest version still supported, the
+// recommended one and versions known to produce bad data. Boards are checked
+// against it once they identify themselves; acquisition is refused on
+// blocked and unsupported versions.
+
+use std::cmp::Ordering;
+
+use log::error;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+const MATRIX_JSON: &str = include_str!("../firmware_compat.json");
+
+static MATRIX: Lazy<CompatibilityMatrix> = Lazy::new(|| {
+    serde_json::from_str(MATRIX_JSON).unwrap_or_else(|e| {
+        error!("Invalid firmware compatibility matrix: {}", e);
+        CompatibilityMatrix::default()
+    })
+});
+
+#[derive(Deserialize, Default, Debug)]
+struct CompatibilityMatrix {
+    firmware: Vec<FirmwareEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+struct FirmwareEntry {
+    name: String,
+    min_supported: String,
+    recommended: String,
+    #[serde(default)]
+    blocked: Vec<BlockedVersion>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BlockedVersion {
+    version: String,
+    reason: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FirmwareStatus {
+    /// At or above the recommended version.
+    Current,
+    /// Supported, but an update is recommended.
+    Outdated,
+    /// Older than the oldest supported version; acquisition is refused.
+    Unsupported,
+    /// Known to produce bad data; acquisition is refused.
+    Blocked,
+    /// Firmware not in the matrix.
+    Unknown,
+}
+
+impl FirmwareStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FirmwareStatus::Current => "current",
+            FirmwareStatus::Outdated => "outdated",
+            FirmwareStatus::Unsupported => "unsupported",
+            FirmwareStatus::Blocked => "blocked",
+            FirmwareStatus::Unknown => "unknown",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        [
+            FirmwareStatus::Current,
+            FirmwareStatus::Outdated,
+            FirmwareStatus::Unsupported,
+            FirmwareStatus::Blocked,
+            FirmwareStatus::Unknown,
+        ]
+        .into_iter()
+        .find(|s| s.as_str() == status)
+    }
+
+    /// Whether a run may be started on a board with this firmware. Versions
+    /// below the supported minimum are refused like blocked ones: nothing
+    /// vouches for their line format or calibration. Outdated firmware and
+    /// firmware missing from the matrix only warn.
+    pub fn may_acquire(&self) -> bool {
+        !matches!(self, FirmwareStatus::Blocked | FirmwareStatus::Unsupported)
+    }
+}
+
+/// Compares dotted version numbers; missing parts count as 0, so `1.2`
+/// equals `1.2.0`.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parts = |v: &str| -> Vec<u64> {
+        v.trim_start_matches(['v', 'V'])
+            .split('.')
+            .map(|p| p.parse().unwrap_or(0))
+            .collect()
+    };
+    let (a, b) = (parts(a), parts(b));
+    (0..a.len().max(b.len()))
+        .map(|i| a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0)))
+        .find(|o| o.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Status of a firmware version, with a message for the UI unless current.
+pub fn check(name: &str, version: &str) -> (FirmwareStatus, Option<String>) {
+    let Some(entry) = MATRIX
+        .firmware
+        .iter()
+        .find(|f| f.name.eq_ignore_ascii_case(name))
+    else {
+        return (
+            FirmwareStatus::Unknown,
+            Some(format!(
+                "Firmware '{}' is not in the compatibility list",
+                name
+            )),
+        );
+    };
+
+    if let Some(blocked) = entry
+        .blocked
+        .iter()
+        .find(|b| compare_versions(&b.version, version).is_eq())
+    {
+        return (
+            FirmwareStatus::Blocked,
+            Some(format!(
+                "{} {} must not be used: {}",
+                name, version, blocked.reason
+            )),
+        );
+    }
+    if compare_versions(version, &entry.min_supported).is_lt() {
+        return (
+            FirmwareStatus::Unsupported,
+            Some(format!(
+                "{} {} is no longer supported; update to {} or later",
+                name, version, entry.recommended
+            )),
+        );
+    }
+    if compare_versions(version, &entry.recommended).is_lt() {
+        return (
+            FirmwareStatus::Outdated,
+            Some(format!(
+                "{} {} is outdated; {} is recommended",
+                name, version, entry.recommended
+            )),
+        );
+    }
+    (FirmwareStatus::Current, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_dotted_versions_numerically() {
+        assert_eq!(compare_versions("1.10.0", "1.9.3"), Ordering::Greater);
+        assert_eq!(compare_versions("v1.2", "1.2.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.2.0", "1.2.0.1"), Ordering::Less);
+        assert_eq!(compare_versions("2.x", "2.0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn the_shipped_matrix_parses() {
+        assert!(!MATRIX.firmware.is_empty());
+    }
+
+    #[test]
+    fn checks_versions_against_the_matrix() {
+        assert_eq!(
+            check("nexus-cancer", "1.2.0"),
+            (FirmwareStatus::Current, None)
+        );
+        assert_eq!(check("NEXUS-CANCER", "1.3").0, FirmwareStatus::Current);
+        assert_eq!(check("nexus-cancer", "1.1.9").0, FirmwareStatus::Outdated);
+        assert_eq!(check("nexus-cancer", "0.9").0, FirmwareStatus::Unsupported);
+        assert_eq!(check("someone-else", "1.0").0, FirmwareStatus::Unknown);
+        assert_eq!(
+            check("nexus-sim", env!("CARGO_PKG_VERSION")).0,
+            FirmwareStatus::Current
+        );
+    }
+
+    #[test]
+    fn refuses_blocked_and_unsupported_firmware() {
+        let allowed: Vec<bool> = [
+            FirmwareStatus::Current,
+            FirmwareStatus::Outdated,
+            FirmwareStatus::Unsupported,
+            FirmwareStatus::Blocked,
+            FirmwareStatus::Unknown,
+        ]
+        .iter()
+        .map(FirmwareStatus::may_acquire)
+        .collect();
+
+        assert_eq!(allowed, [true, true, false, false, true]);
+    }
+
+    #[test]
+    fn parses_stored_statuses() {
+        for status in ["current", "outdated", "unsupported", "blocked", "unknown"] {
+            assert_eq!(FirmwareStatus::parse(status).unwrap().as_str(), status);
+        }
+        assert_eq!(FirmwareStatus::parse("Current"), None);
+    }
+}
@@ -0,0 +1,390 @@
+// src/history.rs
+//
+// Usage and maintenance history of each board: when it was first seen, every
+// connect and disconnect the watcher noticed, how each measurement run ended
+// and any maintenance done on it. Events are keyed by (vid, pid, serial) like
+// the rest of the per-device records.
+
+use chrono::Local;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::acquisition::TIMESTAMP_FORMAT;
+use crate::database::{log_event, Database};
+use crate::types::UsbDevice;
+
+/// Events returned by `get_device_history` when no limit is given.
+const DEFAULT_HISTORY_LIMIT: u32 = 200;
+
+#[derive(Serialize, Debug)]
+pub struct DeviceEvent {
+    pub id: i64,
+    /// `first_seen`, `connected`, `disconnected`, `run`, `error`, `timeout` or `maintenance`.
+    pub kind: String,
+    pub port: Option<String>,
+    pub acquisition_session_id: Option<i64>,
+    pub message: Option<String>,
+    pub performed_by: Option<String>,
+    pub occurred_at: String,
+}
+
+/// Counters over every acquisition run a board did.
+#[derive(Serialize, Debug, Default)]
+pub struct DeviceUsage {
+    pub runs: u32,
+    pub completed: u32,
+    /// Runs ending in an error, timeout, too few cycles or an application exit.
+    pub failures: u32,
+    pub cancelled: u32,
+    /// Total acquisition time.
+    pub hours: f64,
+    pub first_seen: Option<String>,
+    pub last_seen: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DeviceHistory {
+    pub usage: DeviceUsage,
+    /// Newest first.
+    pub events: Vec<DeviceEvent>,
+}
+
+fn now() -> String {
+    Local::now().format(TIMESTAMP_FORMAT).to_string()
+}
+
+fn insert_event(
+    conn: &Connection,
+    device: &UsbDevice,
+    kind: &str,
+    acquisition_session_id: Option<i64>,
+    message: Option<&str>,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO device_events (
+            vid, pid, serial_number, kind, port, acquisition_session_id, message, occurred_at
+         ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            device.vid,
+            device.pid,
+            device.serial_number,
+            kind,
+            device.port,
+            acquisition_session_id,
+            message,
+            now()
+        ],
+    )?;
+    Ok(())
+}
+
+/// Records that the watcher saw a board appear, preceded by `first_seen` the
+/// first time ever.
+pub fn record_connected(conn: &Connection, device: &UsbDevice) -> rusqlite::Result<()> {
+    let known: bool = conn.query_row(
+        "SELECT EXISTS(
+            SELECT 1 FROM device_events WHERE vid = ?1 AND pid = ?2 AND serial_number IS ?3
+         )",
+        params![device.vid, device.pid, device.serial_number],
+        |row| row.get(0),
+    )?;
+    if !known {
+        insert_event(conn, device, "first_seen", None, device.product.as_deref())?;
+    }
+    insert_event(conn, device, "connected", None, None)
+}
+
+pub fn record_disconnected(conn: &Connection, device: &UsbDevice) -> rusqlite::Result<()> {
+    insert_event(conn, device, "disconnected", None, None)
+}
+
+/// Records a failure that happened before any acquisition row was opened,
+/// e.g. the port could not be opened.
+pub fn record_error(conn: &Connection, device: &UsbDevice, message: &str) -> rusqlite::Result<()> {
+    insert_event(conn, device, "error", None, Some(message))
+}
+
+/// Records how an acquisition run ended, from its row. Does nothing for runs
+/// still going, runs without a known device, or runs already recorded.
+pub fn record_run_end(conn: &Connection, session_id: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO device_events (
+            vid, pid, serial_number, kind, port, acquisition_session_id, message, occurred_at
+         )
+         SELECT vid, pid, device_serial,
+                CASE status
+                    WHEN 'completed' THEN 'run'
+                    WHEN 'cancelled' THEN 'run'
+                    WHEN 'timeout' THEN 'timeout'
+                    ELSE 'error'
+                END,
+                port, id, COALESCE(status_message, status), COALESCE(ended_at, ?2)
+         FROM acquisition_sessions
+         WHERE id = ?1 AND status != 'running' AND vid IS NOT NULL AND pid IS NOT NULL
+           AND NOT EXISTS (
+               SELECT 1 FROM device_events WHERE acquisition_session_id = ?1
+           )",
+        params![session_id, now()],
+    )?;
+    Ok(())
+}
+
+pub fn device_usage(
+    conn: &Connection,
+    vid: u16,
+    pid: u16,
+    serial_number: Option<&str>,
+) -> rusqlite::Result<DeviceUsage> {
+    let mut usage = conn.query_row(
+        "SELECT
+            COUNT(*),
+            COALESCE(SUM(status = 'completed'), 0),
+            COALESCE(SUM(status IN ('error', 'timeout', 'incomplete', 'interrupted')), 0),
+            COALESCE(SUM(status = 'cancelled'), 0),
+            COALESCE(SUM(julianday(ended_at) - julianday(started_at)), 0) * 24.0
+         FROM acquisition_sessions
+         WHERE vid = ?1 AND pid = ?2 AND device_serial IS ?3 AND status != 'running'",
+        params![vid, pid, serial_number],
+        |row| {
+            Ok(DeviceUsage {
+                runs: row.get(0)?,
+                completed: row.get(1)?,
+                failures: row.get(2)?,
+                cancelled: row.get(3)?,
+                hours: row.get(4)?,
+                ..Default::default()
+            })
+        },
+    )?;
+
+    (usage.first_seen, usage.last_seen) = conn.query_row(
+        "SELECT MIN(occurred_at), MAX(occurred_at) FROM device_events
+         WHERE vid = ?1 AND pid = ?2 AND serial_number IS ?3",
+        params![vid, pid, serial_number],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    Ok(usage)
+}
+
+/* ----------------------------------------
+   TAURI COMMANDS
+----------------------------------------- */
+
+/// Usage counters and the latest events of a board.
+#[tauri::command]
+pub fn get_device_history(
+    db: State<'_, Database>,
+    vid: u16,
+    pid: u16,
+    serial_number: Option<String>,
+    limit: Option<u32>,
+) -> Result<DeviceHistory, String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+
+    let usage =
+        device_usage(&conn, vid, pid, serial_number.as_deref()).map_err(|e| e.to_string())?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, kind, port, acquisition_session_id, message, performed_by, occurred_at
+             FROM device_events
+             WHERE vid = ?1 AND pid = ?2 AND serial_number IS ?3
+             ORDER BY occurred_at DESC, id DESC
+             LIMIT ?4",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let events = stmt
+        .query_map(
+            params![
+                vid,
+                pid,
+                serial_number,
+                limit.unwrap_or(DEFAULT_HISTORY_LIMIT)
+            ],
+            |row| {
+                Ok(DeviceEvent {
+                    id: row.get(0)?,
+                    kind: row.get(1)?,
+                    port: row.get(2)?,
+                    acquisition_session_id: row.get(3)?,
+                    message: row.get(4)?,
+                    performed_by: row.get(5)?,
+                    occurred_at: row.get(6)?,
+                })
+            },
+        )
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(DeviceHistory { usage, events })
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MaintenanceEntry {
+    pub vid: u16,
+    pub pid: u16,
+    pub serial_number: Option<String>,
+    /// What was done, e.g. "Replaced electrode".
+    pub message: String,
+    #[serde(default)]
+    pub performed_by: Option<String>,
+    /// When the work was done; now when omitted.
+    #[serde(default)]
+    pub performed_at: Option<String>,
+}
+
+#[tauri::command]
+pub fn add_device_maintenance(
+    db: State<'_, Database>,
+    entry: MaintenanceEntry,
+) -> Result<(), String> {
+    if entry.message.trim().is_empty() {
+        return Err("Describe the maintenance that was done.".into());
+    }
+    let occurred_at = entry
+        .performed_at
+        .filter(|at| !at.trim().is_empty())
+        .unwrap_or_else(now);
+
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+
+    conn.execute(
+        "INSERT INTO device_events (
+            vid, pid, serial_number, kind, message, performed_by, occurred_at
+         ) VALUES (?1, ?2, ?3, 'maintenance', ?4, ?5, ?6)",
+        params![
+            entry.vid,
+            entry.pid,
+            entry.serial_number,
+            entry.message.trim(),
+            entry.performed_by,
+            occurred_at
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+
+    log_event(
+        &conn,
+        &format!(
+            "Maintenance on device (VID:{}, PID:{}, SN:{}): {}",
+            entry.vid,
+            entry.pid,
+            entry.serial_number.as_deref().unwrap_or("None"),
+            entry.message.trim()
+        ),
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::open_test_database;
+
+    fn board() -> UsbDevice {
+        UsbDevice {
+            port: "/dev/ttyUSB0".to_string(),
+            vid: 0x1A86,
+            pid: 0x7523,
+            serial_number: Some("A1B2C3".to_string()),
+            product: Some("USB Serial".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn kinds(conn: &Connection) -> Vec<String> {
+        conn.prepare("SELECT kind FROM device_events ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn a_board_is_first_seen_only_once() {
+        let conn = open_test_database();
+
+        record_connected(&conn, &board()).unwrap();
+        record_disconnected(&conn, &board()).unwrap();
+        record_connected(&conn, &board()).unwrap();
+
+        assert_eq!(
+            kinds(&conn),
+            ["first_seen", "connected", "disconnected", "connected"]
+        );
+    }
+
+    #[test]
+    fn counts_runs_by_how_they_ended() {
+        let conn = open_test_database();
+        conn.execute_batch(
+            "
+            INSERT INTO acquisition_sessions (
+                port, vid, pid, device_serial, baud_rate, status, started_at, ended_at
+            ) VALUES
+                ('/dev/ttyUSB0', 6790, 29987, 'A1B2C3', 9600, 'completed',
+                 '2026-01-05 09:00:00.000', '2026-01-05 10:00:00.000'),
+                ('/dev/ttyUSB0', 6790, 29987, 'A1B2C3', 9600, 'timeout',
+                 '2026-01-05 11:00:00.000', '2026-01-05 11:30:00.000'),
+                ('/dev/ttyUSB0', 6790, 29987, 'A1B2C3', 9600, 'interrupted',
+                 '2026-01-05 12:00:00.000', '2026-01-05 12:15:00.000'),
+                ('/dev/ttyUSB0', 6790, 29987, 'A1B2C3', 9600, 'cancelled',
+                 '2026-01-05 13:00:00.000', '2026-01-05 13:15:00.000'),
+                ('/dev/ttyUSB0', 6790, 29987, 'A1B2C3', 9600, 'running',
+                 '2026-01-05 14:00:00.000', NULL),
+                ('/dev/ttyUSB0', 6790, 29987, 'OTHER', 9600, 'completed',
+                 '2026-01-05 09:00:00.000', '2026-01-05 10:00:00.000');
+            ",
+        )
+        .unwrap();
+        record_connected(&conn, &board()).unwrap();
+
+        let usage = device_usage(&conn, 0x1A86, 0x7523, Some("A1B2C3")).unwrap();
+
+        assert_eq!(
+            (usage.runs, usage.completed, usage.failures, usage.cancelled),
+            (4, 1, 2, 1)
+        );
+        assert!((usage.hours - 2.0).abs() < 1e-6, "{}", usage.hours);
+        assert!(usage.first_seen.is_some());
+        assert!(usage.first_seen <= usage.last_seen);
+    }
+
+    #[test]
+    fn records_the_end_of_a_run_once() {
+        let conn = open_test_database();
+        conn.execute(
+            "INSERT INTO acquisition_sessions (
+                port, vid, pid, device_serial, baud_rate, status, status_message, started_at, ended_at
+             ) VALUES ('/dev/ttyUSB0', 6790, 29987, 'A1B2C3', 9600, 'timeout', 'No data for 10 s',
+                       '2026-01-05 09:00:00.000', '2026-01-05 09:01:00.000')",
+            [],
+        )
+        .unwrap();
+
+        record_run_end(&conn, 1).unwrap();
+        record_run_end(&conn, 1).unwrap();
+
+        let events: Vec<(String, Option<String>, String)> = conn
+            .prepare("SELECT kind, message, occurred_at FROM device_events")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            events,
+            [(
+                "timeout".to_string(),
+                Some("No data for 10 s".to_string()),
+                "2026-01-05 09:01:00.000".to_string()
+            )]
+        );
+    }
+}
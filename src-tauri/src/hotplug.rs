@@ -0,0 +1,64 @@
+// src/hotplug.rs
+//
+// Event-driven board detection. On Linux the kernel announces tty devices
+// coming and going over udev's netlink socket, so a plugged-in board can be
+// picked up immediately instead of at the next poll. Other platforms report
+// `Unsupported` and the watcher keeps polling.
+
+use std::io;
+use std::sync::atomic::AtomicBool;
+
+/// How often `watch` wakes up to check whether it should stop.
+#[cfg(target_os = "linux")]
+const STOP_CHECK_INTERVAL_MS: i32 = 500;
+
+/// Blocks until `keep_running` turns false, calling `on_change` after every
+/// burst of tty add/remove events.
+#[cfg(target_os = "linux")]
+pub fn watch(keep_running: &AtomicBool, mut on_change: impl FnMut()) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    use std::sync::atomic::Ordering;
+
+    let context = libudev::Context::new()?;
+    let mut monitor = libudev::Monitor::new(&context)?;
+    monitor.match_subsystem("tty")?;
+    let mut socket = monitor.listen()?;
+
+    while keep_running.load(Ordering::Relaxed) {
+        let mut fds = libc::pollfd {
+            fd: socket.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `fds` is a single valid pollfd that outlives the call.
+        let ready = unsafe { libc::poll(&mut fds, 1, STOP_CHECK_INTERVAL_MS) };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+
+        // Drain the whole burst (a board brings several tty events) and rescan once
+        let mut changed = false;
+        while let Some(event) = socket.receive_event() {
+            if matches!(
+                event.event_type(),
+                libudev::EventType::Add | libudev::EventType::Remove
+            ) {
+                changed = true;
+            }
+        }
+        if changed {
+            on_change();
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn watch(_keep_running: &AtomicBool, _on_change: impl FnMut()) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
@@ -0,0 +1,356 @@
+// src/identify.rs
+//
+// Identification handshake run when a board appears. The CH340, CP210x and
+// FTDI bridges we accept are used by plenty of non-Arduino hardware, so the
+// USB VID alone says nothing about what firmware is on the other end. A board
+// only counts as one of ours once it answers the identify command (or prints
+// a matching boot banner).
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::reader::{LineReader, ReadEvent};
+use crate::transport::Transport;
+use crate::types::UsbDevice;
+
+/// Sent to the firmware to ask who it is.
+pub const DEFAULT_IDENTIFY_COMMAND: &str = "ID?";
+
+/// Identify reply or boot banner, e.g. `ID nexus-cancer 1.2.0 cancer`.
+/// Named groups: `name` and `version` (required), `role` (optional).
+pub const DEFAULT_IDENTIFY_PATTERN: &str =
+    r"(?i)^ID\s+(?P<name>[\w.-]+)\s+v?(?P<version>\d+(?:\.\d+)*)(?:\s+(?P<role>[\w-]+))?";
+
+/// Unique board id a firmware may add to its identify reply, e.g. `uid=5A3F09`.
+/// Only consulted for boards whose USB bridge reports no serial number.
+pub const FIRMWARE_UID_PATTERN: &str = r"(?i)\buid=(?P<uid>[\w:-]+)";
+
+static FIRMWARE_UID: Lazy<Regex> =
+    Lazy::new(|| Regex::new(FIRMWARE_UID_PATTERN).expect("valid firmware uid pattern"));
+
+/// How long a board gets to answer, including its reboot after the DTR reset.
+pub const IDENTIFY_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// Roles a board can be assigned to.
+pub const ROLES: [&str; 2] = ["cancer", "glucose"];
+
+/// What a board reported about its firmware.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FirmwareIdentity {
+    pub name: String,
+    pub version: String,
+    /// One of `ROLES`, if the firmware announced a role we know.
+    pub role: Option<String>,
+    /// Board id reported next to the version (`uid=`), if any.
+    #[serde(default)]
+    pub uid: Option<String>,
+}
+
+/// Maps the role label a firmware prints onto one of `ROLES`.
+pub fn normalize_role(label: &str) -> Option<String> {
+    let label = label.trim().to_lowercase();
+    ROLES
+        .iter()
+        .find(|role| label.starts_with(*role))
+        .map(|role| role.to_string())
+}
+
+pub fn parse_identity(pattern: &Regex, line: &str) -> Option<FirmwareIdentity> {
+    let caps = pattern.captures(line.trim())?;
+    Some(FirmwareIdentity {
+        name: caps.name("name")?.as_str().to_string(),
+        version: caps.name("version")?.as_str().to_string(),
+        role: caps.name("role").and_then(|r| normalize_role(r.as_str())),
+        uid: FIRMWARE_UID
+            .captures(line)
+            .map(|caps| caps["uid"].to_string()),
+    })
+}
+
+/// Physical USB location of a serial port, e.g. `1-1.2` for the second port
+/// of the hub on bus 1. Only known on Linux.
+pub fn usb_location(port: &str) -> Option<String> {
+    let name = Path::new(port).file_name()?.to_str()?;
+    let path = std::fs::canonicalize(format!("/sys/class/tty/{}/device", name)).ok()?;
+    // The last `<bus>-<port>[.<port>…]` component is the USB device itself
+    path.components()
+        .rev()
+        .filter_map(|c| c.as_os_str().to_str())
+        .find(|c| {
+            c.split_once('-').is_some_and(|(bus, ports)| {
+                !bus.is_empty()
+                    && bus.chars().all(|ch| ch.is_ascii_digit())
+                    && !ports.is_empty()
+                    && ports.chars().all(|ch| ch.is_ascii_digit() || ch == '.')
+            })
+        })
+        .map(str::to_string)
+}
+
+/// Stand-in serial number for a board whose USB bridge reports none, so it
+/// still gets a device record of its own: the physical USB location where
+/// known, otherwise the port name (which Windows and macOS derive from the
+/// location for such bridges). A firmware `uid` replaces it once identified.
+pub fn fallback_serial(port: &str) -> (String, &'static str) {
+    match usb_location(port) {
+        Some(location) => (format!("loc:{}", location), "location"),
+        None => (format!("port:{}", port), "port"),
+    }
+}
+
+/// Reads until `accept` recognises a line, re-sending `command` whenever the
+/// line goes quiet: a freshly reset board ignores input until its bootloader
+/// is done.
+pub fn probe<T: Transport, R>(
+    reader: &mut LineReader<T>,
+    command: &str,
+    timeout: Duration,
+    mut accept: impl FnMut(&str) -> Option<R>,
+) -> Option<R> {
+    let deadline = Instant::now() + timeout;
+    let request = format!("{}\n", command.trim());
+    let _ = reader.transport_mut().write_all(request.as_bytes());
+
+    while Instant::now() < deadline {
+        match reader.next_event() {
+            Ok(ReadEvent::Line(line)) => {
+                if let Some(found) = accept(&line) {
+                    return Some(found);
+                }
+            }
+            Ok(ReadEvent::Idle) => {
+                let _ = reader.transport_mut().write_all(request.as_bytes());
+            }
+            Ok(ReadEvent::Closed { .. }) | Err(_) => return None,
+        }
+    }
+
+    None
+}
+
+/// Stores what a board reported on its `devices` row, creating the row if
+/// the board has never been seen before.
+pub fn record_identity(
+    conn: &Connection,
+    device: &UsbDevice,
+    identity: &FirmwareIdentity,
+) -> rusqlite::Result<()> {
+    // `serial_number IS ?` rather than ON CONFLICT: boards without a serial
+    // never conflict on the unique index and would get a new row every time.
+    let updated = conn.execute(
+        "UPDATE devices SET
+            firmware_name = ?4, firmware_version = ?5,
+            role = CASE WHEN role_source = 'manual' THEN role ELSE COALESCE(?6, role) END,
+            role_source = CASE
+                WHEN role_source = 'manual' THEN role_source
+                WHEN ?6 IS NOT NULL THEN 'firmware'
+                ELSE role_source
+            END,
+            last_seen = CURRENT_TIMESTAMP
+         WHERE vid = ?1 AND pid = ?2 AND serial_number IS ?3",
+        params![
+            device.vid,
+            device.pid,
+            device.serial_number,
+            identity.name,
+            identity.version,
+            identity.role
+        ],
+    )?;
+
+    if updated == 0 {
+        conn.execute(
+            "INSERT INTO devices (
+                vid, pid, serial_number, product, firmware_name, firmware_version,
+                role, role_source
+             ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, CASE WHEN ?7 IS NULL THEN NULL ELSE 'firmware' END)",
+            params![
+                device.vid,
+                device.pid,
+                device.serial_number,
+                device.product,
+                identity.name,
+                identity.version,
+                identity.role
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Role stored for a board, whether assigned manually or by its firmware.
+pub fn device_role(conn: &Connection, device: &UsbDevice) -> rusqlite::Result<Option<String>> {
+    conn.query_row(
+        "SELECT role FROM devices
+         WHERE vid = ?1 AND pid = ?2 AND serial_number IS ?3 AND role IS NOT NULL
+         ORDER BY last_seen DESC LIMIT 1",
+        params![device.vid, device.pid, device.serial_number],
+        |row| row.get(0),
+    )
+    .optional()
+    .map(Option::flatten)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{MemoryEvent, MemoryTransport};
+
+    fn default_pattern() -> Regex {
+        Regex::new(DEFAULT_IDENTIFY_PATTERN).unwrap()
+    }
+
+    #[test]
+    fn parses_the_identify_reply() {
+        assert_eq!(
+            parse_identity(
+                &default_pattern(),
+                "ID nexus-cancer v1.2.0 Cancer-Cell uid=5A:3F-09\r"
+            ),
+            Some(FirmwareIdentity {
+                name: "nexus-cancer".to_string(),
+                version: "1.2.0".to_string(),
+                role: Some("cancer".to_string()),
+                uid: Some("5A:3F-09".to_string()),
+            })
+        );
+        assert_eq!(
+            parse_identity(&default_pattern(), "id nexus-glucose 2"),
+            Some(FirmwareIdentity {
+                name: "nexus-glucose".to_string(),
+                version: "2".to_string(),
+                role: None,
+                uid: None,
+            })
+        );
+    }
+
+    #[test]
+    fn ignores_unknown_roles_and_other_lines() {
+        let identity = parse_identity(&default_pattern(), "ID nexus 1.0 heater").unwrap();
+        assert_eq!(identity.role, None);
+
+        for line in ["Output Voltage (ON): 1.1 V", "ID nexus", "ID  "] {
+            assert_eq!(parse_identity(&default_pattern(), line), None, "{}", line);
+        }
+    }
+
+    #[test]
+    fn normalizes_role_labels() {
+        assert_eq!(normalize_role(" GLUCOSE "), Some("glucose".to_string()));
+        assert_eq!(normalize_role("cancer-cell"), Some("cancer".to_string()));
+        assert_eq!(normalize_role("blood"), None);
+    }
+
+    #[test]
+    fn probe_retries_until_the_board_answers() {
+        let mut asked = 0;
+        let transport = MemoryTransport::new([
+            MemoryEvent::Data(b"bootloader\r\n".to_vec()),
+            MemoryEvent::Delay(Duration::from_millis(1100)),
+        ])
+        .with_responder(Box::new(move |command| {
+            asked += 1;
+            if asked < 2 {
+                Vec::new()
+            } else {
+                vec![format!("{} reply", command)]
+            }
+        }));
+        let mut reader = LineReader::new(transport);
+
+        let reply = probe(&mut reader, "ID?", Duration::from_secs(3), |line| {
+            line.ends_with("reply").then(|| line.to_string())
+        });
+
+        assert_eq!(reply.as_deref(), Some("ID? reply"));
+    }
+
+    #[test]
+    fn probe_gives_up_when_the_board_disconnects() {
+        let mut reader = LineReader::new(MemoryTransport::new([MemoryEvent::Disconnect]));
+
+        assert_eq!(
+            probe(&mut reader, "ID?", Duration::from_secs(3), |_| Some(())),
+            None
+        );
+    }
+
+    fn board() -> UsbDevice {
+        UsbDevice {
+            port: "/dev/ttyUSB0".to_string(),
+            vid: 0x1A86,
+            pid: 0x7523,
+            serial_number: Some("A1B2C3".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn reporting(role: Option<&str>) -> FirmwareIdentity {
+        FirmwareIdentity {
+            name: "nexus".to_string(),
+            version: "1.2.0".to_string(),
+            role: role.map(str::to_string),
+            uid: None,
+        }
+    }
+
+    fn role_and_source(conn: &Connection) -> (Option<String>, Option<String>) {
+        conn.query_row("SELECT role, role_source FROM devices", [], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn takes_the_role_the_firmware_reports() {
+        let conn = crate::database::open_test_database();
+        assert_eq!(device_role(&conn, &board()).unwrap(), None);
+
+        record_identity(&conn, &board(), &reporting(Some("cancer"))).unwrap();
+        assert_eq!(
+            device_role(&conn, &board()).unwrap().as_deref(),
+            Some("cancer")
+        );
+
+        // Firmware that reports no role leaves the known one alone
+        record_identity(&conn, &board(), &reporting(None)).unwrap();
+        assert_eq!(
+            role_and_source(&conn),
+            (Some("cancer".to_string()), Some("firmware".to_string()))
+        );
+
+        record_identity(&conn, &board(), &reporting(Some("glucose"))).unwrap();
+        assert_eq!(
+            device_role(&conn, &board()).unwrap().as_deref(),
+            Some("glucose")
+        );
+    }
+
+    #[test]
+    fn a_manual_role_wins_over_the_firmware() {
+        let conn = crate::database::open_test_database();
+        conn.execute(
+            "INSERT INTO devices (vid, pid, serial_number, role, role_source)
+             VALUES (?1, ?2, 'A1B2C3', 'glucose', 'manual')",
+            params![0x1A86, 0x7523],
+        )
+        .unwrap();
+
+        record_identity(&conn, &board(), &reporting(Some("cancer"))).unwrap();
+
+        assert_eq!(
+            role_and_source(&conn),
+            (Some("glucose".to_string()), Some("manual".to_string()))
+        );
+        let firmware: Option<String> = conn
+            .query_row("SELECT firmware_version FROM devices", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(firmware.as_deref(), Some("1.2.0"));
+    }
+}
@@ -1,11 +1,30 @@
 // src/main.rs
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod acquisition;
 mod arduino;
+mod baud;
+mod calibration;
 mod database;
+mod dedup;
 mod errordefs;
+mod firmware;
+mod history;
+mod hotplug;
+mod identify;
 mod logging;
+mod measurement;
+mod protocol;
+mod qc;
+mod quality;
+mod reader;
+mod results;
+mod search;
+mod session;
 mod setup;
+mod simulator;
+mod testrun;
+mod transport;
 mod types;
 mod user;
 
@@ -16,23 +35,107 @@ use tauri_plugin_dialog::init;
 
 use tauri::Listener;
 
+use acquisition::{get_acquisition_lines, get_acquisition_sessions};
 use arduino::{
-    scan_arduino_now, start_arduino_watcher, start_reading_from_port, stop_arduino_watcher,
-    stop_reading_from_port,
+    identify_device, scan_arduino_now, send_device_command, start_arduino_watcher,
+    start_reading_from_port, start_test_run, stop_arduino_watcher, stop_reading_from_port,
+    stop_test_run,
 };
+use calibration::{calibrate_device, get_device_calibrations};
 use database::{
     create_patient, delete_patient_by_admission_no, fetch_all_known_devices, get_admissions_count,
     get_all_patients, get_app_settings, get_global_admission_stats, get_latest_5_admissions,
-    get_logs, get_patient_by_admission_no, get_patient_count, init_database, log_event_command,
-    save_admission, save_patient, save_patient_with_admission, search_admissions_by_patient,
-    search_patients, update_device_alias, update_patient_data, upsert_patient_metadata, Database,
+    get_logs, get_patient_by_admission_no, get_patient_count, get_patient_encounters,
+    init_database, log_event_command, merge_devices, save_admission, save_patient,
+    save_patient_with_admission, search_admissions_by_patient, search_patients, set_device_role,
+    update_device_alias, update_patient_data, upsert_patient_metadata, Database,
 };
+use dedup::{find_duplicate_patients, merge_patients};
+use history::{add_device_maintenance, get_device_history};
 use logging::init_logger;
+use protocol::{
+    delete_protocol_profile, get_protocol_profiles, save_protocol_profile, set_device_protocol,
+};
+use qc::{get_qc_chart, get_qc_materials, get_qc_status, record_qc_run, save_qc_material};
+use quality::assess_acquisition_session;
+use results::get_admission_results;
+use session::get_session_state;
 use setup::{get_default_paths, save_setup_settings, set_setup_complete};
+#[cfg(debug_assertions)]
+use simulator::{
+    add_simulated_device, export_session_capture, list_simulated_devices, remove_simulated_device,
+};
+use testrun::get_test_run;
 use user::get_current_user;
 
 static SHUTDOWN_IN_PROGRESS: Lazy<AtomicBool> = Lazy::new(|| AtomicBool::new(false));
 
+/// Handler for every command of the app plus `extra` ones.
+macro_rules! app_handler {
+    ($($extra:ident),* $(,)?) => {
+        tauri::generate_handler![
+            get_logs,
+            log_event_command,
+            create_patient,
+            start_arduino_watcher,
+            stop_arduino_watcher,
+            scan_arduino_now,
+            start_reading_from_port,
+            stop_reading_from_port,
+            set_setup_complete,
+            save_admission,
+            save_patient,
+            save_patient_with_admission,
+            get_current_user,
+            search_patients,
+            get_all_patients,
+            search_admissions_by_patient,
+            update_device_alias,
+            fetch_all_known_devices,
+            get_patient_count,
+            get_patient_by_admission_no,
+            get_patient_encounters,
+            delete_patient_by_admission_no,
+            save_setup_settings,
+            update_patient_data,
+            upsert_patient_metadata,
+            get_default_paths,
+            get_app_settings,
+            get_admissions_count,
+            get_global_admission_stats,
+            get_latest_5_admissions,
+            get_acquisition_sessions,
+            get_acquisition_lines,
+            get_protocol_profiles,
+            save_protocol_profile,
+            delete_protocol_profile,
+            set_device_protocol,
+            send_device_command,
+            identify_device,
+            set_device_role,
+            get_session_state,
+            start_test_run,
+            stop_test_run,
+            get_test_run,
+            assess_acquisition_session,
+            calibrate_device,
+            get_device_calibrations,
+            get_qc_materials,
+            save_qc_material,
+            record_qc_run,
+            get_qc_chart,
+            get_qc_status,
+            get_device_history,
+            add_device_maintenance,
+            merge_devices,
+            get_admission_results,
+            find_duplicate_patients,
+            merge_patients,
+            $($extra),*
+        ]
+    };
+}
+
 fn main() {
     log::info!("=== TAURI SETUP RUNNING ===");
 
@@ -53,7 +156,7 @@ fn main() {
         }
     });
 
-    tauri::Builder::default()
+    let builder = tauri::Builder::default()
         .plugin(single_instance_plugin)
         .plugin(tauri_plugin_process::init())
         .plugin(tauri_plugin_updater::Builder::new().build())
@@ -157,38 +260,20 @@ fn main() {
             });
 
             Ok(())
-        })
-        .invoke_handler(tauri::generate_handler![
-            get_logs,
-            log_event_command,
-            create_patient,
-            start_arduino_watcher,
-            stop_arduino_watcher,
-            scan_arduino_now,
-            start_reading_from_port,
-            stop_reading_from_port,
-            set_setup_complete,
-            save_admission,
-            save_patient,
-            save_patient_with_admission,
-            get_current_user,
-            search_patients,
-            get_all_patients,
-            search_admissions_by_patient,
-            update_device_alias,
-            fetch_all_known_devices,
-            get_patient_count,
-            get_patient_by_admission_no,
-            delete_patient_by_admission_no,
-            save_setup_settings,
-            update_patient_data,
-            upsert_patient_metadata,
-            get_default_paths,
-            get_app_settings,
-            get_admissions_count,
-            get_global_admission_stats,
-            get_latest_5_admissions
-        ])
+        });
+
+    // The simulator commands only exist in debug builds
+    #[cfg(debug_assertions)]
+    let builder = builder.invoke_handler(app_handler![
+        list_simulated_devices,
+        add_simulated_device,
+        remove_simulated_device,
+        export_session_capture
+    ]);
+    #[cfg(not(debug_assertions))]
+    let builder = builder.invoke_handler(app_handler![]);
+
+    builder
         .run(tauri::generate_context!())
         .expect("Error while running Tauri application");
 }
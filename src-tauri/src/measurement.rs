@@ -0,0 +1,302 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Firmware line carrying a reading. Named groups: `value` (required),
+/// `phase` and `unit` (optional).
+pub const DEFAULT_MEASUREMENT_PATTERN: &str = r"^Output Voltage\s*\((?P<phase>(?i:ON|OFF))\)\s*:\s*(?P<value>[-+]?\d*\.?\d+)\s*(?P<unit>\S+)?";
+
+/// Firmware line announcing a cycle. Named group: `cycle`.
+pub const DEFAULT_CYCLE_PATTERN: &str = r"(?i)^cycle\s*[#:]?\s*(?P<cycle>\d+)\b";
+
+/// Text the firmware prints once the run is over.
+pub const DEFAULT_END_MARKER: &str = "cycles completed";
+
+/// Excitation phase reported by the firmware for a voltage reading.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Phase {
+    On,
+    Off,
+}
+
+impl Phase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::On => "ON",
+            Phase::Off => "OFF",
+        }
+    }
+
+    fn from_label(label: &str) -> Option<Self> {
+        match label.trim().to_ascii_uppercase().as_str() {
+            "ON" => Some(Phase::On),
+            "OFF" => Some(Phase::Off),
+            _ => None,
+        }
+    }
+}
+
+/// A single typed reading parsed from one line of Arduino output.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Measurement {
+    pub phase: Phase,
+    pub voltage: f64,
+    /// 1-based index of the ON/OFF cycle this reading belongs to.
+    pub cycle: u32,
+    pub unit: String,
+}
+
+/// What a single line of firmware output means to the acquisition loop.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParsedLine {
+    Measurement(Measurement),
+    /// Firmware announced the start of a cycle, e.g. "Cycle 2".
+    CycleStart(u32),
+    /// Firmware reported the end of the run ("... cycles completed").
+    Completed,
+    /// Banner, debug output or anything we don't understand.
+    Other,
+}
+
+/// Stateful parser for the firmware's text protocol.
+///
+/// With the default patterns, lines look like `Output Voltage (ON): 1.1046 V`.
+/// The cycle index is taken from explicit "Cycle N" lines when the firmware
+/// prints them, otherwise an ON reading that follows an OFF reading starts a
+/// new cycle. Readings without a phase (single-value firmware) count as OFF.
+#[derive(Debug)]
+pub struct MeasurementParser {
+    measurement: Regex,
+    cycle_header: Option<Regex>,
+    end_marker: String,
+    cycle: u32,
+    last_phase: Option<Phase>,
+}
+
+impl Default for MeasurementParser {
+    fn default() -> Self {
+        Self::with_patterns(
+            DEFAULT_MEASUREMENT_PATTERN,
+            Some(DEFAULT_CYCLE_PATTERN),
+            DEFAULT_END_MARKER,
+        )
+        .expect("built-in measurement patterns are valid")
+    }
+}
+
+impl MeasurementParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_patterns(
+        measurement: &str,
+        cycle_header: Option<&str>,
+        end_marker: &str,
+    ) -> Result<Self, regex::Error> {
+        Ok(Self {
+            measurement: Regex::new(measurement)?,
+            cycle_header: cycle_header.map(Regex::new).transpose()?,
+            end_marker: end_marker.to_lowercase(),
+            cycle: 0,
+            last_phase: None,
+        })
+    }
+
+    /// Number of cycles seen so far.
+    pub fn cycles_seen(&self) -> u32 {
+        self.cycle
+    }
+
+    pub fn parse_line(&mut self, line: &str) -> ParsedLine {
+        let line = line.trim();
+        if line.is_empty() {
+            return ParsedLine::Other;
+        }
+
+        if !self.end_marker.is_empty() && line.to_lowercase().contains(&self.end_marker) {
+            return ParsedLine::Completed;
+        }
+
+        if let Some(cycle) = self.parse_cycle_header(line) {
+            self.cycle = cycle;
+            self.last_phase = None;
+            return ParsedLine::CycleStart(cycle);
+        }
+
+        let Some((phase, voltage, unit)) = self.parse_measurement(line) else {
+            return ParsedLine::Other;
+        };
+
+        if self.cycle == 0 || (phase == Phase::On && self.last_phase == Some(Phase::Off)) {
+            self.cycle += 1;
+        }
+        self.last_phase = Some(phase);
+
+        ParsedLine::Measurement(Measurement {
+            phase,
+            voltage,
+            cycle: self.cycle,
+            unit,
+        })
+    }
+
+    fn parse_measurement(&self, line: &str) -> Option<(Phase, f64, String)> {
+        let caps = self.measurement.captures(line)?;
+
+        let phase = match caps.name("phase") {
+            Some(label) => Phase::from_label(label.as_str())?,
+            None => Phase::Off,
+        };
+        let voltage = caps.name("value")?.as_str().parse::<f64>().ok()?;
+        if !voltage.is_finite() {
+            return None;
+        }
+        let unit = caps
+            .name("unit")
+            .map(|u| u.as_str().to_string())
+            .unwrap_or_else(|| "V".to_string());
+
+        Some((phase, voltage, unit))
+    }
+
+    fn parse_cycle_header(&self, line: &str) -> Option<u32> {
+        self.cycle_header
+            .as_ref()?
+            .captures(line)?
+            .name("cycle")?
+            .as_str()
+            .parse()
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(phase: Phase, voltage: f64, cycle: u32, unit: &str) -> ParsedLine {
+        ParsedLine::Measurement(Measurement {
+            phase,
+            voltage,
+            cycle,
+            unit: unit.to_string(),
+        })
+    }
+
+    #[test]
+    fn parses_on_and_off_readings() {
+        let mut parser = MeasurementParser::new();
+
+        assert_eq!(
+            parser.parse_line("Output Voltage (ON): 1.1046 V\r"),
+            reading(Phase::On, 1.1046, 1, "V")
+        );
+        assert_eq!(
+            parser.parse_line("Output Voltage (off) : .4500 mV"),
+            reading(Phase::Off, 0.45, 1, "mV")
+        );
+        assert_eq!(
+            parser.parse_line("Output Voltage (OFF): -0.02"),
+            reading(Phase::Off, -0.02, 1, "V")
+        );
+    }
+
+    #[test]
+    fn starts_a_cycle_when_on_follows_off() {
+        let mut parser = MeasurementParser::new();
+        let cycles: Vec<u32> = [
+            "Output Voltage (ON): 1.1 V",
+            "Output Voltage (OFF): 0.4 V",
+            "Output Voltage (OFF): 0.41 V",
+            "Output Voltage (ON): 1.2 V",
+            "Output Voltage (OFF): 0.5 V",
+        ]
+        .into_iter()
+        .map(|line| match parser.parse_line(line) {
+            ParsedLine::Measurement(m) => m.cycle,
+            other => panic!("{:?}", other),
+        })
+        .collect();
+
+        assert_eq!(cycles, [1, 1, 1, 2, 2]);
+        assert_eq!(parser.cycles_seen(), 2);
+    }
+
+    #[test]
+    fn counts_readings_without_a_phase_as_off() {
+        let mut parser = MeasurementParser::with_patterns(
+            r"^Glucose\s*:\s*(?P<value>[-+]?\d*\.?\d+)\s*(?P<unit>\S+)?",
+            None,
+            DEFAULT_END_MARKER,
+        )
+        .unwrap();
+
+        assert_eq!(
+            parser.parse_line("Glucose: 5.4 mmol/L"),
+            reading(Phase::Off, 5.4, 1, "mmol/L")
+        );
+        assert_eq!(
+            parser.parse_line("Glucose: 5.6"),
+            reading(Phase::Off, 5.6, 1, "V")
+        );
+        assert_eq!(parser.parse_line("Cycle 2"), ParsedLine::Other);
+    }
+
+    #[test]
+    fn follows_cycle_headers() {
+        let mut parser = MeasurementParser::new();
+
+        assert_eq!(parser.parse_line("Cycle 1"), ParsedLine::CycleStart(1));
+        assert_eq!(
+            parser.parse_line("Output Voltage (OFF): 0.4 V"),
+            reading(Phase::Off, 0.4, 1, "V")
+        );
+        assert_eq!(parser.parse_line("cycle #3"), ParsedLine::CycleStart(3));
+        assert_eq!(
+            parser.parse_line("Output Voltage (ON): 1.1 V"),
+            reading(Phase::On, 1.1, 3, "V")
+        );
+        assert_eq!(parser.cycles_seen(), 3);
+    }
+
+    #[test]
+    fn recognises_the_end_marker() {
+        let mut parser = MeasurementParser::new();
+
+        assert_eq!(
+            parser.parse_line("3 Cycles Completed."),
+            ParsedLine::Completed
+        );
+
+        let mut no_marker =
+            MeasurementParser::with_patterns(DEFAULT_MEASUREMENT_PATTERN, None, "").unwrap();
+        assert_eq!(no_marker.parse_line("cycles completed"), ParsedLine::Other);
+    }
+
+    #[test]
+    fn ignores_lines_it_does_not_understand() {
+        let mut parser = MeasurementParser::new();
+
+        for line in [
+            "",
+            "   ",
+            "Booting sensor v2.1",
+            "Output Voltage (ON):",
+            "Output Voltage (HALF): 0.4 V",
+            "Output Voltage: 0.4 V",
+            "Cycle two",
+        ] {
+            assert_eq!(parser.parse_line(line), ParsedLine::Other, "{:?}", line);
+        }
+        assert_eq!(parser.cycles_seen(), 0);
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!(MeasurementParser::with_patterns("(", None, DEFAULT_END_MARKER).is_err());
+        assert!(
+            MeasurementParser::with_patterns(DEFAULT_MEASUREMENT_PATTERN, Some("["), "").is_err()
+        );
+    }
+}
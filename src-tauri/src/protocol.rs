@@ -0,0 +1,435 @@
+// src/protocol.rs
+//
+// Per-device measurement protocol profiles. Different rigs run different
+// firmware, so the end-of-run marker, timeouts, reset pulse, expected cycle
+// count and line patterns live in the `protocol_profiles` table and are
+// assigned to boards through `devices.protocol_profile_id`.
+
+use std::time::Duration;
+
+use regex::Regex;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::database::Database;
+use crate::identify::{DEFAULT_IDENTIFY_COMMAND, DEFAULT_IDENTIFY_PATTERN};
+use crate::measurement::{
+    MeasurementParser, DEFAULT_CYCLE_PATTERN, DEFAULT_END_MARKER, DEFAULT_MEASUREMENT_PATTERN,
+};
+use crate::reader::ReadLoopConfig;
+use crate::types::UsbDevice;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProtocolProfile {
+    /// `None` for the built-in fallback and for profiles not saved yet.
+    pub id: Option<i64>,
+    pub name: String,
+    pub end_marker: String,
+    pub no_data_timeout_ms: u64,
+    pub dtr_reset_ms: u64,
+    /// Minimum number of cycles a run must contain; 0 disables the check.
+    pub expected_cycles: u32,
+    pub measurement_pattern: String,
+    pub cycle_pattern: Option<String>,
+    /// Sent when a board is plugged in; its reply (or boot banner) must
+    /// match `identify_pattern` for the board to be accepted.
+    pub identify_command: String,
+    pub identify_pattern: String,
+    /// Readings outside this range fail the run's quality check.
+    pub min_voltage: f64,
+    pub max_voltage: f64,
+    /// OFF readings each cycle must contain; 0 disables the check.
+    pub off_readings_per_cycle: u32,
+    /// Largest allowed difference between cycle averages, in volts; 0 disables the check.
+    pub max_cycle_spread: f64,
+}
+
+impl Default for ProtocolProfile {
+    /// The behaviour the firmware has always been driven with. Boards without
+    /// a profile include glucose rigs, so the cycle count is not checked; the
+    /// seeded 'cancer-cell' profile asks for its three cycles.
+    fn default() -> Self {
+        Self {
+            id: None,
+            name: "built-in".to_string(),
+            end_marker: DEFAULT_END_MARKER.to_string(),
+            no_data_timeout_ms: 10_000,
+            dtr_reset_ms: 250,
+            expected_cycles: 0,
+            measurement_pattern: DEFAULT_MEASUREMENT_PATTERN.to_string(),
+            cycle_pattern: Some(DEFAULT_CYCLE_PATTERN.to_string()),
+            identify_command: DEFAULT_IDENTIFY_COMMAND.to_string(),
+            identify_pattern: DEFAULT_IDENTIFY_PATTERN.to_string(),
+            min_voltage: 0.0,
+            max_voltage: 5.0,
+            off_readings_per_cycle: 0,
+            max_cycle_spread: 0.0,
+        }
+    }
+}
+
+impl ProtocolProfile {
+    pub fn parser(&self) -> Result<MeasurementParser, regex::Error> {
+        MeasurementParser::with_patterns(
+            &self.measurement_pattern,
+            self.cycle_pattern
+                .as_deref()
+                .filter(|p| !p.trim().is_empty()),
+            &self.end_marker,
+        )
+    }
+
+    pub fn read_loop_config(&self) -> ReadLoopConfig {
+        ReadLoopConfig {
+            no_data_timeout: Duration::from_millis(self.no_data_timeout_ms),
+            expected_cycles: self.expected_cycles,
+        }
+    }
+
+    pub fn identify_regex(&self) -> Result<Regex, regex::Error> {
+        Regex::new(&self.identify_pattern)
+    }
+
+    pub fn dtr_pulse(&self) -> Duration {
+        Duration::from_millis(self.dtr_reset_ms)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Profile name cannot be empty.".into());
+        }
+        if self.end_marker.trim().is_empty() {
+            return Err("End-of-measurement marker cannot be empty.".into());
+        }
+        if self.no_data_timeout_ms < 1_000 {
+            return Err("No-data timeout must be at least 1000 ms.".into());
+        }
+        self.parser()
+            .map_err(|e| format!("Invalid line pattern: {}", e))?;
+        let measurement = Regex::new(&self.measurement_pattern)
+            .map_err(|e| format!("Invalid line pattern: {}", e))?;
+        if !has_groups(&measurement, &["value"]) {
+            return Err("Measurement pattern needs a named group 'value'.".into());
+        }
+        if self.identify_command.trim().is_empty() {
+            return Err("Identify command cannot be empty.".into());
+        }
+        let identify = self
+            .identify_regex()
+            .map_err(|e| format!("Invalid identify pattern: {}", e))?;
+        if !has_groups(&identify, &["name", "version"]) {
+            return Err("Identify pattern needs named groups 'name' and 'version'.".into());
+        }
+        if self.min_voltage >= self.max_voltage {
+            return Err("Minimum voltage must be below the maximum voltage.".into());
+        }
+        if self.max_cycle_spread < 0.0 {
+            return Err("Maximum cycle spread cannot be negative.".into());
+        }
+        Ok(())
+    }
+}
+
+/// Whether `pattern` defines every named group, spelled either `(?P<name>`
+/// or `(?<name>`.
+fn has_groups(pattern: &Regex, names: &[&str]) -> bool {
+    names
+        .iter()
+        .all(|name| pattern.capture_names().flatten().any(|n| n == *name))
+}
+
+const PROFILE_COLUMNS: &str = "p.id, p.name, p.end_marker, p.no_data_timeout_ms, p.dtr_reset_ms,
+    p.expected_cycles, p.measurement_pattern, p.cycle_pattern, p.identify_command,
+    p.identify_pattern, p.min_voltage, p.max_voltage, p.off_readings_per_cycle,
+    p.max_cycle_spread";
+
+fn map_profile(row: &rusqlite::Row<'_>) -> rusqlite::Result<ProtocolProfile> {
+    Ok(ProtocolProfile {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        end_marker: row.get(2)?,
+        no_data_timeout_ms: row.get(3)?,
+        dtr_reset_ms: row.get(4)?,
+        expected_cycles: row.get(5)?,
+        measurement_pattern: row.get(6)?,
+        cycle_pattern: row.get(7)?,
+        identify_command: row.get(8)?,
+        identify_pattern: row.get(9)?,
+        min_voltage: row.get(10)?,
+        max_voltage: row.get(11)?,
+        off_readings_per_cycle: row.get(12)?,
+        max_cycle_spread: row.get(13)?,
+    })
+}
+
+pub fn profile_by_id(conn: &Connection, id: i64) -> rusqlite::Result<Option<ProtocolProfile>> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM protocol_profiles p WHERE p.id = ?1",
+            PROFILE_COLUMNS
+        ),
+        params![id],
+        map_profile,
+    )
+    .optional()
+}
+
+/// Profile assigned to a board, if any.
+pub fn profile_for_device(
+    conn: &Connection,
+    device: &UsbDevice,
+) -> rusqlite::Result<Option<ProtocolProfile>> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM devices d
+             JOIN protocol_profiles p ON p.id = d.protocol_profile_id
+             WHERE d.vid = ?1 AND d.pid = ?2 AND d.serial_number IS ?3",
+            PROFILE_COLUMNS
+        ),
+        params![device.vid, device.pid, device.serial_number],
+        map_profile,
+    )
+    .optional()
+}
+
+/* ----------------------------------------
+   TAURI COMMANDS
+----------------------------------------- */
+
+#[tauri::command]
+pub fn get_protocol_profiles(db: State<'_, Database>) -> Result<Vec<ProtocolProfile>, String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM protocol_profiles p ORDER BY p.name",
+            PROFILE_COLUMNS
+        ))
+        .map_err(|e| e.to_string())?;
+
+    let rows = stmt
+        .query_map([], map_profile)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(rows)
+}
+
+/// Creates a profile, or updates it when `id` is set. Returns the id.
+#[tauri::command]
+pub fn save_protocol_profile(
+    db: State<'_, Database>,
+    profile: ProtocolProfile,
+) -> Result<i64, String> {
+    profile.validate()?;
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+
+    let result = match profile.id {
+        Some(id) => conn
+            .execute(
+                "UPDATE protocol_profiles SET
+                    name = ?2, end_marker = ?3, no_data_timeout_ms = ?4, dtr_reset_ms = ?5,
+                    expected_cycles = ?6, measurement_pattern = ?7, cycle_pattern = ?8,
+                    identify_command = ?9, identify_pattern = ?10, min_voltage = ?11,
+                    max_voltage = ?12, off_readings_per_cycle = ?13, max_cycle_spread = ?14
+                 WHERE id = ?1",
+                params![
+                    id,
+                    profile.name,
+                    profile.end_marker,
+                    profile.no_data_timeout_ms,
+                    profile.dtr_reset_ms,
+                    profile.expected_cycles,
+                    profile.measurement_pattern,
+                    profile.cycle_pattern,
+                    profile.identify_command,
+                    profile.identify_pattern,
+                    profile.min_voltage,
+                    profile.max_voltage,
+                    profile.off_readings_per_cycle,
+                    profile.max_cycle_spread
+                ],
+            )
+            .and_then(|n| match n {
+                0 => Err(rusqlite::Error::QueryReturnedNoRows),
+                _ => Ok(id),
+            }),
+        None => conn
+            .execute(
+                "INSERT INTO protocol_profiles (
+                    name, end_marker, no_data_timeout_ms, dtr_reset_ms,
+                    expected_cycles, measurement_pattern, cycle_pattern,
+                    identify_command, identify_pattern, min_voltage, max_voltage,
+                    off_readings_per_cycle, max_cycle_spread
+                 ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                params![
+                    profile.name,
+                    profile.end_marker,
+                    profile.no_data_timeout_ms,
+                    profile.dtr_reset_ms,
+                    profile.expected_cycles,
+                    profile.measurement_pattern,
+                    profile.cycle_pattern,
+                    profile.identify_command,
+                    profile.identify_pattern,
+                    profile.min_voltage,
+                    profile.max_voltage,
+                    profile.off_readings_per_cycle,
+                    profile.max_cycle_spread
+                ],
+            )
+            .map(|_| conn.last_insert_rowid()),
+    };
+
+    match result {
+        Ok(id) => Ok(id),
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            Err(format!("Protocol profile {:?} not found.", profile.id))
+        }
+        Err(e)
+            if e.to_string()
+                .contains("UNIQUE constraint failed: protocol_profiles.name") =>
+        {
+            Err(format!(
+                "A protocol profile named '{}' already exists.",
+                profile.name
+            ))
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub fn delete_protocol_profile(db: State<'_, Database>, id: i64) -> Result<(), String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+    let count = conn
+        .execute("DELETE FROM protocol_profiles WHERE id = ?1", params![id])
+        .map_err(|e| e.to_string())?;
+    if count == 0 {
+        return Err(format!("Protocol profile {} not found.", id));
+    }
+    Ok(())
+}
+
+/// Selects the protocol profile for a board; `None` reverts to the built-in one.
+#[tauri::command]
+pub fn set_device_protocol(
+    db: State<'_, Database>,
+    vid: i32,
+    pid: i32,
+    serial_number: Option<String>,
+    product: Option<String>,
+    profile_id: Option<i64>,
+) -> Result<(), String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+
+    conn.execute(
+        "INSERT INTO devices (vid, pid, serial_number, product, protocol_profile_id)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT(vid, pid, serial_number) DO UPDATE SET
+            protocol_profile_id = excluded.protocol_profile_id,
+            last_seen = CURRENT_TIMESTAMP",
+        params![vid, pid, serial_number, product, profile_id],
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_built_in_profile_is_valid() {
+        let profile = ProtocolProfile::default();
+
+        assert_eq!(profile.validate(), Ok(()));
+        assert_eq!(profile.read_loop_config().expected_cycles, 0);
+    }
+
+    #[test]
+    fn accepts_both_named_group_spellings() {
+        for pattern in [r"^V: (?P<value>\d+)", r"^V: (?<value>\d+)"] {
+            let profile = ProtocolProfile {
+                measurement_pattern: pattern.to_string(),
+                ..Default::default()
+            };
+            assert_eq!(profile.validate(), Ok(()), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn rejects_patterns_without_their_groups() {
+        let no_value = ProtocolProfile {
+            measurement_pattern: r"^V: (?P<reading>\d+)".to_string(),
+            ..Default::default()
+        };
+        assert!(no_value.validate().unwrap_err().contains("'value'"));
+
+        let no_version = ProtocolProfile {
+            identify_pattern: r"^ID (?P<name>\w+)".to_string(),
+            ..Default::default()
+        };
+        assert!(no_version.validate().unwrap_err().contains("'version'"));
+
+        let broken = ProtocolProfile {
+            cycle_pattern: Some("(".to_string()),
+            ..Default::default()
+        };
+        assert!(broken
+            .validate()
+            .unwrap_err()
+            .starts_with("Invalid line pattern"));
+    }
+
+    #[test]
+    fn rejects_unusable_settings() {
+        let invalid = [
+            ProtocolProfile {
+                name: " ".to_string(),
+                ..Default::default()
+            },
+            ProtocolProfile {
+                end_marker: String::new(),
+                ..Default::default()
+            },
+            ProtocolProfile {
+                no_data_timeout_ms: 999,
+                ..Default::default()
+            },
+            ProtocolProfile {
+                identify_command: String::new(),
+                ..Default::default()
+            },
+            ProtocolProfile {
+                min_voltage: 5.0,
+                ..Default::default()
+            },
+            ProtocolProfile {
+                max_cycle_spread: -0.1,
+                ..Default::default()
+            },
+        ];
+
+        for profile in invalid {
+            assert!(profile.validate().is_err(), "{:?}", profile);
+        }
+    }
+
+    #[test]
+    fn a_blank_cycle_pattern_disables_cycle_headers() {
+        let profile = ProtocolProfile {
+            cycle_pattern: Some(" ".to_string()),
+            ..Default::default()
+        };
+        let mut parser = profile.parser().unwrap();
+
+        assert_eq!(
+            parser.parse_line("Cycle 2"),
+            crate::measurement::ParsedLine::Other
+        );
+    }
+}
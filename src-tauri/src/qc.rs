@@ -0,0 +1,691 @@
+// src/qc.rs
+//
+// Quality control: control materials with known target values are measured
+// on each device, and every result is judged with the Westgard multirules
+// against the material's target mean and SD. A device whose latest QC was
+// rejected may not be used for patient measurements until QC passes again.
+// Results are kept per device and material for Levey-Jennings charts.
+
+use chrono::Local;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::acquisition::TIMESTAMP_FORMAT;
+use crate::database::{log_event, Database};
+use crate::types::UsbDevice;
+
+/// Results of one material on one device looked back on by the rules (10x).
+const RULE_WINDOW: usize = 10;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QcMaterial {
+    /// `None` when creating a material.
+    #[serde(default)]
+    pub id: Option<i64>,
+    pub name: String,
+    pub lot_number: Option<String>,
+    /// e.g. "low", "normal", "high".
+    pub level: Option<String>,
+    pub target_mean: f64,
+    pub target_sd: f64,
+    pub expires_on: Option<String>,
+    #[serde(default = "default_active")]
+    pub active: bool,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct QcResult {
+    pub id: i64,
+    pub material_id: i64,
+    pub value: f64,
+    /// Distance from the target mean in target SDs.
+    pub z_score: f64,
+    /// Westgard rules the result violated, e.g. `1-3s`.
+    pub violations: Vec<String>,
+    /// `accepted`, `warning` (1-2s only) or `rejected`.
+    pub status: String,
+    pub acquisition_session_id: Option<i64>,
+    pub performed_by: Option<String>,
+    pub measured_at: String,
+}
+
+/// Levey-Jennings data for one material on one device.
+#[derive(Serialize, Debug)]
+pub struct QcChart {
+    pub material: QcMaterial,
+    /// Oldest first.
+    pub results: Vec<QcResult>,
+    /// Running statistics of the results themselves, next to the targets.
+    pub observed_mean: Option<f64>,
+    pub observed_sd: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct QcStatus {
+    /// `ok`, `warning`, `failed` or `missing` (no QC recorded yet).
+    pub state: String,
+    pub last_run_at: Option<String>,
+    /// Violations of the latest rejected or warned results, per material.
+    pub messages: Vec<String>,
+}
+
+impl QcStatus {
+    pub fn blocks_patients(&self) -> bool {
+        self.state == "failed"
+    }
+}
+
+fn now() -> String {
+    Local::now().format(TIMESTAMP_FORMAT).to_string()
+}
+
+/// Westgard evaluation of a new result. `history` holds the earlier z-scores
+/// of the same material on the same device, newest first; `run` the z-scores
+/// of the other materials measured in the same QC run.
+pub fn westgard(z: f64, history: &[f64], run: &[f64]) -> Vec<&'static str> {
+    let mut violations = Vec::new();
+    let series: Vec<f64> = std::iter::once(z).chain(history.iter().copied()).collect();
+    let same_side = |values: &[f64], limit: f64| {
+        values.iter().all(|v| *v > limit) || values.iter().all(|v| *v < -limit)
+    };
+
+    if z.abs() > 3.0 {
+        violations.push("1-3s");
+    }
+    if series.len() >= 2 && same_side(&series[..2], 2.0) {
+        violations.push("2-2s");
+    }
+    let r4s = |other: &f64| (z > 2.0 && *other < -2.0) || (z < -2.0 && *other > 2.0);
+    if run.iter().any(r4s) || history.first().is_some_and(r4s) {
+        violations.push("R-4s");
+    }
+    if series.len() >= 4 && same_side(&series[..4], 1.0) {
+        violations.push("4-1s");
+    }
+    if series.len() >= 10 && same_side(&series[..10], 0.0) {
+        violations.push("10x");
+    }
+    if violations.is_empty() && z.abs() > 2.0 {
+        violations.push("1-2s");
+    }
+    violations
+}
+
+fn status_of(violations: &[&str]) -> &'static str {
+    match violations {
+        [] => "accepted",
+        ["1-2s"] => "warning",
+        _ => "rejected",
+    }
+}
+
+const MATERIAL_COLUMNS: &str =
+    "id, name, lot_number, level, target_mean, target_sd, expires_on, active";
+
+fn map_material(row: &rusqlite::Row<'_>) -> rusqlite::Result<QcMaterial> {
+    Ok(QcMaterial {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        lot_number: row.get(2)?,
+        level: row.get(3)?,
+        target_mean: row.get(4)?,
+        target_sd: row.get(5)?,
+        expires_on: row.get(6)?,
+        active: row.get(7)?,
+    })
+}
+
+fn load_material(conn: &Connection, id: i64) -> rusqlite::Result<QcMaterial> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM qc_materials WHERE id = ?1",
+            MATERIAL_COLUMNS
+        ),
+        params![id],
+        map_material,
+    )
+}
+
+const RESULT_COLUMNS: &str = "id, material_id, value, z_score, violations, status,
+    acquisition_session_id, performed_by, measured_at";
+
+fn map_result(row: &rusqlite::Row<'_>) -> rusqlite::Result<QcResult> {
+    let violations: Option<String> = row.get(4)?;
+    Ok(QcResult {
+        id: row.get(0)?,
+        material_id: row.get(1)?,
+        value: row.get(2)?,
+        z_score: row.get(3)?,
+        violations: violations
+            .and_then(|v| serde_json::from_str(&v).ok())
+            .unwrap_or_default(),
+        status: row.get(5)?,
+        acquisition_session_id: row.get(6)?,
+        performed_by: row.get(7)?,
+        measured_at: row.get(8)?,
+    })
+}
+
+/// Earlier z-scores of a material on a device, newest first.
+fn recent_z_scores(
+    conn: &Connection,
+    vid: u16,
+    pid: u16,
+    serial_number: Option<&str>,
+    material_id: i64,
+) -> rusqlite::Result<Vec<f64>> {
+    let mut stmt = conn.prepare(
+        "SELECT z_score FROM qc_results
+         WHERE vid = ?1 AND pid = ?2 AND serial_number IS ?3 AND material_id = ?4
+         ORDER BY measured_at DESC, id DESC LIMIT ?5",
+    )?;
+    let rows = stmt
+        .query_map(
+            params![vid, pid, serial_number, material_id, RULE_WINDOW as i64],
+            |row| row.get(0),
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rows)
+}
+
+/// Mean calibrated voltage of a session, used as the QC value.
+fn session_mean(conn: &Connection, session_id: i64) -> rusqlite::Result<Option<f64>> {
+    conn.query_row(
+        "SELECT AVG(voltage) FROM acquisition_lines WHERE session_id = ?1 AND voltage IS NOT NULL",
+        params![session_id],
+        |row| row.get(0),
+    )
+}
+
+/// QC state of a device from the latest result of each active material.
+pub fn qc_status(
+    conn: &Connection,
+    vid: u16,
+    pid: u16,
+    serial_number: Option<&str>,
+) -> rusqlite::Result<QcStatus> {
+    let mut stmt = conn.prepare(
+        "SELECT m.name, r.status, r.violations, r.measured_at
+         FROM qc_results r
+         JOIN qc_materials m ON m.id = r.material_id
+         WHERE r.vid = ?1 AND r.pid = ?2 AND r.serial_number IS ?3 AND m.active = 1
+           AND r.id = (
+               SELECT r2.id FROM qc_results r2
+               WHERE r2.vid = r.vid AND r2.pid = r.pid
+                 AND r2.serial_number IS r.serial_number AND r2.material_id = r.material_id
+               ORDER BY r2.measured_at DESC, r2.id DESC LIMIT 1
+           )",
+    )?;
+    let latest = stmt
+        .query_map(params![vid, pid, serial_number], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut state = if latest.is_empty() { "missing" } else { "ok" };
+    let mut messages = Vec::new();
+    for (material, status, violations, _) in &latest {
+        if status == "accepted" {
+            continue;
+        }
+        let rules: Vec<String> = violations
+            .as_deref()
+            .and_then(|v| serde_json::from_str(v).ok())
+            .unwrap_or_default();
+        messages.push(format!("{}: {} ({})", material, status, rules.join(", ")));
+        if status == "rejected" {
+            state = "failed";
+        } else if state == "ok" {
+            state = "warning";
+        }
+    }
+
+    Ok(QcStatus {
+        state: state.to_string(),
+        last_run_at: latest.iter().map(|(_, _, _, at)| at.clone()).max(),
+        messages,
+    })
+}
+
+/// QC state of a connected board.
+pub fn device_qc_status(conn: &Connection, device: &UsbDevice) -> rusqlite::Result<QcStatus> {
+    qc_status(
+        conn,
+        device.vid,
+        device.pid,
+        device.serial_number.as_deref(),
+    )
+}
+
+/* ----------------------------------------
+   TAURI COMMANDS
+----------------------------------------- */
+
+#[tauri::command]
+pub fn get_qc_materials(db: State<'_, Database>) -> Result<Vec<QcMaterial>, String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM qc_materials ORDER BY active DESC, name, level",
+            MATERIAL_COLUMNS
+        ))
+        .map_err(|e| e.to_string())?;
+
+    let rows = stmt
+        .query_map([], map_material)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(rows)
+}
+
+/// Creates a control material, or updates it when `id` is set. Returns the id.
+#[tauri::command]
+pub fn save_qc_material(db: State<'_, Database>, material: QcMaterial) -> Result<i64, String> {
+    if material.name.trim().is_empty() {
+        return Err("Material name cannot be empty.".into());
+    }
+    if material.target_sd <= 0.0 {
+        return Err("Target SD must be greater than zero.".into());
+    }
+
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+
+    match material.id {
+        Some(id) => {
+            let updated = conn
+                .execute(
+                    "UPDATE qc_materials SET
+                        name = ?2, lot_number = ?3, level = ?4, target_mean = ?5,
+                        target_sd = ?6, expires_on = ?7, active = ?8
+                     WHERE id = ?1",
+                    params![
+                        id,
+                        material.name,
+                        material.lot_number,
+                        material.level,
+                        material.target_mean,
+                        material.target_sd,
+                        material.expires_on,
+                        material.active
+                    ],
+                )
+                .map_err(|e| e.to_string())?;
+            if updated == 0 {
+                return Err(format!("QC material {} not found.", id));
+            }
+            Ok(id)
+        }
+        None => {
+            conn.execute(
+                "INSERT INTO qc_materials (
+                    name, lot_number, level, target_mean, target_sd, expires_on, active
+                 ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    material.name,
+                    material.lot_number,
+                    material.level,
+                    material.target_mean,
+                    material.target_sd,
+                    material.expires_on,
+                    material.active
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+            Ok(conn.last_insert_rowid())
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct QcMeasurement {
+    pub material_id: i64,
+    /// Measured value; taken as the mean of `session_id` when omitted.
+    #[serde(default)]
+    pub value: Option<f64>,
+    #[serde(default)]
+    pub session_id: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct QcRunRequest {
+    pub vid: u16,
+    pub pid: u16,
+    pub serial_number: Option<String>,
+    /// One entry per control material measured in this run.
+    pub measurements: Vec<QcMeasurement>,
+    #[serde(default)]
+    pub performed_by: Option<String>,
+}
+
+/// Records a QC run on a device and judges each result with the Westgard rules.
+#[tauri::command]
+pub fn record_qc_run(
+    db: State<'_, Database>,
+    request: QcRunRequest,
+) -> Result<Vec<QcResult>, String> {
+    if request.measurements.is_empty() {
+        return Err("A QC run needs at least one measurement.".into());
+    }
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+    let serial_number = request.serial_number.as_deref();
+
+    // Resolve every value and z-score first so R-4s can compare the run's levels
+    let mut entries = Vec::with_capacity(request.measurements.len());
+    for m in &request.measurements {
+        let material = load_material(&conn, m.material_id)
+            .map_err(|_| format!("QC material {} not found.", m.material_id))?;
+        let value = match (m.value, m.session_id) {
+            (Some(value), _) => value,
+            (None, Some(session_id)) => session_mean(&conn, session_id)
+                .map_err(|e| e.to_string())?
+                .ok_or(format!("Session {} has no voltage readings.", session_id))?,
+            (None, None) => {
+                return Err(format!(
+                    "QC measurement of '{}' needs a value or a session.",
+                    material.name
+                ))
+            }
+        };
+        let z = (value - material.target_mean) / material.target_sd;
+        entries.push((m, material, value, z));
+    }
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    let measured_at = now();
+    let mut ids = Vec::with_capacity(entries.len());
+    for (i, (m, material, value, z)) in entries.iter().enumerate() {
+        let history = recent_z_scores(&tx, request.vid, request.pid, serial_number, m.material_id)
+            .map_err(|e| e.to_string())?;
+        let others: Vec<f64> = entries
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .map(|(_, e)| e.3)
+            .collect();
+
+        let violations = westgard(*z, &history, &others);
+        let status = status_of(&violations);
+        tx.execute(
+            "INSERT INTO qc_results (
+                material_id, vid, pid, serial_number, value, z_score, violations, status,
+                acquisition_session_id, performed_by, measured_at
+             ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                m.material_id,
+                request.vid,
+                request.pid,
+                serial_number,
+                value,
+                z,
+                serde_json::to_string(&violations).map_err(|e| e.to_string())?,
+                status,
+                m.session_id,
+                request.performed_by,
+                measured_at
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        ids.push(tx.last_insert_rowid());
+
+        if status == "rejected" {
+            log_event(
+                &tx,
+                &format!(
+                    "QC rejected on device (VID:{}, PID:{}, SN:{}): {} {} ({})",
+                    request.vid,
+                    request.pid,
+                    serial_number.unwrap_or("None"),
+                    material.name,
+                    value,
+                    violations.join(", ")
+                ),
+            )
+            .map_err(|e| e.to_string())?;
+        }
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+
+    let mut results = Vec::with_capacity(ids.len());
+    for id in ids {
+        results.push(
+            conn.query_row(
+                &format!("SELECT {} FROM qc_results WHERE id = ?1", RESULT_COLUMNS),
+                params![id],
+                map_result,
+            )
+            .map_err(|e| e.to_string())?,
+        );
+    }
+    Ok(results)
+}
+
+/// Levey-Jennings chart of a material on a device.
+#[tauri::command]
+pub fn get_qc_chart(
+    db: State<'_, Database>,
+    vid: u16,
+    pid: u16,
+    serial_number: Option<String>,
+    material_id: i64,
+) -> Result<QcChart, String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+    let material = load_material(&conn, material_id).map_err(|e| e.to_string())?;
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM qc_results
+             WHERE vid = ?1 AND pid = ?2 AND serial_number IS ?3 AND material_id = ?4
+             ORDER BY measured_at, id",
+            RESULT_COLUMNS
+        ))
+        .map_err(|e| e.to_string())?;
+    let results = stmt
+        .query_map(params![vid, pid, serial_number, material_id], map_result)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let n = results.len() as f64;
+    let observed_mean =
+        (!results.is_empty()).then(|| results.iter().map(|r| r.value).sum::<f64>() / n);
+    let observed_sd = observed_mean.filter(|_| results.len() > 1).map(|mean| {
+        (results
+            .iter()
+            .map(|r| (r.value - mean).powi(2))
+            .sum::<f64>()
+            / (n - 1.0))
+            .sqrt()
+    });
+
+    Ok(QcChart {
+        material,
+        results,
+        observed_mean,
+        observed_sd,
+    })
+}
+
+#[tauri::command]
+pub fn get_qc_status(
+    db: State<'_, Database>,
+    vid: u16,
+    pid: u16,
+    serial_number: Option<String>,
+) -> Result<QcStatus, String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+    qc_status(&conn, vid, pid, serial_number.as_deref()).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::open_test_database;
+
+    const VID: u16 = 0x2341;
+    const PID: u16 = 0x0043;
+
+    fn material(conn: &Connection, name: &str, active: bool) -> i64 {
+        conn.execute(
+            "INSERT INTO qc_materials (name, target_mean, target_sd, active)
+             VALUES (?1, 0.5, 0.02, ?2)",
+            params![name, active],
+        )
+        .unwrap();
+        conn.last_insert_rowid()
+    }
+
+    fn result(conn: &Connection, material_id: i64, status: &str, violations: &str, at: &str) {
+        conn.execute(
+            "INSERT INTO qc_results (
+                material_id, vid, pid, serial_number, value, z_score, violations, status,
+                measured_at
+             ) VALUES (?1, ?2, ?3, 'A1', 0.5, 0.0, ?4, ?5, ?6)",
+            params![material_id, VID, PID, violations, status, at],
+        )
+        .unwrap();
+    }
+
+    fn status(conn: &Connection) -> QcStatus {
+        qc_status(conn, VID, PID, Some("A1")).unwrap()
+    }
+
+    #[test]
+    fn accepts_results_within_two_sd() {
+        let violations = westgard(1.5, &[-0.4, 0.8], &[0.3]);
+
+        assert!(violations.is_empty());
+        assert_eq!(status_of(&violations), "accepted");
+    }
+
+    #[test]
+    fn a_lone_two_sd_result_only_warns() {
+        let violations = westgard(-2.4, &[0.5], &[]);
+
+        assert_eq!(violations, ["1-2s"]);
+        assert_eq!(status_of(&violations), "warning");
+    }
+
+    #[test]
+    fn rejects_a_result_beyond_three_sd() {
+        let violations = westgard(3.2, &[], &[]);
+
+        assert_eq!(violations, ["1-3s"]);
+        assert_eq!(status_of(&violations), "rejected");
+    }
+
+    #[test]
+    fn rejects_two_results_beyond_two_sd_on_one_side() {
+        assert_eq!(westgard(2.3, &[2.1, -0.5], &[]), ["2-2s"]);
+        assert_eq!(westgard(-2.3, &[2.1], &[]), ["R-4s"]);
+    }
+
+    #[test]
+    fn rejects_a_four_sd_range_within_a_run() {
+        assert_eq!(westgard(2.2, &[], &[0.4, -2.1]), ["R-4s"]);
+        assert_eq!(westgard(2.2, &[], &[0.4, -1.9]), ["1-2s"]);
+    }
+
+    #[test]
+    fn rejects_trends_on_one_side_of_the_mean() {
+        assert_eq!(westgard(1.2, &[1.1, 1.5, 1.3], &[]), ["4-1s"]);
+        assert!(westgard(1.2, &[1.1, 1.5], &[]).is_empty());
+
+        let history = [0.3, 0.5, 0.2, 0.9, 0.1, 0.4, 0.6, 0.2, 0.3];
+        assert_eq!(westgard(0.4, &history, &[]), ["10x"]);
+        assert!(westgard(-0.4, &history, &[]).is_empty());
+    }
+
+    #[test]
+    fn reports_every_rule_broken() {
+        let violations = westgard(3.5, &[2.5, 1.2, 1.1, -2.0], &[-2.5]);
+
+        assert_eq!(violations, ["1-3s", "2-2s", "R-4s", "4-1s"]);
+        assert_eq!(status_of(&violations), "rejected");
+    }
+
+    #[test]
+    fn a_device_without_qc_is_missing_but_not_blocked() {
+        let conn = open_test_database();
+        material(&conn, "Normal", true);
+
+        let status = status(&conn);
+        assert_eq!(status.state, "missing");
+        assert_eq!(status.last_run_at, None);
+        assert!(!status.blocks_patients());
+    }
+
+    #[test]
+    fn only_a_rejected_latest_result_blocks_patients() {
+        let conn = open_test_database();
+        let normal = material(&conn, "Normal", true);
+        let high = material(&conn, "High", true);
+
+        result(&conn, normal, "accepted", "[]", "2026-03-01 08:00:00");
+        result(&conn, high, "accepted", "[]", "2026-03-01 08:05:00");
+        assert_eq!(status(&conn).state, "ok");
+
+        result(&conn, high, "warning", r#"["1-2s"]"#, "2026-03-02 08:05:00");
+        let warned = status(&conn);
+        assert_eq!(warned.state, "warning");
+        assert_eq!(warned.messages, ["High: warning (1-2s)"]);
+        assert!(!warned.blocks_patients());
+
+        result(
+            &conn,
+            normal,
+            "rejected",
+            r#"["1-3s"]"#,
+            "2026-03-03 08:00:00",
+        );
+        let failed = status(&conn);
+        assert_eq!(failed.state, "failed");
+        assert_eq!(failed.last_run_at.as_deref(), Some("2026-03-03 08:00:00"));
+        assert!(failed.blocks_patients());
+
+        // A passing rerun of the rejected material clears the block
+        result(&conn, normal, "accepted", "[]", "2026-03-03 09:00:00");
+        assert_eq!(status(&conn).state, "warning");
+    }
+
+    #[test]
+    fn ignores_other_devices_and_inactive_materials() {
+        let conn = open_test_database();
+        let retired = material(&conn, "Retired", false);
+        result(
+            &conn,
+            retired,
+            "rejected",
+            r#"["1-3s"]"#,
+            "2026-03-01 08:00:00",
+        );
+        assert_eq!(status(&conn).state, "missing");
+
+        let normal = material(&conn, "Normal", true);
+        result(
+            &conn,
+            normal,
+            "rejected",
+            r#"["1-3s"]"#,
+            "2026-03-01 08:00:00",
+        );
+        assert_eq!(
+            qc_status(&conn, VID, PID, Some("B2")).unwrap().state,
+            "missing"
+        );
+        assert_eq!(qc_status(&conn, VID, PID, None).unwrap().state, "missing");
+        assert!(status(&conn).blocks_patients());
+    }
+}
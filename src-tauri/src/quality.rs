@@ -0,0 +1,519 @@
+// src/quality.rs
+//
+// Signal quality checks run on a finished acquisition before its numbers may
+// be saved with an admission. A run fails when readings leave the profile's
+// voltage range, cycles are missing OFF readings or their OFF averages
+// disagree too much, or the signal is flat or pinned to a rail. Gaps in the
+// line stream only warn.
+
+use chrono::NaiveDateTime;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::acquisition::TIMESTAMP_FORMAT;
+use crate::database::Database;
+use crate::measurement::Phase;
+use crate::protocol::{self, ProtocolProfile};
+
+/// Readings varying less than this across the whole run are a flat line.
+const FLAT_LINE_TOLERANCE: f64 = 0.001;
+
+/// Readings this close to a range limit count as saturated.
+const SATURATION_MARGIN: f64 = 0.01;
+
+/// Consecutive saturated readings that fail the run.
+const SATURATION_RUN: usize = 3;
+
+/// A pause longer than this many typical line intervals means lines were lost.
+const GAP_FACTOR: f64 = 2.5;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Verdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verdict::Pass => "pass",
+            Verdict::Warn => "warn",
+            Verdict::Fail => "fail",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QualityIssue {
+    /// `range`, `off_readings`, `cycle_spread`, `flat_line`, `saturation`,
+    /// `dropped_lines` or `no_readings`.
+    pub check: String,
+    pub verdict: Verdict,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QualityReport {
+    pub verdict: Verdict,
+    pub issues: Vec<QualityIssue>,
+}
+
+/// Limits from the protocol profile. Zero disables the OFF count and spread checks.
+#[derive(Clone, Debug)]
+pub struct QualityLimits {
+    pub min_voltage: f64,
+    pub max_voltage: f64,
+    pub off_readings_per_cycle: u32,
+    pub max_cycle_spread: f64,
+}
+
+impl From<&ProtocolProfile> for QualityLimits {
+    fn from(profile: &ProtocolProfile) -> Self {
+        Self {
+            min_voltage: profile.min_voltage,
+            max_voltage: profile.max_voltage,
+            off_readings_per_cycle: profile.off_readings_per_cycle,
+            max_cycle_spread: profile.max_cycle_spread,
+        }
+    }
+}
+
+/// One stored reading, as the checks see it.
+pub struct Reading {
+    pub phase: Option<Phase>,
+    pub voltage: f64,
+    pub cycle: u32,
+    pub received_at: Option<NaiveDateTime>,
+}
+
+fn issue(check: &str, verdict: Verdict, message: String) -> QualityIssue {
+    QualityIssue {
+        check: check.to_string(),
+        verdict,
+        message,
+    }
+}
+
+pub fn evaluate(readings: &[Reading], limits: &QualityLimits) -> QualityReport {
+    let mut issues = Vec::new();
+
+    if readings.is_empty() {
+        issues.push(issue(
+            "no_readings",
+            Verdict::Fail,
+            "No voltage readings were received.".into(),
+        ));
+    } else {
+        check_range(readings, limits, &mut issues);
+        check_off_readings(readings, limits, &mut issues);
+        check_cycle_spread(readings, limits, &mut issues);
+        check_flat_or_saturated(readings, limits, &mut issues);
+        check_dropped_lines(readings, &mut issues);
+    }
+
+    QualityReport {
+        verdict: issues
+            .iter()
+            .map(|i| i.verdict)
+            .max()
+            .unwrap_or(Verdict::Pass),
+        issues,
+    }
+}
+
+fn check_range(readings: &[Reading], limits: &QualityLimits, issues: &mut Vec<QualityIssue>) {
+    let outside = readings
+        .iter()
+        .filter(|r| r.voltage < limits.min_voltage || r.voltage > limits.max_voltage)
+        .count();
+    if outside > 0 {
+        issues.push(issue(
+            "range",
+            Verdict::Fail,
+            format!(
+                "{} reading(s) outside {}–{} V.",
+                outside, limits.min_voltage, limits.max_voltage
+            ),
+        ));
+    }
+}
+
+/// Cycle numbers present in the run, in order.
+fn cycles(readings: &[Reading]) -> Vec<u32> {
+    let mut cycles: Vec<u32> = readings.iter().map(|r| r.cycle).collect();
+    cycles.sort_unstable();
+    cycles.dedup();
+    cycles
+}
+
+fn check_off_readings(
+    readings: &[Reading],
+    limits: &QualityLimits,
+    issues: &mut Vec<QualityIssue>,
+) {
+    if limits.off_readings_per_cycle == 0 {
+        return;
+    }
+    // Readings without a phase count as OFF, as in the parser
+    let short: Vec<String> = cycles(readings)
+        .into_iter()
+        .filter_map(|cycle| {
+            let off = readings
+                .iter()
+                .filter(|r| r.cycle == cycle && r.phase != Some(Phase::On))
+                .count();
+            (off != limits.off_readings_per_cycle as usize)
+                .then(|| format!("cycle {}: {}", cycle, off))
+        })
+        .collect();
+    if !short.is_empty() {
+        issues.push(issue(
+            "off_readings",
+            Verdict::Fail,
+            format!(
+                "Expected {} OFF reading(s) per cycle ({}).",
+                limits.off_readings_per_cycle,
+                short.join(", ")
+            ),
+        ));
+    }
+}
+
+fn check_cycle_spread(
+    readings: &[Reading],
+    limits: &QualityLimits,
+    issues: &mut Vec<QualityIssue>,
+) {
+    if limits.max_cycle_spread <= 0.0 {
+        return;
+    }
+    // Only OFF readings are stored as results, so only they are compared
+    let off: Vec<&Reading> = readings
+        .iter()
+        .filter(|r| r.phase != Some(Phase::On))
+        .collect();
+    let means: Vec<f64> = cycles(readings)
+        .into_iter()
+        .filter_map(|cycle| {
+            let values: Vec<f64> = off
+                .iter()
+                .filter(|r| r.cycle == cycle)
+                .map(|r| r.voltage)
+                .collect();
+            (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+        })
+        .collect();
+    if means.len() < 2 {
+        return;
+    }
+
+    let highest = means.iter().copied().fold(f64::MIN, f64::max);
+    let lowest = means.iter().copied().fold(f64::MAX, f64::min);
+    if highest - lowest > limits.max_cycle_spread {
+        issues.push(issue(
+            "cycle_spread",
+            Verdict::Fail,
+            format!(
+                "Cycle averages differ by {:.4} V (at most {} V allowed).",
+                highest - lowest,
+                limits.max_cycle_spread
+            ),
+        ));
+    }
+}
+
+fn check_flat_or_saturated(
+    readings: &[Reading],
+    limits: &QualityLimits,
+    issues: &mut Vec<QualityIssue>,
+) {
+    let pinned = |v: f64| {
+        v <= limits.min_voltage + SATURATION_MARGIN || v >= limits.max_voltage - SATURATION_MARGIN
+    };
+    let mut run = 0;
+    let mut longest = 0;
+    for reading in readings {
+        run = if pinned(reading.voltage) { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    if longest >= SATURATION_RUN {
+        issues.push(issue(
+            "saturation",
+            Verdict::Fail,
+            format!(
+                "{} consecutive readings at the edge of the {}–{} V range.",
+                longest, limits.min_voltage, limits.max_voltage
+            ),
+        ));
+        return;
+    }
+
+    if readings.len() >= 3 {
+        let highest = readings.iter().map(|r| r.voltage).fold(f64::MIN, f64::max);
+        let lowest = readings.iter().map(|r| r.voltage).fold(f64::MAX, f64::min);
+        if highest - lowest < FLAT_LINE_TOLERANCE {
+            issues.push(issue(
+                "flat_line",
+                Verdict::Fail,
+                format!("Signal is flat at {:.4} V.", lowest),
+            ));
+        }
+    }
+}
+
+fn check_dropped_lines(readings: &[Reading], issues: &mut Vec<QualityIssue>) {
+    let times: Vec<NaiveDateTime> = readings.iter().filter_map(|r| r.received_at).collect();
+    let mut intervals: Vec<f64> = times
+        .windows(2)
+        .map(|w| (w[1] - w[0]).num_milliseconds() as f64)
+        .collect();
+    if intervals.len() < 3 {
+        return;
+    }
+    let gaps: Vec<f64> = intervals.clone();
+    intervals.sort_by(f64::total_cmp);
+    let typical = intervals[intervals.len() / 2];
+    if typical <= 0.0 {
+        return;
+    }
+
+    let missing: f64 = gaps
+        .iter()
+        .filter(|gap| **gap > typical * GAP_FACTOR)
+        .map(|gap| (gap / typical).round() - 1.0)
+        .sum();
+    if missing >= 1.0 {
+        issues.push(issue(
+            "dropped_lines",
+            Verdict::Warn,
+            format!("About {} reading(s) appear to be missing.", missing as u64),
+        ));
+    }
+}
+
+/// Readings stored for a session, in the order they arrived.
+pub fn load_readings(conn: &Connection, session_id: i64) -> rusqlite::Result<Vec<Reading>> {
+    let mut stmt = conn.prepare(
+        "SELECT phase, voltage, cycle_index, received_at FROM acquisition_lines
+         WHERE session_id = ?1 AND voltage IS NOT NULL
+         ORDER BY line_no",
+    )?;
+    let rows = stmt
+        .query_map(params![session_id], |row| {
+            let phase: Option<String> = row.get(0)?;
+            let received_at: String = row.get(3)?;
+            Ok(Reading {
+                phase: phase.as_deref().and_then(|p| match p {
+                    "ON" => Some(Phase::On),
+                    "OFF" => Some(Phase::Off),
+                    _ => None,
+                }),
+                voltage: row.get(1)?,
+                cycle: row.get::<_, Option<u32>>(2)?.unwrap_or(1),
+                received_at: NaiveDateTime::parse_from_str(&received_at, TIMESTAMP_FORMAT).ok(),
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rows)
+}
+
+/// Checks a session against the limits of the profile it was recorded with,
+/// and stores the verdict on it.
+pub fn assess_session(conn: &Connection, session_id: i64) -> rusqlite::Result<QualityReport> {
+    let profile_id: Option<i64> = conn.query_row(
+        "SELECT protocol_profile_id FROM acquisition_sessions WHERE id = ?1",
+        params![session_id],
+        |row| row.get(0),
+    )?;
+    let profile = match profile_id {
+        Some(id) => protocol::profile_by_id(conn, id)?,
+        None => None,
+    }
+    .unwrap_or_default();
+
+    let report = evaluate(
+        &load_readings(conn, session_id)?,
+        &QualityLimits::from(&profile),
+    );
+    let issues = serde_json::to_string(&report.issues)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    conn.execute(
+        "UPDATE acquisition_sessions SET quality_verdict = ?2, quality_issues = ?3 WHERE id = ?1",
+        params![session_id, report.verdict.as_str(), issues],
+    )?;
+    Ok(report)
+}
+
+/// Stored verdict of a session, assessing it first if that never happened.
+pub fn session_report(conn: &Connection, session_id: i64) -> rusqlite::Result<QualityReport> {
+    let stored: Option<(Option<String>, Option<String>)> = conn
+        .query_row(
+            "SELECT quality_verdict, quality_issues FROM acquisition_sessions WHERE id = ?1",
+            params![session_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    match stored {
+        Some((Some(verdict), Some(issues))) => {
+            let verdict = match verdict.as_str() {
+                "pass" => Verdict::Pass,
+                "warn" => Verdict::Warn,
+                _ => Verdict::Fail,
+            };
+            let issues = serde_json::from_str(&issues).unwrap_or_default();
+            Ok(QualityReport { verdict, issues })
+        }
+        Some(_) => assess_session(conn, session_id),
+        None => Err(rusqlite::Error::QueryReturnedNoRows),
+    }
+}
+
+/* ----------------------------------------
+   TAURI COMMANDS
+----------------------------------------- */
+
+/// Re-runs the checks, e.g. after the profile's limits were changed.
+#[tauri::command]
+pub fn assess_acquisition_session(
+    db: State<'_, Database>,
+    session_id: i64,
+) -> Result<QualityReport, String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+    assess_session(&conn, session_id).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: QualityLimits = QualityLimits {
+        min_voltage: 0.0,
+        max_voltage: 5.0,
+        off_readings_per_cycle: 3,
+        max_cycle_spread: 0.05,
+    };
+
+    /// A run of `cycles`, each with one ON and three OFF readings 500 ms apart.
+    fn run(cycles: &[[f64; 3]]) -> Vec<Reading> {
+        let start =
+            NaiveDateTime::parse_from_str("2024-01-01 08:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let mut readings = Vec::new();
+        for (i, off) in cycles.iter().enumerate() {
+            let cycle = i as u32 + 1;
+            readings.push(Reading {
+                phase: Some(Phase::On),
+                voltage: 1.1 + i as f64 * 0.3,
+                cycle,
+                received_at: None,
+            });
+            for &voltage in off {
+                readings.push(Reading {
+                    phase: Some(Phase::Off),
+                    voltage,
+                    cycle,
+                    received_at: None,
+                });
+            }
+        }
+        for (i, reading) in readings.iter_mut().enumerate() {
+            reading.received_at = Some(start + chrono::Duration::milliseconds(500 * i as i64));
+        }
+        readings
+    }
+
+    fn checks(report: &QualityReport) -> Vec<&str> {
+        report.issues.iter().map(|i| i.check.as_str()).collect()
+    }
+
+    #[test]
+    fn a_clean_run_passes() {
+        let report = evaluate(&run(&[[0.45, 0.46, 0.44], [0.47, 0.45, 0.46]]), &LIMITS);
+
+        assert_eq!(report.verdict, Verdict::Pass);
+        assert!(report.issues.is_empty());
+    }
+
+    #[test]
+    fn fails_a_run_without_readings() {
+        let report = evaluate(&[], &LIMITS);
+
+        assert_eq!(report.verdict, Verdict::Fail);
+        assert_eq!(checks(&report), ["no_readings"]);
+    }
+
+    #[test]
+    fn fails_readings_out_of_range() {
+        let report = evaluate(&run(&[[0.45, 5.2, 0.44]]), &LIMITS);
+
+        assert_eq!(report.verdict, Verdict::Fail);
+        assert_eq!(checks(&report), ["range"]);
+    }
+
+    #[test]
+    fn counts_off_readings_per_cycle() {
+        let mut readings = run(&[[0.45, 0.46, 0.44], [0.47, 0.45, 0.46]]);
+        readings.remove(7);
+        // A phase-less reading counts as OFF
+        readings[1].phase = None;
+
+        let report = evaluate(&readings, &LIMITS);
+
+        assert_eq!(checks(&report), ["off_readings"]);
+        assert!(report.issues[0].message.contains("cycle 2: 2"));
+    }
+
+    #[test]
+    fn compares_only_off_averages_across_cycles() {
+        // The ON readings differ by 0.3 V between cycles and must be ignored
+        let close = evaluate(&run(&[[0.45, 0.45, 0.45], [0.48, 0.48, 0.48]]), &LIMITS);
+        assert!(close.issues.is_empty());
+
+        let apart = evaluate(&run(&[[0.45, 0.45, 0.45], [0.60, 0.61, 0.59]]), &LIMITS);
+        assert_eq!(checks(&apart), ["cycle_spread"]);
+    }
+
+    #[test]
+    fn disabled_limits_skip_their_checks() {
+        let limits = QualityLimits {
+            off_readings_per_cycle: 0,
+            max_cycle_spread: 0.0,
+            ..LIMITS
+        };
+        let mut readings = run(&[[0.45, 0.45, 0.45], [0.90, 0.91, 0.89]]);
+        readings.pop();
+
+        assert_eq!(evaluate(&readings, &limits).verdict, Verdict::Pass);
+    }
+
+    #[test]
+    fn fails_flat_and_saturated_signals() {
+        let flat: Vec<Reading> = run(&[[0.45, 0.45, 0.45]])
+            .into_iter()
+            .map(|r| Reading { voltage: 0.45, ..r })
+            .collect();
+        assert_eq!(checks(&evaluate(&flat, &LIMITS)), ["flat_line"]);
+
+        let pinned = evaluate(&run(&[[4.995, 5.0, 4.999]]), &LIMITS);
+        assert_eq!(checks(&pinned), ["saturation"]);
+    }
+
+    #[test]
+    fn warns_about_dropped_lines() {
+        let mut readings = run(&[[0.45, 0.46, 0.44], [0.47, 0.45, 0.46]]);
+        for reading in &mut readings[5..] {
+            reading.received_at = reading
+                .received_at
+                .map(|t| t + chrono::Duration::milliseconds(1500));
+        }
+
+        let report = evaluate(&readings, &LIMITS);
+
+        assert_eq!(report.verdict, Verdict::Warn);
+        assert_eq!(checks(&report), ["dropped_lines"]);
+        assert!(report.issues[0].message.contains("About 3 "));
+    }
+}
@@ -0,0 +1,564 @@
+// src/reader.rs
+//
+// Hardware-independent acquisition loop: splits the transport's byte stream
+// into lines, applies the no-data timeout and detects the end of a run.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::error;
+
+use crate::measurement::{MeasurementParser, ParsedLine};
+use crate::transport::Transport;
+
+/// What `LineReader::next_event` observed on the transport.
+#[derive(Debug, PartialEq)]
+pub enum ReadEvent {
+    /// A complete line, without its line ending.
+    Line(String),
+    /// Nothing arrived within the transport's read timeout.
+    Idle,
+    /// The connection closed; carries any unterminated trailing data.
+    Closed { partial: Option<String> },
+}
+
+/// Buffers bytes until a full line is available, so lines split across
+/// several reads are reassembled.
+pub struct LineReader<T: Transport> {
+    transport: T,
+    buffer: Vec<u8>,
+}
+
+impl<T: Transport> LineReader<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn next_event(&mut self) -> io::Result<ReadEvent> {
+        let mut chunk = [0u8; 256];
+        loop {
+            if let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+                return Ok(ReadEvent::Line(decode(&line)));
+            }
+
+            match self.transport.read(&mut chunk) {
+                Ok(0) => {
+                    let partial = (!self.buffer.is_empty())
+                        .then(|| decode(&std::mem::take(&mut self.buffer)));
+                    return Ok(ReadEvent::Closed { partial });
+                }
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => return Ok(ReadEvent::Idle),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn decode(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches(['\r', '\n'])
+        .to_string()
+}
+
+#[derive(Clone, Debug)]
+pub struct ReadLoopConfig {
+    /// The run fails if no non-empty line arrives for this long.
+    pub no_data_timeout: Duration,
+    /// A run that ends with fewer cycles than this is incomplete; 0 disables the check.
+    pub expected_cycles: u32,
+}
+
+/// Result delivered back to whoever queued a `DeviceCommand`: the reply line
+/// if one was awaited, `None` once the command has merely been written.
+pub type CommandResult = Result<Option<String>, String>;
+
+/// A line of text for the firmware, written by the read loop that owns the
+/// port so commands never race the reader for the device handle.
+pub struct DeviceCommand {
+    pub text: String,
+    /// Wait for the first line starting with this prefix ("" accepts any line).
+    pub reply_prefix: Option<String>,
+    pub timeout: Duration,
+    pub reply: Sender<CommandResult>,
+}
+
+struct PendingReply {
+    prefix: String,
+    deadline: Instant,
+    reply: Sender<CommandResult>,
+}
+
+/// Asks a read loop running on another thread to stop. Checked between
+/// reads, so it takes effect within one transport read timeout.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// How an acquisition run ended.
+#[derive(Debug, PartialEq)]
+pub enum ReadOutcome {
+    Completed {
+        cycles: u32,
+    },
+    /// The end marker arrived before the expected number of cycles.
+    Incomplete {
+        cycles: u32,
+        expected: u32,
+    },
+    NoData,
+    Disconnected,
+    Failed(String),
+    /// Stopped through the `CancelToken`.
+    Cancelled,
+}
+
+/// Writes queued commands and registers the ones waiting for a reply.
+fn dispatch_commands<T: Transport>(
+    reader: &mut LineReader<T>,
+    commands: &Receiver<DeviceCommand>,
+    pending: &mut Vec<PendingReply>,
+) {
+    while let Ok(command) = commands.try_recv() {
+        let line = format!("{}\n", command.text.trim_end());
+        if let Err(e) = reader.transport_mut().write_all(line.as_bytes()) {
+            error!("Failed to write command '{}': {}", command.text, e);
+            let _ = command.reply.send(Err(e.to_string()));
+            continue;
+        }
+
+        match command.reply_prefix {
+            Some(prefix) => pending.push(PendingReply {
+                prefix,
+                deadline: Instant::now() + command.timeout,
+                reply: command.reply,
+            }),
+            None => {
+                let _ = command.reply.send(Ok(None));
+            }
+        }
+    }
+
+    let now = Instant::now();
+    pending.retain(|p| p.deadline > now);
+}
+
+/// Reads until the firmware reports completion or something goes wrong.
+/// `on_line` sees every non-empty line, including a trailing partial line
+/// left behind by a disconnect. Commands arriving on `commands` are written
+/// between reads; reply lines are still passed to `on_line`.
+pub fn run_read_loop<T: Transport>(
+    reader: &mut LineReader<T>,
+    parser: &mut MeasurementParser,
+    config: &ReadLoopConfig,
+    commands: Option<&Receiver<DeviceCommand>>,
+    cancel: Option<&CancelToken>,
+    mut on_line: impl FnMut(&str, &ParsedLine),
+) -> ReadOutcome {
+    let mut last_data_time = Instant::now();
+    let mut pending: Vec<PendingReply> = Vec::new();
+
+    loop {
+        if cancel.is_some_and(CancelToken::is_cancelled) {
+            return ReadOutcome::Cancelled;
+        }
+        if last_data_time.elapsed() > config.no_data_timeout {
+            return ReadOutcome::NoData;
+        }
+
+        if let Some(commands) = commands {
+            dispatch_commands(reader, commands, &mut pending);
+        }
+
+        match reader.next_event() {
+            Ok(ReadEvent::Line(raw)) => {
+                if raw.trim().is_empty() {
+                    continue;
+                }
+                last_data_time = Instant::now();
+
+                let text = raw.trim();
+                if let Some(i) = pending.iter().position(|p| text.starts_with(&p.prefix)) {
+                    let _ = pending.remove(i).reply.send(Ok(Some(text.to_string())));
+                }
+
+                let parsed = parser.parse_line(&raw);
+                on_line(&raw, &parsed);
+
+                if parsed == ParsedLine::Completed {
+                    let cycles = parser.cycles_seen();
+                    if cycles < config.expected_cycles {
+                        return ReadOutcome::Incomplete {
+                            cycles,
+                            expected: config.expected_cycles,
+                        };
+                    }
+                    return ReadOutcome::Completed { cycles };
+                }
+            }
+            Ok(ReadEvent::Idle) => continue,
+            Ok(ReadEvent::Closed { partial }) => {
+                if let Some(raw) = partial.filter(|p| !p.trim().is_empty()) {
+                    // Never treat a truncated line as a measurement.
+                    on_line(&raw, &ParsedLine::Other);
+                }
+                return ReadOutcome::Disconnected;
+            }
+            Err(e) => return ReadOutcome::Failed(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::Phase;
+    use crate::transport::{MemoryEvent, MemoryTransport, READ_TIMEOUT};
+    use std::sync::mpsc::{self, TryRecvError};
+    use std::sync::Mutex;
+
+    fn data(text: &str) -> MemoryEvent {
+        MemoryEvent::Data(text.as_bytes().to_vec())
+    }
+
+    fn config(no_data_timeout: Duration) -> ReadLoopConfig {
+        ReadLoopConfig {
+            no_data_timeout,
+            expected_cycles: 0,
+        }
+    }
+
+    /// Runs the loop over `script`, returning its outcome and the lines it saw.
+    fn run(
+        script: Vec<MemoryEvent>,
+        config: &ReadLoopConfig,
+        cancel: Option<&CancelToken>,
+    ) -> (ReadOutcome, Vec<(String, ParsedLine)>) {
+        let mut reader = LineReader::new(MemoryTransport::new(script));
+        let mut parser = MeasurementParser::new();
+        let mut lines = Vec::new();
+        let outcome = run_read_loop(
+            &mut reader,
+            &mut parser,
+            config,
+            None,
+            cancel,
+            |raw, parsed| {
+                lines.push((raw.to_string(), parsed.clone()));
+            },
+        );
+        (outcome, lines)
+    }
+
+    /// A board answering each command line with `replies`, recording what was written.
+    fn board(
+        script: Vec<MemoryEvent>,
+        replies: &'static [&'static str],
+    ) -> (MemoryTransport, Arc<Mutex<Vec<String>>>) {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let log = written.clone();
+        let transport = MemoryTransport::new(script).with_responder(Box::new(move |command| {
+            log.lock().unwrap().push(command.to_string());
+            replies.iter().map(|r| r.to_string()).collect()
+        }));
+        (transport, written)
+    }
+
+    fn command(
+        text: &str,
+        reply_prefix: Option<&str>,
+        timeout: Duration,
+    ) -> (DeviceCommand, Receiver<CommandResult>) {
+        let (reply, result) = mpsc::channel();
+        let command = DeviceCommand {
+            text: text.to_string(),
+            reply_prefix: reply_prefix.map(str::to_string),
+            timeout,
+            reply,
+        };
+        (command, result)
+    }
+
+    /// Runs the loop with `commands` queued before the first read.
+    fn run_with_commands(
+        transport: MemoryTransport,
+        commands: Vec<DeviceCommand>,
+    ) -> (ReadOutcome, Vec<String>) {
+        let (queue, received) = mpsc::channel();
+        for command in commands {
+            queue.send(command).unwrap();
+        }
+        let mut reader = LineReader::new(transport);
+        let mut parser = MeasurementParser::new();
+        let mut lines = Vec::new();
+        let outcome = run_read_loop(
+            &mut reader,
+            &mut parser,
+            &config(Duration::from_secs(5)),
+            Some(&received),
+            None,
+            |raw, _| lines.push(raw.to_string()),
+        );
+        (outcome, lines)
+    }
+
+    #[test]
+    fn reassembles_a_line_split_across_reads() {
+        let mut reader = LineReader::new(MemoryTransport::new([
+            data("Output Volt"),
+            data("age (OFF): 0.4500 V\r\nCycle"),
+            data(" 2\n"),
+            MemoryEvent::Disconnect,
+        ]));
+
+        assert_eq!(
+            reader.next_event().unwrap(),
+            ReadEvent::Line("Output Voltage (OFF): 0.4500 V".into())
+        );
+        assert_eq!(
+            reader.next_event().unwrap(),
+            ReadEvent::Line("Cycle 2".into())
+        );
+        assert_eq!(
+            reader.next_event().unwrap(),
+            ReadEvent::Closed { partial: None }
+        );
+    }
+
+    #[test]
+    fn completes_on_the_end_marker() {
+        let (outcome, lines) = run(
+            vec![
+                data("Output Voltage (ON): 1.1000 V\r\nOutput Volt"),
+                data("age (OFF): 0.4500 V\r\n"),
+                data("1 cycles completed\r\n"),
+            ],
+            &config(Duration::from_secs(5)),
+            None,
+        );
+
+        assert_eq!(outcome, ReadOutcome::Completed { cycles: 1 });
+        assert_eq!(lines.len(), 3);
+        assert!(matches!(
+            &lines[1].1,
+            ParsedLine::Measurement(m) if m.phase == Phase::Off && m.voltage == 0.45
+        ));
+    }
+
+    #[test]
+    fn reports_too_few_cycles_as_incomplete() {
+        let config = ReadLoopConfig {
+            no_data_timeout: Duration::from_secs(5),
+            expected_cycles: 3,
+        };
+        let (outcome, _) = run(
+            vec![data(
+                "Output Voltage (ON): 1.1 V\nOutput Voltage (OFF): 0.4 V\ncycles completed\n",
+            )],
+            &config,
+            None,
+        );
+
+        assert_eq!(
+            outcome,
+            ReadOutcome::Incomplete {
+                cycles: 1,
+                expected: 3
+            }
+        );
+    }
+
+    #[test]
+    fn passes_on_a_truncated_line_when_disconnected() {
+        let (outcome, lines) = run(
+            vec![
+                data("Output Voltage (ON): 1.1000 V\r\nOutput Voltage (OFF): 0.4"),
+                MemoryEvent::Disconnect,
+            ],
+            &config(Duration::from_secs(5)),
+            None,
+        );
+
+        assert_eq!(outcome, ReadOutcome::Disconnected);
+        assert_eq!(lines.len(), 2);
+        // The cut-off reading must not be taken as a measurement
+        assert_eq!(
+            lines[1],
+            ("Output Voltage (OFF): 0.4".to_string(), ParsedLine::Other)
+        );
+    }
+
+    #[test]
+    fn times_out_when_the_board_goes_silent() {
+        let (outcome, lines) = run(
+            vec![
+                data("Output Voltage (ON): 1.1000 V\n"),
+                MemoryEvent::Delay(Duration::from_secs(3)),
+                data("Output Voltage (OFF): 0.4500 V\n"),
+            ],
+            &config(Duration::from_millis(200)),
+            None,
+        );
+
+        assert_eq!(outcome, ReadOutcome::NoData);
+        assert_eq!(lines.len(), 1);
+    }
+
+    #[test]
+    fn fails_on_a_read_error() {
+        let (outcome, _) = run(
+            vec![
+                data("Output Voltage (ON): 1.1000 V\n"),
+                MemoryEvent::Error(io::ErrorKind::BrokenPipe),
+            ],
+            &config(Duration::from_secs(5)),
+            None,
+        );
+
+        assert!(matches!(outcome, ReadOutcome::Failed(_)));
+    }
+
+    #[test]
+    fn stops_once_cancelled() {
+        let cancel = CancelToken::default();
+        let mut reader = LineReader::new(MemoryTransport::new([data(
+            "Output Voltage (ON): 1.1 V\nOutput Voltage (OFF): 0.4 V\ncycles completed\n",
+        )]));
+        let mut parser = MeasurementParser::new();
+        let mut seen = 0;
+
+        let outcome = run_read_loop(
+            &mut reader,
+            &mut parser,
+            &config(Duration::from_secs(5)),
+            None,
+            Some(&cancel),
+            |_, _| {
+                seen += 1;
+                cancel.cancel();
+            },
+        );
+
+        assert_eq!(outcome, ReadOutcome::Cancelled);
+        assert_eq!(seen, 1);
+    }
+
+    #[test]
+    fn writes_a_command_that_expects_no_reply() {
+        let (transport, written) = board(vec![data("cycles completed\n")], &[]);
+        let (start, result) = command("START", None, Duration::from_secs(1));
+
+        let (outcome, _) = run_with_commands(transport, vec![start]);
+
+        assert_eq!(outcome, ReadOutcome::Completed { cycles: 0 });
+        assert_eq!(*written.lock().unwrap(), ["START"]);
+        assert_eq!(result.try_recv().unwrap(), Ok(None));
+    }
+
+    #[test]
+    fn answers_with_the_first_line_matching_the_prefix() {
+        let (transport, _) = board(
+            vec![data("cycles completed\n")],
+            &["BUSY", "ID nexus-cancer v1.2", "ID again"],
+        );
+        let (identify, result) = command("ID?", Some("ID"), Duration::from_secs(1));
+
+        let (_, lines) = run_with_commands(transport, vec![identify]);
+
+        assert_eq!(
+            result.try_recv().unwrap(),
+            Ok(Some("ID nexus-cancer v1.2".to_string()))
+        );
+        // Replies are ordinary lines of the run as well
+        assert_eq!(
+            lines,
+            [
+                "BUSY",
+                "ID nexus-cancer v1.2",
+                "ID again",
+                "cycles completed"
+            ]
+        );
+    }
+
+    #[test]
+    fn an_empty_prefix_accepts_the_first_line() {
+        let (transport, _) = board(vec![data("cycles completed\n")], &["OK", "READY"]);
+        let (ping, result) = command("PING", Some(""), Duration::from_secs(1));
+
+        run_with_commands(transport, vec![ping]);
+
+        assert_eq!(result.try_recv().unwrap(), Ok(Some("OK".to_string())));
+    }
+
+    #[test]
+    fn drops_a_reply_that_arrives_after_the_timeout() {
+        // Longer than one read, so the loop notices the deadline before the line arrives
+        let (transport, _) = board(
+            vec![
+                MemoryEvent::Delay(READ_TIMEOUT + Duration::from_millis(100)),
+                data("ID nexus-cancer v1.2\ncycles completed\n"),
+            ],
+            &[],
+        );
+        let (identify, result) = command("ID?", Some("ID"), Duration::from_millis(50));
+
+        let (outcome, lines) = run_with_commands(transport, vec![identify]);
+
+        assert_eq!(outcome, ReadOutcome::Completed { cycles: 0 });
+        assert_eq!(lines[0], "ID nexus-cancer v1.2");
+        assert_eq!(result.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn writes_nothing_once_cancelled() {
+        let (transport, written) = board(
+            vec![data("Output Voltage (ON): 1.1 V\ncycles completed\n")],
+            &["OK"],
+        );
+        let cancel = CancelToken::default();
+        let (queue, received) = mpsc::channel();
+        let mut reader = LineReader::new(transport);
+        let mut parser = MeasurementParser::new();
+        let mut results = Vec::new();
+
+        let outcome = run_read_loop(
+            &mut reader,
+            &mut parser,
+            &config(Duration::from_secs(5)),
+            Some(&received),
+            Some(&cancel),
+            |_, _| {
+                cancel.cancel();
+                let (stop, result) = command("STOP", None, Duration::from_secs(1));
+                queue.send(stop).unwrap();
+                results.push(result);
+            },
+        );
+
+        assert_eq!(outcome, ReadOutcome::Cancelled);
+        assert!(written.lock().unwrap().is_empty());
+        assert_eq!(results.len(), 1);
+        assert!(results[0].try_recv().is_err());
+    }
+}
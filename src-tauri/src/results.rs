@@ -0,0 +1,200 @@
+// src/results.rs
+//
+// Measured values of an admission, one `test_results` row per sample (the
+// reference cells or the cancer cells) and one `result_values` row per
+// reading. They used to live in the admissions table as JSON strings such as
+// `{"voltage_off":[...]}`; the commands still hand that shape to the UI, built
+// from these tables.
+
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+use crate::database::Database;
+
+/// Unit stored when the acquisition did not report one.
+const DEFAULT_UNIT: &str = "V";
+
+#[derive(Serialize, Debug)]
+pub struct ResultValue {
+    pub position: u32,
+    pub cycle_index: Option<u32>,
+    pub phase: Option<String>,
+    pub value: f64,
+    pub unit: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TestResult {
+    pub id: i64,
+    pub admission_id: i64,
+    /// `reference` or `cancer`.
+    pub sample_type: String,
+    pub acquisition_session_id: Option<i64>,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub device_serial: Option<String>,
+    /// Calibration the values were corrected with, if the device had one.
+    pub calibration_id: Option<i64>,
+    /// Firmware the board reported when the values were recorded.
+    pub firmware_name: Option<String>,
+    pub firmware_version: Option<String>,
+    pub values: Vec<ResultValue>,
+}
+
+/// A sample in the legacy JSON form. `{}`, which the commands return for a
+/// missing sample, holds no readings.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LegacySample {
+    #[serde(default)]
+    voltage_off: Vec<f64>,
+}
+
+/// Readings in the legacy `{"voltage_off":[...]}` form the UI submits.
+pub fn parse_legacy(json: &str) -> Result<Vec<f64>, serde_json::Error> {
+    serde_json::from_str::<LegacySample>(json).map(|sample| sample.voltage_off)
+}
+
+/// SQL expression rebuilding the legacy JSON of one sample of admission
+/// `admission`, for commands that still return it.
+pub fn sample_json_sql(admission: &str, sample_type: &str) -> String {
+    format!(
+        "COALESCE((SELECT CASE WHEN COUNT(*) > 0 THEN
+                       json_object('voltage_off', json_group_array(v.value ORDER BY v.position))
+                   END
+                   FROM test_results r JOIN result_values v ON v.result_id = r.id
+                   WHERE r.admission_id = {} AND r.sample_type = '{}'), '{{}}')",
+        admission, sample_type
+    )
+}
+
+/// Stores one sample of an admission from the OFF readings a completed
+/// acquisition session recorded. They are already calibrated, and the device,
+/// calibration and firmware that produced them are recorded with them. Returns
+/// `None` when the session recorded no OFF readings.
+pub fn store_session_sample(
+    conn: &Connection,
+    admission_id: i64,
+    sample_type: &str,
+    session_id: i64,
+) -> rusqlite::Result<Option<i64>> {
+    let readings: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM acquisition_lines
+         WHERE session_id = ?1 AND voltage IS NOT NULL AND COALESCE(phase, 'OFF') = 'OFF'",
+        params![session_id],
+        |row| row.get(0),
+    )?;
+    if readings == 0 {
+        return Ok(None);
+    }
+
+    conn.execute(
+        "INSERT INTO test_results (
+            admission_id, sample_type, acquisition_session_id, vid, pid, device_serial,
+            calibration_id, firmware_name, firmware_version
+         )
+         SELECT ?1, ?2, id, vid, pid, device_serial, calibration_id, firmware_name,
+                firmware_version
+         FROM acquisition_sessions WHERE id = ?3",
+        params![admission_id, sample_type, session_id],
+    )?;
+    let result_id = conn.last_insert_rowid();
+
+    conn.execute(
+        "INSERT INTO result_values (result_id, position, cycle_index, phase, value, unit)
+         SELECT ?1, ROW_NUMBER() OVER (ORDER BY line_no) - 1, cycle_index, 'OFF', voltage,
+                COALESCE(unit, ?3)
+         FROM acquisition_lines
+         WHERE session_id = ?2 AND voltage IS NOT NULL AND COALESCE(phase, 'OFF') = 'OFF'",
+        params![result_id, session_id, DEFAULT_UNIT],
+    )?;
+
+    Ok(Some(result_id))
+}
+
+pub fn load_results(conn: &Connection, admission_id: i64) -> rusqlite::Result<Vec<TestResult>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, admission_id, sample_type, acquisition_session_id, vid, pid, device_serial,
+                calibration_id, firmware_name, firmware_version
+         FROM test_results WHERE admission_id = ?1 ORDER BY id",
+    )?;
+    let mut results = stmt
+        .query_map(params![admission_id], |row| {
+            Ok(TestResult {
+                id: row.get(0)?,
+                admission_id: row.get(1)?,
+                sample_type: row.get(2)?,
+                acquisition_session_id: row.get(3)?,
+                vid: row.get(4)?,
+                pid: row.get(5)?,
+                device_serial: row.get(6)?,
+                calibration_id: row.get(7)?,
+                firmware_name: row.get(8)?,
+                firmware_version: row.get(9)?,
+                values: Vec::new(),
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT position, cycle_index, phase, value, unit FROM result_values
+         WHERE result_id = ?1 ORDER BY position",
+    )?;
+    for result in results.iter_mut() {
+        result.values = stmt
+            .query_map(params![result.id], |row| {
+                Ok(ResultValue {
+                    position: row.get(0)?,
+                    cycle_index: row.get(1)?,
+                    phase: row.get(2)?,
+                    value: row.get(3)?,
+                    unit: row.get(4)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+    }
+    Ok(results)
+}
+
+/* ----------------------------------------
+   TAURI COMMANDS
+----------------------------------------- */
+
+/// Every sample of an admission with its individual readings.
+#[tauri::command]
+pub fn get_admission_results(
+    db: State<'_, Database>,
+    admission_id: i64,
+) -> Result<Vec<TestResult>, String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+    load_results(&conn, admission_id).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_legacy_sample_shape() {
+        assert_eq!(
+            parse_legacy(r#"{"voltage_off": [0.45, 0.46, -0.01]}"#).unwrap(),
+            [0.45, 0.46, -0.01]
+        );
+        assert!(parse_legacy("{}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        for json in [
+            "",
+            "[0.45]",
+            r#"{"voltage_off": "0.45"}"#,
+            r#"{"voltage_off": [0.45, "0.46"]}"#,
+            r#"{"voltage_off": [0.45], "voltage_on": [1.1]}"#,
+            r#"{"voltage": [0.45]}"#,
+        ] {
+            assert!(parse_legacy(json).is_err(), "{}", json);
+        }
+    }
+}
@@ -0,0 +1,394 @@
+// src/search.rs
+//
+// Free-text patient search. `patient_search` is an FTS5 index with one row
+// per encounter (rowid = encounter id) holding the admission number and the
+// patient's national ID and names; triggers on `patients` and `encounters`
+// keep it in sync. Every word typed must match the start of a word in one of
+// those columns, accents ignored, and results are ranked with bm25.
+//
+// `AdmissionFilter` adds the structured filters of the admission views on
+// top of that search.
+
+use chrono::{Days, NaiveDate};
+use rusqlite::types::Value;
+use serde::Deserialize;
+
+/// bm25 column weights, in `patient_search` column order: admission number,
+/// national ID, first name, last name. An exact ID hit outranks a name hit.
+const COLUMN_WEIGHTS: &str = "10.0, 10.0, 5.0, 5.0";
+
+/// FTS5 query for what the user typed: every word becomes a quoted prefix
+/// term, so `jane 1234` finds Jane with an ID or admission number starting
+/// with 1234. Punctuation splits words like the index tokenizer does, which
+/// also keeps FTS5 operators out of the query. `None` when nothing is left.
+pub fn match_expression(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| format!("\"{}\"*", term))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+/// Join restricting `encounters e` to those matching `query`, exposing their
+/// `s.relevance` (lower is better) for ORDER BY. With nothing to search for
+/// every encounter is kept at equal relevance.
+pub fn encounter_match_join(query: &str) -> String {
+    match match_expression(query) {
+        // Terms only hold alphanumerics, quotes and `*`, so they can be inlined
+        Some(expression) => format!(
+            "INNER JOIN (
+                SELECT rowid AS encounter_id, bm25(patient_search, {}) AS relevance
+                FROM patient_search WHERE patient_search MATCH '{}'
+            ) s ON s.encounter_id = e.id",
+            COLUMN_WEIGHTS, expression
+        ),
+        None => "INNER JOIN (SELECT 0.0 AS relevance) s".to_string(),
+    }
+}
+
+/// Worst quality verdict among the sessions linked to an admission, or a
+/// failed run whose results were saved with an override.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityFlag {
+    Pass,
+    Warn,
+    Fail,
+    Overridden,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AdmissionSort {
+    /// Best search match first, then latest; the order without a query.
+    #[default]
+    Relevance,
+    Timestamp,
+    AdmissionNo,
+    Patient,
+    Doctor,
+    Technician,
+    DiabetesTest,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+/// Filter shared by the admission search, its count, the global stats and
+/// the latest-five chart, so all of them describe the same admissions.
+/// Every field is optional; empty strings count as not set.
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct AdmissionFilter {
+    /// Free text for the patient index, see `match_expression`.
+    pub query: Option<String>,
+    /// First and last day included, as `YYYY-MM-DD`.
+    pub date_from: Option<String>,
+    pub date_to: Option<String>,
+    /// Admission doctor or the encounter's doctor.
+    pub doctor: Option<String>,
+    pub technician: Option<String>,
+    pub classification: Option<String>,
+    pub test_type: Option<String>,
+    /// `devices.id` of a board that measured the admission.
+    pub device_id: Option<i64>,
+    pub quality: Option<QualityFlag>,
+    pub has_cancer: Option<bool>,
+    pub has_reference: Option<bool>,
+    pub has_diabetes: Option<bool>,
+    pub sort: AdmissionSort,
+    /// Defaults to newest or largest first for dates and values, A to Z for text.
+    pub direction: Option<SortDirection>,
+}
+
+fn parse_day(label: &str, value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("Invalid {} '{}', expected YYYY-MM-DD", label, value))
+}
+
+fn text(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+impl AdmissionFilter {
+    /// FROM and WHERE clauses over `admissions a`, `encounters e`,
+    /// `patients p` and the search join `s`, with the values for their `?`
+    /// placeholders in order.
+    pub fn sql_from(&self) -> Result<(String, Vec<Value>), String> {
+        let mut conditions: Vec<String> = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+
+        if let Some(from) = text(&self.date_from) {
+            let day = parse_day("start date", from)?;
+            conditions.push("a.timestamp >= ?".into());
+            values.push(Value::Text(day.format("%Y-%m-%d").to_string()));
+        }
+        if let Some(to) = text(&self.date_to) {
+            let day = parse_day("end date", to)? + Days::new(1);
+            conditions.push("a.timestamp < ?".into());
+            values.push(Value::Text(day.format("%Y-%m-%d").to_string()));
+        }
+        if let Some(doctor) = text(&self.doctor) {
+            conditions.push(
+                "(a.doctor_in_charge = ? COLLATE NOCASE OR e.doctor = ? COLLATE NOCASE)".into(),
+            );
+            values.push(Value::Text(doctor.to_string()));
+            values.push(Value::Text(doctor.to_string()));
+        }
+        for (column, value) in [
+            ("a.technician", &self.technician),
+            ("e.classification", &self.classification),
+            ("e.test_type", &self.test_type),
+        ] {
+            if let Some(value) = text(value) {
+                conditions.push(format!("{} = ? COLLATE NOCASE", column));
+                values.push(Value::Text(value.to_string()));
+            }
+        }
+        if let Some(device_id) = self.device_id {
+            conditions.push(
+                "EXISTS (
+                    SELECT 1 FROM devices d
+                    WHERE d.id = ? AND (
+                        EXISTS (SELECT 1 FROM test_results r
+                                WHERE r.admission_id = a.id AND r.vid = d.vid AND r.pid = d.pid
+                                  AND r.device_serial IS d.serial_number)
+                        OR EXISTS (SELECT 1 FROM acquisition_sessions q
+                                   WHERE q.admission_id = a.id AND q.vid = d.vid AND q.pid = d.pid
+                                     AND q.device_serial IS d.serial_number)
+                    )
+                )"
+                .into(),
+            );
+            values.push(Value::Integer(device_id));
+        }
+        if let Some(quality) = self.quality {
+            conditions.push(match quality {
+                QualityFlag::Overridden => "EXISTS (
+                    SELECT 1 FROM acquisition_sessions q
+                    WHERE q.admission_id = a.id AND q.quality_verdict = 'fail'
+                      AND q.quality_override_by IS NOT NULL
+                )"
+                .into(),
+                flag => format!(
+                    "(SELECT MAX(CASE q.quality_verdict
+                                 WHEN 'pass' THEN 1 WHEN 'warn' THEN 2 WHEN 'fail' THEN 3 END)
+                      FROM acquisition_sessions q WHERE q.admission_id = a.id) = {}",
+                    match flag {
+                        QualityFlag::Pass => 1,
+                        QualityFlag::Warn => 2,
+                        _ => 3,
+                    }
+                ),
+            });
+        }
+        for (sample_type, wanted) in [
+            ("cancer", self.has_cancer),
+            ("reference", self.has_reference),
+        ] {
+            if let Some(wanted) = wanted {
+                conditions.push(format!(
+                    "{}EXISTS (SELECT 1 FROM test_results r
+                               WHERE r.admission_id = a.id AND r.sample_type = '{}')",
+                    if wanted { "" } else { "NOT " },
+                    sample_type
+                ));
+            }
+        }
+        if let Some(wanted) = self.has_diabetes {
+            conditions.push(format!(
+                "a.diabetes_test IS {}NULL",
+                if wanted { "NOT " } else { "" }
+            ));
+        }
+
+        let mut clause = format!(
+            "FROM admissions a
+             INNER JOIN encounters e ON e.admission_no = a.admission_no
+             INNER JOIN patients p ON p.id = e.patient_id
+             {}",
+            encounter_match_join(self.query.as_deref().unwrap_or_default())
+        );
+        if !conditions.is_empty() {
+            clause.push_str("\n             WHERE ");
+            clause.push_str(&conditions.join("\n               AND "));
+        }
+        Ok((clause, values))
+    }
+
+    /// ORDER BY expression for the chosen sort, ties broken by admission id.
+    pub fn order_by(&self) -> String {
+        let direction = |default: SortDirection| match self.direction.unwrap_or(default) {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        };
+        let order = match self.sort {
+            AdmissionSort::Relevance => "s.relevance ASC, a.timestamp DESC".to_string(),
+            AdmissionSort::Timestamp => {
+                format!("a.timestamp {}", direction(SortDirection::Desc))
+            }
+            AdmissionSort::AdmissionNo => format!(
+                "a.admission_no COLLATE NOCASE {}",
+                direction(SortDirection::Asc)
+            ),
+            AdmissionSort::Patient => {
+                let dir = direction(SortDirection::Asc);
+                format!(
+                    "p.lastname COLLATE NOCASE {0}, p.firstname COLLATE NOCASE {0}",
+                    dir
+                )
+            }
+            AdmissionSort::Doctor => format!(
+                "a.doctor_in_charge COLLATE NOCASE {}",
+                direction(SortDirection::Asc)
+            ),
+            AdmissionSort::Technician => format!(
+                "a.technician COLLATE NOCASE {}",
+                direction(SortDirection::Asc)
+            ),
+            AdmissionSort::DiabetesTest => {
+                format!("a.diabetes_test {}", direction(SortDirection::Desc))
+            }
+        };
+        format!("{}, a.id DESC", order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turns_each_word_into_a_prefix_term() {
+        assert_eq!(
+            match_expression("  Jane  1234 "),
+            Some(r#""Jane"* "1234"*"#.to_string())
+        );
+        assert_eq!(
+            match_expression("Zoë O'Brien"),
+            Some(r#""Zoë"* "O"* "Brien"*"#.to_string())
+        );
+    }
+
+    #[test]
+    fn keeps_fts_syntax_out_of_the_query() {
+        assert_eq!(
+            match_expression(r#"jane" OR "x* NOT (a:b) ^c"#),
+            Some(r#""jane"* "OR"* "x"* "NOT"* "a"* "b"* "c"*"#.to_string())
+        );
+        assert_eq!(match_expression(r#" "*-: "#), None);
+    }
+
+    #[test]
+    fn an_empty_query_keeps_every_encounter() {
+        assert_eq!(
+            encounter_match_join("  "),
+            "INNER JOIN (SELECT 0.0 AS relevance) s"
+        );
+        let join = encounter_match_join("jane");
+        assert!(join.contains(r#"MATCH '"jane"*'"#), "{}", join);
+        assert!(join.contains(COLUMN_WEIGHTS), "{}", join);
+    }
+
+    fn placeholders(sql: &str) -> usize {
+        sql.matches('?').count()
+    }
+
+    #[test]
+    fn an_empty_filter_has_no_conditions() {
+        let filter = AdmissionFilter {
+            query: Some(" ".into()),
+            doctor: Some("".into()),
+            technician: Some("  ".into()),
+            ..Default::default()
+        };
+        let (sql, values) = filter.sql_from().unwrap();
+
+        assert!(!sql.contains("WHERE"), "{}", sql);
+        assert!(values.is_empty());
+        assert_eq!(
+            filter.order_by(),
+            "s.relevance ASC, a.timestamp DESC, a.id DESC"
+        );
+    }
+
+    #[test]
+    fn binds_a_value_for_every_placeholder() {
+        let filter = AdmissionFilter {
+            query: Some("jane".into()),
+            date_from: Some("2024-02-01".into()),
+            date_to: Some("2024-02-29".into()),
+            doctor: Some(" Dr Mwangi ".into()),
+            technician: Some("Achieng".into()),
+            device_id: Some(7),
+            quality: Some(QualityFlag::Warn),
+            has_cancer: Some(true),
+            has_reference: Some(false),
+            has_diabetes: Some(false),
+            ..Default::default()
+        };
+        let (sql, values) = filter.sql_from().unwrap();
+
+        assert_eq!(placeholders(&sql), values.len(), "{}", sql);
+        assert_eq!(
+            values,
+            [
+                Value::Text("2024-02-01".into()),
+                // The end date is included, so the bound is the next day
+                Value::Text("2024-03-01".into()),
+                Value::Text("Dr Mwangi".into()),
+                Value::Text("Dr Mwangi".into()),
+                Value::Text("Achieng".into()),
+                Value::Integer(7),
+            ]
+        );
+        assert!(sql.contains("WHERE q.admission_id = a.id) = 2"), "{}", sql);
+        assert!(sql.contains("r.sample_type = 'cancer'"), "{}", sql);
+        assert!(sql.contains("NOT EXISTS"), "{}", sql);
+        assert!(sql.contains("a.diabetes_test IS NULL"));
+        assert!(sql.contains(r#"MATCH '"jane"*'"#));
+    }
+
+    #[test]
+    fn rejects_malformed_dates() {
+        let filter = AdmissionFilter {
+            date_to: Some("29/02/2024".into()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            filter.sql_from().unwrap_err(),
+            "Invalid end date '29/02/2024', expected YYYY-MM-DD"
+        );
+    }
+
+    #[test]
+    fn sorts_dates_newest_and_text_a_to_z_by_default() {
+        let order = |sort, direction| {
+            AdmissionFilter {
+                sort,
+                direction,
+                ..Default::default()
+            }
+            .order_by()
+        };
+
+        assert_eq!(
+            order(AdmissionSort::Timestamp, None),
+            "a.timestamp DESC, a.id DESC"
+        );
+        assert_eq!(
+            order(AdmissionSort::Patient, None),
+            "p.lastname COLLATE NOCASE ASC, p.firstname COLLATE NOCASE ASC, a.id DESC"
+        );
+        assert_eq!(
+            order(AdmissionSort::Doctor, Some(SortDirection::Desc)),
+            "a.doctor_in_charge COLLATE NOCASE DESC, a.id DESC"
+        );
+    }
+}
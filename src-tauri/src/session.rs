@@ -0,0 +1,368 @@
+// src/session.rs
+//
+// Lifecycle of the measurement on each port:
+//
+//   Idle → Resetting → Acquiring → Completed | Failed | Cancelled
+//
+// A port has exactly one `MeasurementSession`. Every start bumps its
+// generation and the reader started for it is the only one allowed to move it
+// forward; a reader that has been stopped, or replaced by a restart, finds its
+// generation stale and its updates are ignored. Each accepted change is
+// published as a `session-state-changed` event.
+//
+// Readers are plain threads. Stopping a run cancels its token and marks it
+// over straight away; the thread notices at its next read and is joined by
+// the following run on the port before that one opens it again.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+use chrono::Local;
+use log::{debug, info};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+
+use crate::errordefs::AppError;
+use crate::reader::CancelToken;
+use crate::testrun;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+    Idle,
+    /// Port opened (or being opened) and the board reset.
+    Resetting,
+    Acquiring,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl SessionState {
+    /// A reader owns the port in these states.
+    pub fn is_active(self) -> bool {
+        matches!(self, SessionState::Resetting | SessionState::Acquiring)
+    }
+
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            SessionState::Completed | SessionState::Failed | SessionState::Cancelled
+        )
+    }
+
+    fn can_become(self, next: SessionState) -> bool {
+        use SessionState::*;
+        matches!(
+            (self, next),
+            (Resetting, Acquiring)
+                | (Resetting | Acquiring, Failed | Cancelled)
+                | (Acquiring, Completed)
+        )
+    }
+}
+
+/// What the user asked for when they started reading from a port.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ReadRequest {
+    pub admission_no: Option<String>,
+    /// Rate the user asked for; `None` uses the remembered rate or the settings default.
+    pub baud_rate: Option<u32>,
+    /// Try the common rates until the board makes sense, and remember the winner.
+    pub auto_baud: bool,
+    /// Test run this reading is part of, if any.
+    pub test_run_id: Option<i64>,
+    /// Measures a control material rather than a patient.
+    pub qc: bool,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct MeasurementSession {
+    pub port: String,
+    pub state: SessionState,
+    /// Incremented on every start; identifies the reader that owns the run.
+    pub generation: u64,
+    pub request: ReadRequest,
+    /// Row in `acquisition_sessions` recording the current run.
+    pub acquisition_session_id: Option<i64>,
+    pub baud_rate: Option<u32>,
+    /// Why the run failed or was cancelled.
+    pub message: Option<String>,
+    pub updated_at: String,
+}
+
+impl MeasurementSession {
+    fn idle(port: &str) -> Self {
+        Self {
+            port: port.to_string(),
+            state: SessionState::Idle,
+            generation: 0,
+            request: ReadRequest::default(),
+            acquisition_session_id: None,
+            baud_rate: None,
+            message: None,
+            updated_at: now(),
+        }
+    }
+}
+
+struct Slot {
+    session: MeasurementSession,
+    cancel: CancelToken,
+    reader: Option<JoinHandle<()>>,
+}
+
+/// A run claimed by `start`, handed to the reader thread that carries it out.
+pub struct Run {
+    pub generation: u64,
+    pub cancel: CancelToken,
+    previous: Option<JoinHandle<()>>,
+}
+
+impl Run {
+    /// Waits until the reader of the previous run on the port has let go of it.
+    pub fn wait_for_previous(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            let _ = previous.join();
+        }
+    }
+}
+
+static SESSIONS: Lazy<Mutex<HashMap<String, Slot>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn now() -> String {
+    Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+}
+
+fn publish(app: &AppHandle, session: &MeasurementSession) {
+    debug!(
+        "Session on {} (gen {}) is now {:?}",
+        session.port, session.generation, session.state
+    );
+    let _ = app.emit("session-state-changed", session);
+}
+
+/// Reports a finished session to the test run it belongs to. Called once the
+/// sessions lock is released, so it is never held while waiting for the database.
+fn settle_test_run(app: &AppHandle, session: &MeasurementSession) {
+    if session.state.is_finished() {
+        if let Some(run_id) = session.request.test_run_id {
+            testrun::session_finished(app, run_id, session);
+        }
+    }
+}
+
+/// Claims a port for a new run and starts its reader with `spawn`, all under
+/// the sessions lock so no stop or second start can slip in between. Returns
+/// the run's generation; fails while another run owns the port.
+pub fn start(
+    app: &AppHandle,
+    port: &str,
+    request: ReadRequest,
+    spawn: impl FnOnce(Run) -> io::Result<JoinHandle<()>>,
+) -> Result<u64, AppError> {
+    let mut sessions = SESSIONS.lock()?;
+    let slot = sessions.entry(port.to_string()).or_insert_with(|| Slot {
+        session: MeasurementSession::idle(port),
+        cancel: CancelToken::default(),
+        reader: None,
+    });
+
+    if slot.session.state.is_active() {
+        return Err(AppError::Resource(format!("Already reading from {}", port)));
+    }
+
+    let session = &mut slot.session;
+    session.generation += 1;
+    session.state = SessionState::Resetting;
+    session.request = request;
+    session.acquisition_session_id = None;
+    session.baud_rate = None;
+    session.message = None;
+    session.updated_at = now();
+    slot.cancel = CancelToken::default();
+
+    let run = Run {
+        generation: session.generation,
+        cancel: slot.cancel.clone(),
+        previous: slot.reader.take(),
+    };
+    match spawn(run) {
+        Ok(reader) => slot.reader = Some(reader),
+        Err(e) => {
+            session.state = SessionState::Failed;
+            session.message = Some(format!("Could not start reader: {}", e));
+            publish(app, session);
+            let failed = session.clone();
+            drop(sessions);
+            settle_test_run(app, &failed);
+            return Err(e.into());
+        }
+    }
+
+    publish(app, session);
+    Ok(session.generation)
+}
+
+/// Records which acquisition row and baud rate the run uses.
+pub fn set_run_details(
+    port: &str,
+    generation: u64,
+    acquisition_session_id: Option<i64>,
+    baud_rate: u32,
+) {
+    if let Ok(mut sessions) = SESSIONS.lock() {
+        if let Some(slot) = sessions.get_mut(port) {
+            if slot.session.generation == generation {
+                slot.session.acquisition_session_id = acquisition_session_id;
+                slot.session.baud_rate = Some(baud_rate);
+            }
+        }
+    }
+}
+
+/// Moves the run identified by `generation` to `next`. Returns `false`, and
+/// changes nothing, when the run has been stopped or superseded or the move
+/// is not allowed; the caller must then stay silent about its outcome.
+pub fn transition(
+    app: &AppHandle,
+    port: &str,
+    generation: u64,
+    next: SessionState,
+    message: Option<&str>,
+) -> bool {
+    let Ok(mut sessions) = SESSIONS.lock() else {
+        return false;
+    };
+    let Some(slot) = sessions.get_mut(port) else {
+        return false;
+    };
+
+    let session = &mut slot.session;
+    if session.generation != generation || !session.state.can_become(next) {
+        debug!(
+            "Ignoring {:?} → {:?} on {} from gen {} (current gen {})",
+            session.state, next, port, generation, session.generation
+        );
+        return false;
+    }
+
+    session.state = next;
+    session.message = message.map(str::to_string);
+    session.updated_at = now();
+
+    publish(app, session);
+    let session = session.clone();
+    drop(sessions);
+    settle_test_run(app, &session);
+    true
+}
+
+/// Ends whatever run owns the port, from outside the reader: a stop request
+/// (`Cancelled`) or the board disappearing (`Failed`). The reader is asked to
+/// stop but not waited for. Returns the session as it was if a run was active.
+pub fn stop(
+    app: &AppHandle,
+    port: &str,
+    next: SessionState,
+    reason: &str,
+) -> Option<MeasurementSession> {
+    let mut sessions = SESSIONS.lock().ok()?;
+    let slot = sessions.get_mut(port)?;
+    if !slot.session.state.can_become(next) {
+        return None;
+    }
+
+    let previous = slot.session.clone();
+    slot.cancel.cancel();
+    slot.session.state = next;
+    slot.session.message = Some(reason.to_string());
+    slot.session.updated_at = now();
+    info!("Run on {} ended: {}", port, reason);
+
+    publish(app, &slot.session);
+    let session = slot.session.clone();
+    drop(sessions);
+    settle_test_run(app, &session);
+    Some(previous)
+}
+
+/// Current session of a port; `Idle` if nothing has run on it yet.
+pub fn snapshot(port: &str) -> MeasurementSession {
+    SESSIONS
+        .lock()
+        .ok()
+        .and_then(|sessions| sessions.get(port).map(|slot| slot.session.clone()))
+        .unwrap_or_else(|| MeasurementSession::idle(port))
+}
+
+/// Whether the run identified by `generation` still owns the port.
+pub fn owns(port: &str, generation: u64) -> bool {
+    let session = snapshot(port);
+    session.generation == generation && session.state.is_active()
+}
+
+pub fn is_active(port: &str) -> bool {
+    snapshot(port).state.is_active()
+}
+
+pub fn active_ports() -> Vec<String> {
+    SESSIONS
+        .lock()
+        .map(|sessions| {
+            sessions
+                .values()
+                .filter(|slot| slot.session.state.is_active())
+                .map(|slot| slot.session.port.clone())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/* ----------------------------------------
+   TAURI COMMANDS
+----------------------------------------- */
+
+#[tauri::command]
+pub fn get_session_state(port_name: String) -> MeasurementSession {
+    snapshot(&port_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SessionState::{self, *};
+
+    const ALL: [SessionState; 6] = [Idle, Resetting, Acquiring, Completed, Failed, Cancelled];
+
+    #[test]
+    fn runs_only_move_forward() {
+        let allowed: Vec<(SessionState, SessionState)> = ALL
+            .iter()
+            .flat_map(|&from| ALL.iter().map(move |&to| (from, to)))
+            .filter(|&(from, to)| from.can_become(to))
+            .collect();
+
+        assert_eq!(
+            allowed,
+            [
+                (Resetting, Acquiring),
+                (Resetting, Failed),
+                (Resetting, Cancelled),
+                (Acquiring, Completed),
+                (Acquiring, Failed),
+                (Acquiring, Cancelled),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_state_is_either_active_finished_or_idle() {
+        for state in ALL {
+            let kinds = [state.is_active(), state.is_finished(), state == Idle];
+            assert_eq!(kinds.iter().filter(|k| **k).count(), 1, "{:?}", state);
+        }
+    }
+}
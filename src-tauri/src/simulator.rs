@@ -0,0 +1,536 @@
+// src/simulator.rs
+//
+// Virtual Arduino boards for development, demos and integration tests.
+// A simulated device shows up in scans like a USB board and produces the same
+// text protocol as the firmware, either synthesized or replayed from a capture.
+//
+// Only debug builds can create simulated boards: the commands and the
+// `NEXUS_SIMULATOR` hook are compiled out of release builds, which therefore
+// never see one.
+
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+#[cfg(debug_assertions)]
+use std::{fs, path::Path};
+#[cfg(debug_assertions)]
+use tauri::{path::BaseDirectory, AppHandle, Manager, State};
+
+#[cfg(debug_assertions)]
+use crate::database::Database;
+use crate::errordefs::AppError;
+use crate::transport::{FileTransport, MemoryEvent, MemoryTransport, Transport};
+use crate::types::UsbDevice;
+
+/// Ports of simulated devices all start with this prefix.
+pub const SIMULATOR_PORT_PREFIX: &str = "SIM";
+
+/// Interval between synthetic lines at 1x speed.
+const LINE_INTERVAL: Duration = Duration::from_millis(500);
+
+static SIMULATED_DEVICES: Lazy<Mutex<Vec<SimulatedDevice>>> =
+    Lazy::new(|| Mutex::new(devices_from_env()));
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SimulatorSource {
+    /// Generated ON/OFF cycles ending with "cycles completed".
+    Synthetic {
+        cycles: u32,
+        readings_per_phase: u32,
+        on_voltage: f64,
+        off_voltage: f64,
+        /// Peak-to-peak noise added to every reading, in volts.
+        noise: f64,
+        #[serde(default)]
+        fault: Option<SimulatedFault>,
+    },
+    /// Lines from a capture file, either plain text or `<offset_ms>\t<line>`.
+    Replay { path: PathBuf },
+}
+
+impl Default for SimulatorSource {
+    fn default() -> Self {
+        SimulatorSource::Synthetic {
+            cycles: 3,
+            readings_per_phase: 3,
+            on_voltage: 1.1,
+            off_voltage: 0.45,
+            noise: 0.02,
+            fault: None,
+        }
+    }
+}
+
+/// Failure injected into a synthetic run after a number of lines.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SimulatedFault {
+    /// The cable is pulled halfway through a line.
+    Disconnect { after_lines: usize },
+    /// The board stops talking, which trips the no-data timeout.
+    Stall { after_lines: usize },
+    /// The OS reports an I/O error on the port.
+    IoError { after_lines: usize },
+}
+
+impl SimulatedFault {
+    fn after_lines(&self) -> usize {
+        match self {
+            SimulatedFault::Disconnect { after_lines }
+            | SimulatedFault::Stall { after_lines }
+            | SimulatedFault::IoError { after_lines } => *after_lines,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SimulatedDevice {
+    pub port: String,
+    pub source: SimulatorSource,
+    /// Playback speed multiplier; 2.0 runs twice as fast as real time.
+    pub speed: f64,
+}
+
+impl SimulatedDevice {
+    fn to_usb_device(&self) -> UsbDevice {
+        let board_name = match self.source {
+            SimulatorSource::Synthetic { .. } => "Simulated Arduino",
+            SimulatorSource::Replay { .. } => "Replay Arduino",
+        };
+        UsbDevice {
+            port: self.port.clone(),
+            vid: 0,
+            pid: 0,
+            serial_number: Some(format!("SIMULATOR-{}", self.port)),
+            product: Some(format!("{} ({})", board_name, self.port)),
+            status: "connected".to_string(),
+            custom_name: None,
+            device_unit: None,
+            board_name: board_name.to_string(),
+            firmware_name: None,
+            firmware_version: None,
+            role: None,
+            last_seen: None,
+            firmware_status: None,
+            firmware_message: None,
+            identity_source: Some("usb".to_string()),
+            device_id: None,
+        }
+    }
+}
+
+/// Reads `NEXUS_SIMULATOR` (`synthetic` or `replay:<path>`) and
+/// `NEXUS_SIMULATOR_SPEED` so demo machines get a board without any UI.
+/// Ignored in release builds.
+fn devices_from_env() -> Vec<SimulatedDevice> {
+    if !cfg!(debug_assertions) {
+        return Vec::new();
+    }
+    let Ok(spec) = std::env::var("NEXUS_SIMULATOR") else {
+        return Vec::new();
+    };
+
+    let speed = std::env::var("NEXUS_SIMULATOR_SPEED")
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|s| *s > 0.0)
+        .unwrap_or(1.0);
+
+    let source = match spec.split_once(':') {
+        Some(("replay", path)) => SimulatorSource::Replay { path: path.into() },
+        _ if spec == "synthetic" => SimulatorSource::default(),
+        _ => {
+            warn!("Ignoring unknown NEXUS_SIMULATOR value '{}'", spec);
+            return Vec::new();
+        }
+    };
+
+    info!(
+        "Simulator enabled from environment: {:?} @ {}x",
+        source, speed
+    );
+    vec![SimulatedDevice {
+        port: format!("{}0", SIMULATOR_PORT_PREFIX),
+        source,
+        speed,
+    }]
+}
+
+pub fn is_simulated_port(port: &str) -> bool {
+    port.starts_with(SIMULATOR_PORT_PREFIX)
+}
+
+/// Simulated boards to merge into a device scan.
+pub fn simulated_usb_devices() -> Vec<UsbDevice> {
+    SIMULATED_DEVICES
+        .lock()
+        .map(|devices| devices.iter().map(SimulatedDevice::to_usb_device).collect())
+        .unwrap_or_default()
+}
+
+/// Opens a transport for a simulated port, as if it were a serial port.
+pub fn open(port: &str) -> Result<Box<dyn Transport>, AppError> {
+    let device = SIMULATED_DEVICES
+        .lock()?
+        .iter()
+        .find(|d| d.port == port)
+        .cloned()
+        .ok_or_else(|| AppError::Resource(format!("No simulated device on {}", port)))?;
+
+    match &device.source {
+        SimulatorSource::Synthetic {
+            cycles,
+            readings_per_phase,
+            on_voltage,
+            off_voltage,
+            noise,
+            fault,
+        } => {
+            let lines = synthetic_lines(
+                *cycles,
+                *readings_per_phase,
+                *on_voltage,
+                *off_voltage,
+                *noise,
+            );
+            Ok(Box::new(
+                MemoryTransport::new(synthetic_events(lines, fault.as_ref(), device.speed))
+                    .with_responder(Box::new(firmware_reply)),
+            ))
+        }
+        // The capture holds the run, not the handshake, so the simulator answers that
+        SimulatorSource::Replay { path } => Ok(Box::new(
+            FileTransport::open(path, device.speed)?.with_responder(Box::new(firmware_reply)),
+        )),
+    }
+}
+
+/// Acknowledges every command the way the bench firmware does; answers the
+/// version query and the identify handshake so round trips can be exercised.
+fn firmware_reply(command: &str) -> Vec<String> {
+    match command.to_ascii_uppercase().as_str() {
+        "VERSION?" | "VERSION" => vec![format!("VERSION nexus-sim {}", env!("CARGO_PKG_VERSION"))],
+        "ID?" => vec![format!("ID nexus-sim {} cancer", env!("CARGO_PKG_VERSION"))],
+        _ => vec![format!("OK {}", command)],
+    }
+}
+
+fn synthetic_lines(
+    cycles: u32,
+    readings_per_phase: u32,
+    on_voltage: f64,
+    off_voltage: f64,
+    noise: f64,
+) -> Vec<String> {
+    let mut rng = XorShift::seeded();
+    let mut jitter = |v: f64| (v + (rng.next_f64() - 0.5) * noise).max(0.0);
+    let mut lines = vec!["Nexus simulator ready".to_string()];
+
+    for cycle in 1..=cycles {
+        lines.push(format!("Cycle {}", cycle));
+        for _ in 0..readings_per_phase {
+            lines.push(format!("Output Voltage (ON): {:.4} V", jitter(on_voltage)));
+        }
+        for _ in 0..readings_per_phase {
+            lines.push(format!(
+                "Output Voltage (OFF): {:.4} V",
+                jitter(off_voltage)
+            ));
+        }
+    }
+
+    lines.push(format!("{} cycles completed", cycles));
+    lines
+}
+
+/// Spaces the lines out in time and cuts the run short if a fault is set.
+fn synthetic_events(
+    lines: Vec<String>,
+    fault: Option<&SimulatedFault>,
+    speed: f64,
+) -> Vec<MemoryEvent> {
+    let interval = LINE_INTERVAL.div_f64(if speed > 0.0 { speed } else { 1.0 });
+    let cut_at = fault.map(SimulatedFault::after_lines).unwrap_or(usize::MAX);
+
+    let mut events = Vec::new();
+    for line in lines.iter().take(cut_at) {
+        events.push(MemoryEvent::Delay(interval));
+        events.push(MemoryEvent::Data(format!("{}\r\n", line).into_bytes()));
+    }
+
+    match fault {
+        Some(SimulatedFault::Disconnect { .. }) => {
+            // Half a line, then the cable is gone.
+            events.push(MemoryEvent::Delay(interval));
+            events.push(MemoryEvent::Data(b"Output Voltage (O".to_vec()));
+            events.push(MemoryEvent::Disconnect);
+        }
+        Some(SimulatedFault::IoError { .. }) => {
+            events.push(MemoryEvent::Delay(interval));
+            events.push(MemoryEvent::Error(io::ErrorKind::BrokenPipe));
+        }
+        // Running out of script leaves the board silent.
+        Some(SimulatedFault::Stall { .. }) | None => {}
+    }
+
+    events
+}
+
+/// Small dependency-free PRNG; the noise only has to look plausible.
+struct XorShift(u64);
+
+impl XorShift {
+    fn seeded() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0x9E37_79B9_7F4A_7C15);
+        XorShift(seed | 1)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/* ----------------------------------------
+   TAURI COMMANDS (debug builds only)
+----------------------------------------- */
+
+#[cfg(debug_assertions)]
+#[tauri::command]
+pub fn list_simulated_devices() -> Result<Vec<SimulatedDevice>, AppError> {
+    Ok(SIMULATED_DEVICES.lock()?.clone())
+}
+
+/// Registers a virtual board; it appears on the next scan.
+#[cfg(debug_assertions)]
+#[tauri::command]
+pub fn add_simulated_device(
+    port: Option<String>,
+    source: Option<SimulatorSource>,
+    speed: Option<f64>,
+) -> Result<UsbDevice, AppError> {
+    let mut devices = SIMULATED_DEVICES.lock()?;
+
+    let port = match port {
+        Some(p) if is_simulated_port(&p) => p,
+        Some(p) => {
+            return Err(AppError::Resource(format!(
+                "Simulated ports must start with '{}', got '{}'",
+                SIMULATOR_PORT_PREFIX, p
+            )))
+        }
+        None => (0..)
+            .map(|i| format!("{}{}", SIMULATOR_PORT_PREFIX, i))
+            .find(|p| !devices.iter().any(|d| &d.port == p))
+            .unwrap_or_default(),
+    };
+
+    if devices.iter().any(|d| d.port == port) {
+        return Err(AppError::Resource(format!("{} is already simulated", port)));
+    }
+
+    let source = source.unwrap_or_default();
+    if let SimulatorSource::Replay { path } = &source {
+        if !path.is_file() {
+            return Err(AppError::Io(format!(
+                "Capture file not found: {}",
+                path.display()
+            )));
+        }
+    }
+
+    let device = SimulatedDevice {
+        port,
+        source,
+        speed: speed.filter(|s| *s > 0.0).unwrap_or(1.0),
+    };
+    info!("Simulated device added on {}", device.port);
+    let usb = device.to_usb_device();
+    devices.push(device);
+    Ok(usb)
+}
+
+#[cfg(debug_assertions)]
+#[tauri::command]
+pub fn remove_simulated_device(port: String) -> Result<(), AppError> {
+    let mut devices = SIMULATED_DEVICES.lock()?;
+    let before = devices.len();
+    devices.retain(|d| d.port != port);
+    if devices.len() == before {
+        return Err(AppError::Resource(format!(
+            "No simulated device on {}",
+            port
+        )));
+    }
+    info!("Simulated device removed from {}", port);
+    Ok(())
+}
+
+/// Writes a stored acquisition session as a replayable capture file named
+/// `file_name` in the app data `captures` directory, and returns its path.
+#[cfg(debug_assertions)]
+#[tauri::command]
+pub fn export_session_capture(
+    app: AppHandle,
+    db: State<'_, Database>,
+    session_id: i64,
+    file_name: String,
+) -> Result<PathBuf, AppError> {
+    // A bare file name, so nothing is written outside the captures directory
+    if file_name.is_empty() || Path::new(&file_name).file_name() != Some(file_name.as_ref()) {
+        return Err(AppError::Resource(format!(
+            "'{}' is not a plain file name",
+            file_name
+        )));
+    }
+    let dir = app.path().resolve("captures", BaseDirectory::AppData)?;
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(&file_name);
+
+    let conn = db.0.lock()?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT
+                CAST((julianday(l.received_at) - julianday(s.started_at)) * 86400000 AS INTEGER),
+                l.raw
+             FROM acquisition_lines l
+             JOIN acquisition_sessions s ON s.id = l.session_id
+             WHERE l.session_id = ?1
+             ORDER BY l.line_no",
+        )
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+
+    let lines = stmt
+        .query_map([session_id], |row| {
+            Ok(format!(
+                "{}\t{}",
+                row.get::<_, Option<i64>>(0)?.unwrap_or(0).max(0),
+                row.get::<_, String>(1)?
+            ))
+        })
+        .map_err(|e| AppError::Internal(e.to_string()))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+
+    if lines.is_empty() {
+        return Err(AppError::Resource(format!(
+            "Session {} has no recorded lines",
+            session_id
+        )));
+    }
+
+    fs::write(&path, lines.join("\n"))?;
+    info!(
+        "Exported {} line(s) of session {} to {}",
+        lines.len(),
+        session_id,
+        path.display()
+    );
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::{MeasurementParser, ParsedLine, Phase};
+    use crate::reader::{run_read_loop, LineReader, ReadLoopConfig, ReadOutcome};
+
+    fn run(events: Vec<MemoryEvent>) -> (ReadOutcome, Vec<String>) {
+        let mut reader = LineReader::new(MemoryTransport::new(events));
+        let mut parser = MeasurementParser::new();
+        let config = ReadLoopConfig {
+            no_data_timeout: Duration::from_millis(500),
+            expected_cycles: 2,
+        };
+        let mut lines = Vec::new();
+        let outcome = run_read_loop(&mut reader, &mut parser, &config, None, None, |raw, _| {
+            lines.push(raw.to_string())
+        });
+        (outcome, lines)
+    }
+
+    #[test]
+    fn synthetic_lines_parse_as_the_requested_cycles() {
+        let mut parser = MeasurementParser::new();
+        let parsed: Vec<ParsedLine> = synthetic_lines(2, 3, 1.1, 0.45, 0.0)
+            .iter()
+            .map(|line| parser.parse_line(line))
+            .collect();
+
+        let readings: Vec<(Phase, u32)> = parsed
+            .iter()
+            .filter_map(|p| match p {
+                ParsedLine::Measurement(m) => Some((m.phase, m.cycle)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(readings.len(), 12);
+        assert_eq!(readings[2], (Phase::On, 1));
+        assert_eq!(readings[3], (Phase::Off, 1));
+        assert_eq!(readings[6], (Phase::On, 2));
+        assert!(parsed.contains(&ParsedLine::CycleStart(2)));
+        assert_eq!(parsed.last(), Some(&ParsedLine::Completed));
+    }
+
+    #[test]
+    fn synthetic_noise_stays_within_its_bounds() {
+        for line in synthetic_lines(3, 3, 1.1, 0.45, 0.02) {
+            let Some(value) = line
+                .strip_prefix("Output Voltage (ON): ")
+                .and_then(|v| v.strip_suffix(" V"))
+            else {
+                continue;
+            };
+            let value: f64 = value.parse().unwrap();
+            assert!((1.09..=1.11).contains(&value), "{}", line);
+        }
+    }
+
+    #[test]
+    fn a_synthetic_run_completes() {
+        let lines = synthetic_lines(2, 2, 1.1, 0.45, 0.01);
+        let (outcome, seen) = run(synthetic_events(lines.clone(), None, 100.0));
+
+        assert_eq!(outcome, ReadOutcome::Completed { cycles: 2 });
+        assert_eq!(seen, lines);
+    }
+
+    #[test]
+    fn injected_faults_end_the_run_as_on_hardware() {
+        let lines = synthetic_lines(2, 2, 1.1, 0.45, 0.01);
+        let fault = |fault| run(synthetic_events(lines.clone(), Some(&fault), 100.0));
+
+        let (outcome, seen) = fault(SimulatedFault::Disconnect { after_lines: 3 });
+        assert_eq!(outcome, ReadOutcome::Disconnected);
+        assert_eq!(seen.len(), 4);
+        assert_eq!(seen[3], "Output Voltage (O");
+
+        let (outcome, seen) = fault(SimulatedFault::IoError { after_lines: 3 });
+        assert!(matches!(outcome, ReadOutcome::Failed(_)));
+        assert_eq!(seen.len(), 3);
+
+        let (outcome, seen) = fault(SimulatedFault::Stall { after_lines: 3 });
+        assert_eq!(outcome, ReadOutcome::NoData);
+        assert_eq!(seen.len(), 3);
+    }
+
+    #[test]
+    fn answers_the_identify_handshake() {
+        let reply = firmware_reply("id?");
+        assert_eq!(reply.len(), 1);
+        assert!(reply[0].starts_with("ID nexus-sim "));
+        assert!(reply[0].ends_with(" cancer"));
+
+        assert_eq!(firmware_reply("START"), ["OK START"]);
+    }
+}
@@ -0,0 +1,268 @@
+// src/testrun.rs
+//
+// Test runs: several boards (typically a cancer-screening and a glucose rig)
+// measured together for one admission. Each board is read by its own
+// measurement session; the run follows them and only ends once every one of
+// them has, as `completed` if all did, `partially_failed` if some did.
+
+use chrono::Local;
+use log::{error, info};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::acquisition::TIMESTAMP_FORMAT;
+use crate::database::Database;
+use crate::session::{MeasurementSession, SessionState};
+
+#[derive(Serialize, Debug)]
+pub struct TestRunDevice {
+    pub port: String,
+    pub role: Option<String>,
+    /// `running`, `completed`, `failed` or `cancelled`.
+    pub status: String,
+    pub status_message: Option<String>,
+    pub acquisition_session_id: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TestRun {
+    pub id: i64,
+    pub admission_no: Option<String>,
+    /// `running`, `completed`, `partially_failed`, `failed`, `cancelled` or `interrupted`.
+    pub status: String,
+    pub started_at: String,
+    pub ended_at: Option<String>,
+    pub devices: Vec<TestRunDevice>,
+}
+
+fn now() -> String {
+    Local::now().format(TIMESTAMP_FORMAT).to_string()
+}
+
+/// Creates a `running` run with one `running` entry per port, given as
+/// `(port, role)`, and returns its id.
+pub fn create(
+    conn: &Connection,
+    admission_no: Option<&str>,
+    devices: &[(String, Option<String>)],
+) -> rusqlite::Result<i64> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "INSERT INTO test_runs (admission_no, status, started_at) VALUES (?1, 'running', ?2)",
+        params![admission_no, now()],
+    )?;
+    let run_id = tx.last_insert_rowid();
+
+    for (port, role) in devices {
+        tx.execute(
+            "INSERT INTO test_run_devices (test_run_id, port, role, status)
+             VALUES (?1, ?2, ?3, 'running')",
+            params![run_id, port, role],
+        )?;
+    }
+
+    tx.commit()?;
+    Ok(run_id)
+}
+
+pub fn load(conn: &Connection, run_id: i64) -> rusqlite::Result<TestRun> {
+    let mut run = conn.query_row(
+        "SELECT id, admission_no, status, started_at, ended_at FROM test_runs WHERE id = ?1",
+        params![run_id],
+        |row| {
+            Ok(TestRun {
+                id: row.get(0)?,
+                admission_no: row.get(1)?,
+                status: row.get(2)?,
+                started_at: row.get(3)?,
+                ended_at: row.get(4)?,
+                devices: Vec::new(),
+            })
+        },
+    )?;
+
+    let mut stmt = conn.prepare(
+        "SELECT port, role, status, status_message, acquisition_session_id
+         FROM test_run_devices WHERE test_run_id = ?1 ORDER BY id",
+    )?;
+    run.devices = stmt
+        .query_map(params![run_id], |row| {
+            Ok(TestRunDevice {
+                port: row.get(0)?,
+                role: row.get(1)?,
+                status: row.get(2)?,
+                status_message: row.get(3)?,
+                acquisition_session_id: row.get(4)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(run)
+}
+
+/// Status of a run whose devices have all stopped, from their statuses.
+fn overall_status(statuses: &[String]) -> &'static str {
+    let completed = statuses.iter().filter(|s| *s == "completed").count();
+    if completed == statuses.len() {
+        "completed"
+    } else if completed > 0 {
+        "partially_failed"
+    } else if statuses.iter().all(|s| s == "cancelled") {
+        "cancelled"
+    } else {
+        "failed"
+    }
+}
+
+/// Records how one device of the run ended and closes the run once none is
+/// left running. Returns whether this closed the run.
+fn settle_device(
+    conn: &Connection,
+    run_id: i64,
+    port: &str,
+    status: &str,
+    message: Option<&str>,
+    acquisition_session_id: Option<i64>,
+) -> rusqlite::Result<bool> {
+    let updated = conn.execute(
+        "UPDATE test_run_devices
+         SET status = ?3, status_message = ?4,
+             acquisition_session_id = COALESCE(?5, acquisition_session_id)
+         WHERE test_run_id = ?1 AND port = ?2 AND status = 'running'",
+        params![run_id, port, status, message, acquisition_session_id],
+    )?;
+    if updated == 0 {
+        return Ok(false);
+    }
+
+    if let Some(id) = acquisition_session_id {
+        conn.execute(
+            "UPDATE acquisition_sessions SET test_run_id = ?1 WHERE id = ?2",
+            params![run_id, id],
+        )?;
+    }
+
+    let mut stmt = conn.prepare("SELECT status FROM test_run_devices WHERE test_run_id = ?1")?;
+    let statuses = stmt
+        .query_map(params![run_id], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    if statuses.iter().any(|s| s == "running") {
+        return Ok(false);
+    }
+
+    conn.execute(
+        "UPDATE test_runs SET status = ?2, ended_at = ?3 WHERE id = ?1 AND status = 'running'",
+        params![run_id, overall_status(&statuses), now()],
+    )?;
+    Ok(true)
+}
+
+/// Follows a device's session into the run once the session has ended.
+/// Emits `test-run-changed` for every device, then `test-run-complete` or
+/// `test-run-failed` when the last one is done.
+pub fn session_finished(app: &AppHandle, run_id: i64, session: &MeasurementSession) {
+    let status = match session.state {
+        SessionState::Completed => "completed",
+        SessionState::Cancelled => "cancelled",
+        _ => "failed",
+    };
+    settle_and_emit(
+        app,
+        run_id,
+        &session.port,
+        status,
+        session.message.as_deref(),
+        session.acquisition_session_id,
+    );
+}
+
+/// Marks a device of the run as failed before its reader could start.
+pub fn device_not_started(app: &AppHandle, run_id: i64, port: &str, message: &str) {
+    settle_and_emit(app, run_id, port, "failed", Some(message), None);
+}
+
+fn settle_and_emit(
+    app: &AppHandle,
+    run_id: i64,
+    port: &str,
+    status: &str,
+    message: Option<&str>,
+    acquisition_session_id: Option<i64>,
+) {
+    let Some(db) = app.try_state::<Database>() else {
+        return;
+    };
+    let conn = match db.0.lock() {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("Database lock poisoned: {}", e);
+            return;
+        }
+    };
+    let result = settle_device(&conn, run_id, port, status, message, acquisition_session_id)
+        .and_then(|closed| Ok((load(&conn, run_id)?, closed)));
+    drop(conn);
+
+    let (run, closed) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            error!("Failed to update test run {}: {}", run_id, e);
+            return;
+        }
+    };
+
+    let _ = app.emit("test-run-changed", &run);
+    if closed {
+        info!("Test run {} finished: {}", run.id, run.status);
+        let event = if run.status == "completed" {
+            "test-run-complete"
+        } else {
+            "test-run-failed"
+        };
+        let _ = app.emit(event, &run);
+    }
+}
+
+/// Runs left `running` by a crash or forced exit can never finish.
+pub fn mark_interrupted_runs(conn: &Connection) -> rusqlite::Result<usize> {
+    conn.execute(
+        "UPDATE test_run_devices SET status = 'failed', status_message = 'Application exited during acquisition'
+         WHERE status = 'running'",
+        [],
+    )?;
+    conn.execute(
+        "UPDATE test_runs SET status = 'interrupted', ended_at = ?1 WHERE status = 'running'",
+        params![now()],
+    )
+}
+
+/* ----------------------------------------
+   TAURI COMMANDS
+----------------------------------------- */
+
+#[tauri::command]
+pub fn get_test_run(db: State<'_, Database>, run_id: i64) -> Result<TestRun, String> {
+    let conn = db.0.lock().map_err(|e| e.to_string())?;
+    load(&conn, run_id).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(statuses: &[&str]) -> &'static str {
+        let statuses: Vec<String> = statuses.iter().map(|s| s.to_string()).collect();
+        overall_status(&statuses)
+    }
+
+    #[test]
+    fn summarizes_how_the_devices_ended() {
+        assert_eq!(status(&["completed", "completed"]), "completed");
+        assert_eq!(status(&["completed", "failed"]), "partially_failed");
+        assert_eq!(status(&["cancelled", "completed"]), "partially_failed");
+        assert_eq!(status(&["cancelled", "cancelled"]), "cancelled");
+        assert_eq!(status(&["cancelled", "failed"]), "failed");
+        assert_eq!(status(&["no_data"]), "failed");
+    }
+}
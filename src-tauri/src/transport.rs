@@ -0,0 +1,348 @@
+// src/transport.rs
+//
+// Byte-level access to a board. The read loop only talks to `Transport`, so
+// it runs the same against real hardware, the simulator, a capture file or a
+// scripted in-memory stream.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use serialport::SerialPort;
+
+use crate::errordefs::AppError;
+
+/// Read timeout used by every transport that can block.
+pub const READ_TIMEOUT: Duration = Duration::from_millis(1000);
+
+pub trait Transport: Send {
+    /// Reads whatever bytes are available.
+    ///
+    /// `Ok(0)` means the connection is gone; an `ErrorKind::TimedOut` error
+    /// means nothing arrived within the transport's read timeout.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()>;
+
+    /// Resets the board by pulsing DTR. Transports without control lines
+    /// have nothing to reset.
+    fn reset(&mut self, _pulse: Duration) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (**self).read(buf)
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        (**self).write_all(data)
+    }
+
+    fn reset(&mut self, pulse: Duration) -> io::Result<()> {
+        (**self).reset(pulse)
+    }
+}
+
+/* ----------------------------------------
+   SERIAL
+----------------------------------------- */
+
+pub struct SerialTransport {
+    port: Box<dyn SerialPort>,
+}
+
+impl SerialTransport {
+    pub fn open(port_name: &str, baud_rate: u32) -> Result<Self, AppError> {
+        let port = serialport::new(port_name, baud_rate)
+            .timeout(READ_TIMEOUT)
+            .open()?;
+        Ok(Self { port })
+    }
+}
+
+impl Transport for SerialTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.port.write_all(data)?;
+        self.port.flush()
+    }
+
+    fn reset(&mut self, pulse: Duration) -> io::Result<()> {
+        self.port.write_data_terminal_ready(true)?;
+        std::thread::sleep(pulse);
+        self.port.write_data_terminal_ready(false)?;
+        Ok(())
+    }
+}
+
+/* ----------------------------------------
+   IN-MEMORY
+----------------------------------------- */
+
+/// One scripted step of a `MemoryTransport`.
+#[derive(Clone, Debug)]
+pub enum MemoryEvent {
+    /// Bytes returned by the next read; may hold partial or several lines.
+    Data(Vec<u8>),
+    /// Silence on the line before the next event.
+    Delay(Duration),
+    /// The next read fails with this error kind.
+    Error(io::ErrorKind),
+    /// The next read returns `Ok(0)`, i.e. the cable was pulled.
+    Disconnect,
+}
+
+/// Turns one written command line into the lines the fake board answers with.
+pub type Responder = Box<dyn FnMut(&str) -> Vec<String> + Send>;
+
+/// Scripted transport for running the reader without hardware.
+///
+/// Delays are honoured in real time but never block longer than
+/// `READ_TIMEOUT`, so the reader sees the same timeouts as on a serial port.
+/// Once the script is exhausted the transport behaves like an idle board.
+pub struct MemoryTransport {
+    script: VecDeque<MemoryEvent>,
+    pending: VecDeque<u8>,
+    responder: Option<Responder>,
+}
+
+impl MemoryTransport {
+    pub fn new(script: impl IntoIterator<Item = MemoryEvent>) -> Self {
+        Self {
+            script: script.into_iter().collect(),
+            pending: VecDeque::new(),
+            responder: None,
+        }
+    }
+
+    /// Answers written commands; replies are read before the rest of the script.
+    pub fn with_responder(mut self, responder: Responder) -> Self {
+        self.responder = Some(responder);
+        self
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() {
+            match self.script.pop_front() {
+                Some(MemoryEvent::Data(bytes)) => self.pending.extend(bytes),
+                Some(MemoryEvent::Delay(delay)) if delay > READ_TIMEOUT => {
+                    std::thread::sleep(READ_TIMEOUT);
+                    self.script
+                        .push_front(MemoryEvent::Delay(delay - READ_TIMEOUT));
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+                Some(MemoryEvent::Delay(delay)) => std::thread::sleep(delay),
+                Some(MemoryEvent::Error(kind)) => return Err(kind.into()),
+                Some(MemoryEvent::Disconnect) => return Ok(0),
+                None => {
+                    std::thread::sleep(READ_TIMEOUT);
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+            }
+        }
+
+        Ok(drain_into(&mut self.pending, buf))
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        let Some(responder) = self.responder.as_mut() else {
+            return Ok(());
+        };
+
+        let text = String::from_utf8_lossy(data);
+        let replies: Vec<String> = text
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .flat_map(&mut *responder)
+            .collect();
+        for reply in replies.into_iter().rev() {
+            self.script
+                .push_front(MemoryEvent::Data(format!("{}\r\n", reply).into_bytes()));
+        }
+        Ok(())
+    }
+}
+
+/* ----------------------------------------
+   FILE
+----------------------------------------- */
+
+/// Interval between replayed lines when a capture carries no timing.
+const DEFAULT_LINE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Replays a capture file line by line.
+///
+/// Lines may be plain text or `<offset_ms>\t<line>` as written by
+/// `export_session_capture`, in which case the original timing is kept,
+/// scaled by `speed`.
+pub struct FileTransport {
+    inner: MemoryTransport,
+}
+
+impl FileTransport {
+    pub fn open(path: &Path, speed: f64) -> Result<Self, AppError> {
+        let capture = fs::read_to_string(path)?;
+        Ok(Self {
+            inner: MemoryTransport::new(capture_events(&capture, speed)),
+        })
+    }
+
+    /// Answers written commands, which a capture cannot do by itself; replies
+    /// are read before the rest of the capture.
+    pub fn with_responder(self, responder: Responder) -> Self {
+        Self {
+            inner: self.inner.with_responder(responder),
+        }
+    }
+}
+
+impl Transport for FileTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+
+    /// Ignored unless a responder was set.
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        self.inner.write_all(data)
+    }
+}
+
+fn capture_events(capture: &str, speed: f64) -> Vec<MemoryEvent> {
+    let speed = if speed > 0.0 { speed } else { 1.0 };
+    let mut previous_offset = 0;
+    let mut events = Vec::new();
+
+    for line in capture.lines().filter(|l| !l.trim().is_empty()) {
+        let timed = line
+            .split_once('\t')
+            .and_then(|(offset, text)| Some((offset.trim().parse::<u64>().ok()?, text)));
+        let (delay, text) = match timed {
+            Some((offset, text)) => {
+                let delay = Duration::from_millis(offset.saturating_sub(previous_offset));
+                previous_offset = offset;
+                (delay, text)
+            }
+            None => (DEFAULT_LINE_INTERVAL, line),
+        };
+        events.push(MemoryEvent::Delay(delay.div_f64(speed)));
+        events.push(MemoryEvent::Data(format!("{}\r\n", text).into_bytes()));
+    }
+
+    events
+}
+
+fn drain_into(pending: &mut VecDeque<u8>, buf: &mut [u8]) -> usize {
+    let n = buf.len().min(pending.len());
+    for (slot, byte) in buf.iter_mut().zip(pending.drain(..n)) {
+        *slot = byte;
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(transport: &mut impl Transport) -> String {
+        let mut text = Vec::new();
+        let mut buf = [0u8; 4];
+        loop {
+            match transport.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => text.extend_from_slice(&buf[..n]),
+                Err(e) => panic!("{}", e),
+            }
+        }
+        String::from_utf8(text).unwrap()
+    }
+
+    #[test]
+    fn memory_transport_hands_out_data_in_buffer_sized_pieces() {
+        let mut transport = MemoryTransport::new([
+            MemoryEvent::Data(b"Cycle 1\r\n".to_vec()),
+            MemoryEvent::Delay(Duration::from_millis(1)),
+            MemoryEvent::Data(b"done\r\n".to_vec()),
+            MemoryEvent::Disconnect,
+        ]);
+
+        assert_eq!(read_all(&mut transport), "Cycle 1\r\ndone\r\n");
+    }
+
+    #[test]
+    fn memory_transport_reports_scripted_errors() {
+        let mut transport = MemoryTransport::new([MemoryEvent::Error(io::ErrorKind::BrokenPipe)]);
+        let mut buf = [0u8; 8];
+
+        let err = transport.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn memory_transport_answers_commands_before_the_script() {
+        let mut transport = MemoryTransport::new([
+            MemoryEvent::Data(b"Cycle 1\r\n".to_vec()),
+            MemoryEvent::Disconnect,
+        ])
+        .with_responder(Box::new(|command| vec![format!("OK {}", command)]));
+
+        transport.write_all(b"START\nSTOP\n").unwrap();
+
+        assert_eq!(
+            read_all(&mut transport),
+            "OK START\r\nOK STOP\r\nCycle 1\r\n"
+        );
+    }
+
+    #[test]
+    fn replays_capture_timing_scaled_by_speed() {
+        let events = capture_events("0\tCycle 1\n\n250\tOutput Voltage (ON): 1.1 V\n", 2.0);
+
+        assert_eq!(events.len(), 4);
+        assert!(matches!(events[0], MemoryEvent::Delay(d) if d.is_zero()));
+        assert!(matches!(&events[1], MemoryEvent::Data(b) if b == b"Cycle 1\r\n"));
+        assert!(matches!(events[2], MemoryEvent::Delay(d) if d == Duration::from_millis(125)));
+        assert!(
+            matches!(&events[3], MemoryEvent::Data(b) if b == b"Output Voltage (ON): 1.1 V\r\n")
+        );
+    }
+
+    #[test]
+    fn spaces_untimed_capture_lines_evenly() {
+        let events = capture_events("Cycle 1\nOutput Voltage (OFF): 0.4 V", 0.0);
+
+        assert_eq!(events.len(), 4);
+        assert!(matches!(events[0], MemoryEvent::Delay(d) if d == DEFAULT_LINE_INTERVAL));
+        assert!(matches!(events[2], MemoryEvent::Delay(d) if d == DEFAULT_LINE_INTERVAL));
+        assert!(
+            matches!(&events[3], MemoryEvent::Data(b) if b == b"Output Voltage (OFF): 0.4 V\r\n")
+        );
+    }
+
+    #[test]
+    fn a_replay_answers_commands_before_the_capture() {
+        let path = std::env::temp_dir().join(format!("nexus-replay-{}.txt", std::process::id()));
+        fs::write(&path, "0\tCycle 1\n").unwrap();
+        let mut transport = FileTransport::open(&path, 1000.0)
+            .unwrap()
+            .with_responder(Box::new(|command| {
+                vec![format!("ID sim 1.0 for {}", command)]
+            }));
+        fs::remove_file(&path).unwrap();
+
+        transport.write_all(b"ID?\n").unwrap();
+        let mut buf = [0u8; 64];
+        let n = transport.read(&mut buf).unwrap();
+
+        assert_eq!(&buf[..n], b"ID sim 1.0 for ID?\r\n");
+    }
+}
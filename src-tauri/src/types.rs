@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct UsbDevice {
     pub port: String, // COM3, /dev/ttyUSB0, …
     pub vid: u16,
@@ -11,4 +11,28 @@ pub struct UsbDevice {
     pub custom_name: Option<String>,
     pub device_unit: Option<String>,
     pub board_name: String,
+    #[serde(default)]
+    pub firmware_name: Option<String>,
+    #[serde(default)]
+    pub firmware_version: Option<String>,
+    /// "cancer" or "glucose"; decides which tests the board is used for.
+    #[serde(default)]
+    pub role: Option<String>,
+    /// When the watcher last saw the board; set for devices loaded from the database.
+    #[serde(default)]
+    pub last_seen: Option<String>,
+    /// `current`, `outdated`, `unsupported`, `blocked` or `unknown` against the
+    /// firmware compatibility list; `None` until the board identified itself.
+    #[serde(default)]
+    pub firmware_status: Option<String>,
+    /// Why the firmware is not `current`, for the UI.
+    #[serde(default)]
+    pub firmware_message: Option<String>,
+    /// Where `serial_number` comes from: `usb`, or for bridges without a serial
+    /// `firmware` (its `uid`), `location` (USB port path) or `port` (port name).
+    #[serde(default)]
+    pub identity_source: Option<String>,
+    /// Row id in the devices table, for devices loaded from the database.
+    #[serde(default)]
+    pub device_id: Option<i64>,
 }
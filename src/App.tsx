@@ -50,6 +50,7 @@ const cleanDeviceForRedux = (d: any): ArduinoDevice => ({
     serial_number: getNormalizedValue(d.serial_number),
     custom_name: getNormalizedValue(d.custom_name),
     board_name: getNormalizedValue(d.board_name),
+    role: d.role === 'cancer' || d.role === 'glucose' ? d.role : undefined,
     status: getNormalizedValue(d.status) === 'connected' ? 'connected' : 'disconnected', 
 });
 
@@ -32,6 +32,7 @@ const cleanDeviceForRedux = (d: any): ArduinoDevice => ({
     serial_number: getNormalizedValue(d.serial_number),
     custom_name: getNormalizedValue(d.custom_name),
     board_name: getNormalizedValue(d.board_name),
+    role: d.role === 'cancer' || d.role === 'glucose' ? d.role : undefined,
     status: getNormalizedValue(d.status) === 'connected' ? 'connected' : 'disconnected', 
 });
 
@@ -106,7 +107,8 @@ export function useArduinoWatcher() {
                 const isIdentityStable = 
                     knownDevice.status === 'connected' &&
                     getNormalizedString(knownDevice.product) === getNormalizedString(liveDevice.product) &&
-                    getNormalizedString(knownDevice.board_name) === getNormalizedString(liveDevice.board_name);
+                    getNormalizedString(knownDevice.board_name) === getNormalizedString(liveDevice.board_name) &&
+                    knownDevice.role === liveDevice.role;
                 
                 const isPortStable = knownDevice.port === newLivePort;
                 
@@ -125,6 +127,7 @@ export function useArduinoWatcher() {
                     pid: liveDevice.pid,
                     serial_number: liveDevice.serial_number,
                     custom_name: knownDevice.custom_name, // <-- PRESERVE CUSTOM NAME
+                    role: liveDevice.role ?? knownDevice.role,
                     port: newLivePort, 
                     product: getNormalizedValue(liveDevice.product), 
                     board_name: getNormalizedValue(liveDevice.board_name),
@@ -163,6 +166,7 @@ export function useArduinoWatcher() {
                     pid: liveDevice.pid,
                     serial_number: liveDevice.serial_number,
                     custom_name: undefined, // New device has no custom name yet
+                    role: liveDevice.role,
                     port: newLivePort,
                     product: getNormalizedValue(liveDevice.product), 
                     board_name: getNormalizedValue(liveDevice.board_name),
@@ -135,14 +135,14 @@ export default function Analytics() {
             const [tableResults, count, globalStatsData, chartResults] = await Promise.all([
                 // Data for the TABLE (Paginated)
                 invoke<AdmissionRecord[]>("search_admissions_by_patient", { 
-                    query: searchQuery, limit: currentRows, offset: currentFirst 
+                    filter: { query: searchQuery }, limit: currentRows, offset: currentFirst 
                 }),
                 // Total count for PAGINATOR
-                invoke<number>("get_admissions_count", { query: searchQuery }),
+                invoke<number>("get_admissions_count", { filter: { query: searchQuery } }),
                 // Data for STAT CARDS (Global)
-                invoke<any>("get_global_admission_stats", { query: searchQuery }),
+                invoke<any>("get_global_admission_stats", { filter: { query: searchQuery } }),
                 // Data for the CHART (Always latest 5)
-                invoke<AdmissionRecord[]>("get_latest_5_admissions", { query: searchQuery })
+                invoke<AdmissionRecord[]>("get_latest_5_admissions", { filter: { query: searchQuery } })
             ]);
 
             setAdmissions(tableResults);
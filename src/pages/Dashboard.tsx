@@ -27,6 +27,14 @@ interface PatientForm {
     sample_type: "normal" | "cancer" | "";
 }
 
+// Payload of `session-state-changed`, published on every change of a port's run
+interface MeasurementSession {
+    port: string;
+    state: 'idle' | 'resetting' | 'acquiring' | 'completed' | 'failed' | 'cancelled';
+    acquisition_session_id: number | null;
+    message: string | null;
+}
+
 const getNormalizedString = (s?: string | null): string => {
     if (!s) return '';
     return String(s).trim().replace(/\s+/g, ' ');
@@ -45,6 +53,7 @@ const cleanDeviceForRedux = (d: any): ArduinoDevice => ({
     serial_number: getNormalizedValue(d.serial_number),
     custom_name: getNormalizedValue(d.custom_name),
     board_name: getNormalizedValue(d.board_name),
+    role: d.role === 'cancer' || d.role === 'glucose' ? d.role : undefined,
     status: getNormalizedValue(d.status) === 'connected' ? 'connected' : 'disconnected', 
 });
 
@@ -58,6 +67,8 @@ export default function Dashboard() {
 
   const [normalCellReadings, setNormalCellReadings] = useState<number[]>([]);
   const [cancerCellReadings, setCancerCellReadings] = useState<number[]>([]);
+  // The last completed run; its recorded readings are what gets saved
+  const [cancerSessionId, setCancerSessionId] = useState<number | null>(null);
 
   const consoleRef = useRef<HTMLDivElement>(null);
   const [showModal, setShowModal] = useState(false);
@@ -188,58 +199,44 @@ export default function Dashboard() {
   /* 2. LISTEN TO TAURI STATE EVENTS                                   */
   /* ------------------------------------------------------------------ */
   useEffect(() => {
-    let unlistenStart: UnlistenFn | undefined;
-    let unlistenStop: UnlistenFn | undefined;
-    let unlistenDisconnect: UnlistenFn | undefined;
-    let unlistenTimeout: UnlistenFn | undefined;
+    let unlistenSession: UnlistenFn | undefined;
 
     (async () => {
+      unlistenSession = await listen<MeasurementSession>("session-state-changed", (e) => {
+        const { port, state, message, acquisition_session_id } = e.payload;
 
-      const unlistenTimeout = listen<{ port: string; message: string }>("arduino-timeout", (e) => {
-        toast.error(`Timeout on ${e.payload.port}\n${e.payload.message}`, {
-          duration: 6000,
-        });
-      });
-
-      unlistenStart = await listen<{ port: string }>("arduino-reading-started", (e) => {
-        setReadingPorts((prev) => new Set(prev).add(e.payload.port));
-      });
-
-      unlistenStop = await listen<{ port: string }>("arduino-reading-stopped", (e) => {
         setReadingPorts((prev) => {
           const next = new Set(prev);
-          next.delete(e.payload.port);
+          if (state === "resetting" || state === "acquiring") {
+            next.add(port);
+          } else {
+            next.delete(port);
+          }
           return next;
         });
-      });
 
-      unlistenDisconnect = await listen<{ port: string; error: string }>(
-        "arduino-disconnected",
-        (e) => {
-          setReadingPorts((prev) => {
-            const next = new Set(prev);
-            next.delete(e.payload.port);
-            return next;
+        if (state === "completed") {
+          setCancerSessionId(acquisition_session_id);
+          toast.success(`Cycle complete on ${port}`);
+        } else if (state === "failed") {
+          toast.error(`Measurement failed on ${port}\n${message ?? ""}`, {
+            duration: 6000,
           });
         }
-      );
+      });
     })();
 
     return () => {
-      unlistenStart?.();
-      unlistenStop?.();
-      unlistenDisconnect?.();
-      unlistenTimeout?.();
+      unlistenSession?.();
     };
   }, []);
 
   /* ------------------------------------------------------------------ */
-  /* 3. LISTEN TO SERIAL DATA + CYCLE COMPLETE                         */
+  /* 3. LISTEN TO SERIAL DATA                                          */
   /* ------------------------------------------------------------------ */
   useEffect(() => {
     console.log("running once")
     let unlistenData: UnlistenFn | undefined;
-    let unlistenCycle: UnlistenFn | undefined;
 
     (async () => {
       // --- Serial data (console only) ---
@@ -259,20 +256,12 @@ export default function Dashboard() {
           setConsoleLines((prev) => [...prev.slice(-100), stamped]);
         }
 
-        // DO NOT show toast here — handled by arduino-cycle-complete
-      });
-
-      // --- Cycle complete (toast + UI) ---
-      unlistenCycle = await listen<{ port: string }>("arduino-cycle-complete", (e) => {
-        toast.success(`Cycle complete on ${e.payload.port}`);
-
-       
+        // DO NOT show toast here — handled by session-state-changed
       });
     })();
 
     return () => {
       unlistenData?.();
-      unlistenCycle?.();
     };
   }, []);
 
@@ -302,16 +291,7 @@ export default function Dashboard() {
     
     try {
 
-      let cancer_test = {
-        voltage_off : extractOffVoltages(consoleLines)
-      }
-
-      let normal_test = {
-        voltage_off: normalCellReadings
-      }
-
-      
-      let data = { ...formData, cancer_test }
+      let data = { ...formData, cancer_session_id: cancerSessionId }
       console.log(data)
       // Store via Tauri backend or API
       delete (data as any).sample_type;
@@ -321,6 +301,7 @@ export default function Dashboard() {
 
       toast.success("Saved!");
       setConsoleLines([])
+      setCancerSessionId(null)
       setSugarContent(null)
       setFormData({
         admission_no: "",
@@ -345,17 +326,6 @@ export default function Dashboard() {
     setIsSaving(false);
   };
 
-  const extractOffVoltages = (lines: string[]) => {
-    const regex = /Output Voltage \(OFF\):\s*([0-9]*\.[0-9]+)/;
-
-    return lines
-      .map(line => {
-        const match = line.match(regex);
-        return match ? Number.parseFloat(match[1]) : null;
-      })
-      .filter(v => v !== null);
-  }
-
   /* ------------------------------------------------------------------ */
   /* 5. RENDER                                                         */
   /* ------------------------------------------------------------------ */
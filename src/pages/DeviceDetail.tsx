@@ -10,8 +10,8 @@ import { Card } from 'primereact/card';
 import { Dropdown } from "primereact/dropdown";
 import "./DeviceDetail.css";
 
-// Assuming you have this action in your arduinoSlice.ts
-import { setDeviceCustomName } from '../store/arduinoSlice'; 
+import { setDeviceRole } from '../store/arduinoSlice';
+import type { DeviceRole } from '../store/arduinoSlice';
 
 
 // Define the type for the URL parameters
@@ -21,8 +21,8 @@ interface DeviceDetailParams {
 }
 
 const deviceOptions = [
-    { label: 'Cancer Screening Unit', value: 'Cancer Screening Unit' },
-    { label: 'Glucose Monitoring Unit', value: 'Glucose Monitoring Unit' }
+    { label: 'Cancer Screening Unit', value: 'cancer' },
+    { label: 'Glucose Monitoring Unit', value: 'glucose' }
 ];
 
 export default function DeviceDetail() {
@@ -35,8 +35,8 @@ export default function DeviceDetail() {
         state.arduino.devices.find(d => d.port === portName)
     );
     
-    // Local state for the editable name (alias)
-    const [alias, setAlias] = useState("");
+    // Local state for the role picked in the dropdown
+    const [role, setRole] = useState<DeviceRole | "">("");
     const [isUpdating, setIsUpdating] = useState(false);
 
     // --- Effect to handle initial state and navigation ---
@@ -45,11 +45,8 @@ export default function DeviceDetail() {
             toast.error(`Device ${portName} not found.`);
             navigate('/');
         } else {
-            // Set the initial alias: 
-            // 1. Prefer custom_name (the saved name)
-            // 2. Fall back to product name
-            // 3. Fall back to port name
-            // setAlias(device.custom_name || device.product || device.port || ""); 
+            // Preselect the role the device already has (manual or from its firmware)
+            setRole(device.role ?? "");
         }
     }, [device, portName, navigate]);
 
@@ -57,40 +54,34 @@ export default function DeviceDetail() {
         return <div className="detail-layout">Loading...</div>;
     }
 
-    // Determine the baseline name for comparison (current saved name or default)
-    const comparisonName = device.custom_name || device.product || device.port || "";
-    const isNameUnchanged = alias.trim() === comparisonName.trim();
+    const isRoleUnchanged = role === (device.role ?? "");
     
     // --- Data Handling: Updated to pass hardware IDs ---
-    const handleAliasChange = async () => {
-        setIsUpdating(true);
-        const trimmedAlias = alias.trim();
-        
+    const handleRoleChange = async () => {
         // Safety check to prevent unnecessary API calls
-        if (isNameUnchanged) {
-            setIsUpdating(false);
+        if (!role || isRoleUnchanged) {
             return;
         }
+        setIsUpdating(true);
 
         try {
             // 💡 INVOKE COMMAND: Pass all unique hardware identifiers
-            await invoke("update_device_alias", {
-                portName: device.port, 
-                product: device.product,
+            await invoke("set_device_role", {
                 vid: device.vid,
                 pid: device.pid,
                 serialNumber: device.serial_number, // Passed as Option<String> in Rust
-                newAlias: trimmedAlias 
+                product: device.product,
+                role
             });
 
-            // 💡 REDUX UPDATE: Update the custom_name in the store
-            dispatch(setDeviceCustomName({ port: device.port, customName: trimmedAlias })); 
-            setAlias("")
-            toast.success(`Device name updated to "${trimmedAlias}"`);
+            // 💡 REDUX UPDATE: The role travels with the device, whatever it is named
+            dispatch(setDeviceRole({ port: device.port, role }));
+            const label = deviceOptions.find(o => o.value === role)?.label ?? role;
+            toast.success(`Device assigned to "${label}"`);
         } catch (error) {
             // 💡 ERROR HANDLING: Use the error message returned from Rust
             const errorMessage = typeof error === 'string' ? error : "An unknown error occurred while saving.";
-            console.error("Failed to update device alias:", error);
+            console.error("Failed to update device role:", error);
             toast.error(errorMessage);
         } finally {
             setIsUpdating(false);
@@ -164,20 +155,18 @@ export default function DeviceDetail() {
                     
                     <Dropdown 
                         id="device-role"
-                        value={alias} 
+                        value={role} 
                         options={deviceOptions} 
-                        onChange={(e) => setAlias(e.value)} 
+                        onChange={(e) => setRole(e.value)} 
                         placeholder="Select a medical role"
                         className="p-inputtext-lg"
-                        // If the device already has a custom_name, show it as selected
-                        // value={alias || device.custom_name} 
                     />
 
                     <Button 
                         label={isUpdating ? "Saving..." : "Assign Role"} 
                         icon="pi pi-check-circle"
-                        onClick={handleAliasChange} 
-                        disabled={!alias || isUpdating || isNameUnchanged}
+                        onClick={handleRoleChange} 
+                        disabled={!role || isUpdating || isRoleUnchanged}
                         className="p-button-primary save-custom-name"
                     />
                 </div>
@@ -18,6 +18,7 @@ import { ask } from "@tauri-apps/plugin-dialog";
 // NOTE: PatientRecord should mirror the Rust PatientRecord struct
 interface PatientRecord {
     id: number;
+    patient_id: number;
     admission_no: string;
     national_id: string | null;
     location: string | null;
@@ -19,10 +19,6 @@ import { ask } from "@tauri-apps/plugin-dialog";
 import { FaDroplet } from 'react-icons/fa6';
 
 
-const ROLE_CANCER = 'Cancer Screening Unit';
-const ROLE_GLUCOSE = 'Glucose Monitoring Unit';
-
-
 // Simplified Patient Metadata for display
 interface PatientMetadata {
     firstname: string;
@@ -37,6 +33,7 @@ interface ArduinoDevice {
     status: 'connected' | 'disconnected' | 'reading';
     custom_name?: string;
     product?: string;
+    role?: 'cancer' | 'glucose';
 }
 
 // Data structures for storing readings
@@ -44,6 +41,14 @@ type ReadingSet = { time: string; value: number }[];
 
 type SampleType = "normal" | "cancer" | "glucose";
 
+// Payload of `session-state-changed`, published on every change of a port's run
+interface MeasurementSession {
+    port: string;
+    state: 'idle' | 'resetting' | 'acquiring' | 'completed' | 'failed' | 'cancelled';
+    acquisition_session_id: number | null;
+    message: string | null;
+}
+
 export default function PatientTestDashboard() {
     
     // Hooks and State
@@ -74,6 +79,9 @@ export default function PatientTestDashboard() {
     // Storage for completed test data (to be saved)
     const [normalCellReadings, setNormalCellReadings] = useState<number[]>([]);
     const [cancerCellReadings, setCancerCellReadings] = useState<number[]>([]);
+    // Acquisition sessions the readings come from; the backend stores their values
+    const [normalSessionId, setNormalSessionId] = useState<number | null>(null);
+    const [cancerSessionId, setCancerSessionId] = useState<number | null>(null);
 
     /* ------------------------------------------------------------------ */
     /* RUST COMMANDS (PLACEHOLDERS)                                       */
@@ -100,7 +108,7 @@ export default function PatientTestDashboard() {
         try {
             // Placeholder: Assume this Rust command starts the hardware test
             // await invoke("start_test_cycle", { port, sampleType });
-            await invoke("start_reading_from_port", { portName, baudRate: baud_rate_default });
+            await invoke("start_reading_from_port", { portName, baudRate: baud_rate_default, admissionNo });
             console.log(portName, sampleType)
             toast.dismiss();
             toast.success(`Test for ${sampleType} cells initiated.`);
@@ -160,27 +168,32 @@ export default function PatientTestDashboard() {
     }
 
 
+    // --- Calibrated OFF voltages the backend stored for a session ---
+    const loadOffVoltages = async (sessionId: number) => {
+        const lines: { phase: string | null; voltage: number | null }[] =
+            await invoke("get_acquisition_lines", { sessionId });
+        return lines
+            .filter(l => l.voltage !== null && l.phase !== "ON")
+            .map(l => l.voltage as number);
+    }
+
     const getTestTypeFromDevice = (device: ArduinoDevice): SampleType => {
-        const name = (device.custom_name || device.product || "").toLowerCase();
-        if (name.includes("glucose") || name.includes("diabetes")) return "glucose";
-        return "normal"; 
+        return device.role === "glucose" ? "glucose" : "normal";
     };
 
     const isHardwareMismatch = (activeDevice: ArduinoDevice | undefined, currentMode: SampleType) => {
         if (!activeDevice) return false;
-        const role = activeDevice.custom_name;
+        const role = activeDevice.role;
         
-        if (currentMode === "glucose" && role !== ROLE_GLUCOSE) return true;
-        if ((currentMode === "normal" || currentMode === "cancer") && role !== ROLE_CANCER) return true;
+        if (currentMode === "glucose" && role !== "glucose") return true;
+        if ((currentMode === "normal" || currentMode === "cancer") && role !== "cancer") return true;
         
         return false;
     };
  
     useEffect(() => {
         let unlistenData: UnlistenFn | undefined;
-        let unlistenCycle: UnlistenFn | undefined;
-        let unlistenStop: UnlistenFn | undefined; // Add listener for cycle stop/timeout
-        let unlistenTimeout: UnlistenFn | undefined;
+        let unlistenSession: UnlistenFn | undefined;
 
         const extractAndPlotData = (line: string, time: string) => {
             const regex = /Output Voltage \((ON|OFF)\): ([\d.]+) V/;
@@ -225,69 +238,69 @@ export default function PatientTestDashboard() {
             }
         });
     
-            // --- Cycle complete (Store readings + toast) ---
-            unlistenCycle = await listen<{ port: string }>("arduino-cycle-complete", (e) => {
-                toast.success(`Test Cycle complete on ${e.payload.port}. Data ready to save.`);
-                const device = devices.find(d => d.port === e.payload.port);
-                const role = device?.custom_name;
-                
-                // Remove port from readingPorts state
-                setReadingPorts(prev => {
-                    const next = new Set(prev);
-                    next.delete(e.payload.port);
-                    return next;
-                });
-                
-                if (role === ROLE_CANCER) {
-                    // Extract and store the final 'OFF' voltages from the latest console run
-                    const newOffVoltages = extractOffVoltages(consoleLinesRef.current);
-                    
+            // --- Run finished (Store readings + toast) ---
+            unlistenSession = await listen<MeasurementSession>("session-state-changed", async (e) => {
+                const { port, state, message } = e.payload;
+                if (state !== 'completed' && state !== 'failed' && state !== 'cancelled') return;
+
+                // Remove port from readingPorts state
+                setReadingPorts(prev => {
+                    const next = new Set(prev);
+                    next.delete(port);
+                    return next;
+                });
+
+                if (state === 'failed') {
+                    toast.error(message || `Measurement failed on ${port}.`);
+                    return;
+                }
+                if (state === 'cancelled') return;
+
+                toast.success(`Test Cycle complete on ${port}. Data ready to save.`);
+                const device = devices.find(d => d.port === port);
+                const role = device?.role;
+                
+                if (role === 'cancer') {
+                    // The calibrated 'OFF' voltages of the run, as they will be saved;
+                    // the console lines only hold the raw ones
+                    const rawLines = consoleLinesRef.current;
                     consoleLinesRef.current = [];
+                    const sessionId = e.payload.acquisition_session_id;
+                    let newOffVoltages: number[];
+                    try {
+                        newOffVoltages = sessionId !== null
+                            ? await loadOffVoltages(sessionId)
+                            : extractOffVoltages(rawLines);
+                    } catch (err) {
+                        toast.error(`Could not load the readings of session ${sessionId}: ${err}`);
+                        return;
+                    }
 
                     console.log("THE OFF VOLTAGES: ", newOffVoltages)
 
                     if (sampleType === 'normal') {
                         setNormalCellReadings(newOffVoltages);
+                        setNormalSessionId(sessionId);
                         toast(`Stored ${newOffVoltages.length} normal cell readings.`, { icon: '🟢' });
                     } else if (sampleType === 'cancer') {
                         setCancerCellReadings(newOffVoltages);
+                        setCancerSessionId(sessionId);
                         toast(`Stored ${newOffVoltages.length} cancer cell readings.`, { icon: '🔴' });
                     }
 
                     // Reset chart after cycle complete for next run clarity
                     setChartData([]);
                     chartDataRef.current = [];
-                } else if(role === ROLE_GLUCOSE) {
+                } else if(role === 'glucose') {
                     // to be implemented
                 }
             });
-            
-            // --- Cycle Stop/Timeout Listener (Cleanup) ---
-            unlistenStop = await listen<{ port: string; reason: string }>("arduino-reading-stopped", (e) => {
-                setReadingPorts(prev => {
-                    const next = new Set(prev);
-                    next.delete(e.payload.port);
-                    return next;
-                });
-            });
-
-            unlistenTimeout = await listen<{ port: string; message: string }>("arduino-timeout", (e) => {
-                const port = e.payload.port;
-                setReadingPorts(prev => {
-                    const next = new Set(prev);
-                    next.delete(port);
-                    return next;
-                });
-                toast.error(e.payload.message);
-            });
 
         })();
 
         return () => {
             unlistenData?.();
-            unlistenCycle?.();
-            unlistenStop?.();
-            unlistenTimeout?.();
+            unlistenSession?.();
         };
     }, [sampleType]); 
 
@@ -323,6 +336,10 @@ export default function PatientTestDashboard() {
                 diabetes_test: glucoseReading, // This goes to the INTEGER column
                 reference: JSON.stringify({ voltage_off: normalCellReadings }),
                 cancer_tests: JSON.stringify({ voltage_off: cancerCellReadings }),
+                reference_session_id: normalSessionId,
+                cancer_session_id: cancerSessionId,
+                // Only these runs are linked to the admission
+                session_ids: [normalSessionId, cancerSessionId].filter((id): id is number => id !== null),
             };
 
             await invoke("save_admission", { data: admissionDataToSave });
@@ -333,6 +350,8 @@ export default function PatientTestDashboard() {
             setConsoleLines([]);
             setNormalCellReadings([]);
             setCancerCellReadings([]);
+            setNormalSessionId(null);
+            setCancerSessionId(null);
             setChartData([]);
             const encodedAdmissionNo = encodeURIComponent(patientData.admission_no);
             navigate(`/analytics/${encodedAdmissionNo}`);
@@ -351,7 +370,7 @@ export default function PatientTestDashboard() {
         if (isReading) {
             await invoke("stop_reading_from_port", { portName });
         } else {
-            await invoke("start_reading_from_port", { portName, baudRate: 9600 });
+            await invoke("start_reading_from_port", { portName, baudRate: 9600, admissionNo });
         }
         } catch (err: any) {
         toast.error(`Failed to ${isReading ? "stop" : "start"}: ${err.message || err}`);
@@ -396,9 +415,11 @@ export default function PatientTestDashboard() {
     const handleClearReadings = (type: "normal" | "cancer" | "glucose") => {
         if (type === "normal") {
             setNormalCellReadings([]);
+            setNormalSessionId(null);
             toast.success("Normal cell readings cleared.", { icon: '🗑️' });
         } else if (type === "cancer") {
             setCancerCellReadings([]);
+            setCancerSessionId(null);
             toast.success("Cancer cell readings cleared.", { icon: '🗑️' });
         }
     };
@@ -1,6 +1,8 @@
 // src/store/arduinoSlice.ts
 import { createSlice, PayloadAction } from '@reduxjs/toolkit';
 
+export type DeviceRole = 'cancer' | 'glucose';
+
 export interface ArduinoDevice {
   port: string;
   vid: number;
@@ -9,6 +11,7 @@ export interface ArduinoDevice {
   serial_number?: string,
   custom_name?: string,
   board_name?: string,
+  role?: DeviceRole,
   status: 'connected' | 'disconnected';
 }
 
@@ -48,8 +51,15 @@ const arduinoSlice = createSlice({
             device.custom_name = customName;
         }
     },
+    setDeviceRole: (state, action: PayloadAction<{ port: string; role?: DeviceRole }>) => {
+        const { port, role } = action.payload;
+        const device = state.devices.find(d => d.port === port);
+        if (device) {
+            device.role = role;
+        }
+    },
   },
 });
 
-export const { setScanning, setDevices, updateDevice, setDeviceCustomName } = arduinoSlice.actions;
+export const { setScanning, setDevices, updateDevice, setDeviceCustomName, setDeviceRole } = arduinoSlice.actions;
 export default arduinoSlice.reducer;
\ No newline at end of file